@@ -0,0 +1,10 @@
+-- Cross-cutting labels (e.g. 'critical', 'always-on') attached to devices
+CREATE TABLE device_tags (
+    device_id INTEGER NOT NULL,
+    tag TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (device_id, tag),
+    FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_device_tags_tag ON device_tags(tag);
\ No newline at end of file
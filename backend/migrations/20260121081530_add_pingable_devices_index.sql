@@ -0,0 +1,2 @@
+-- Partial covering index for the background pinger, which only sweeps devices with an IP
+CREATE INDEX idx_devices_pingable ON devices(id, ip_address) WHERE ip_address IS NOT NULL;
\ No newline at end of file
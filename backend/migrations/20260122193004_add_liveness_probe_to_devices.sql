@@ -0,0 +1,2 @@
+-- How the pinger decides whether a device is up: 'icmp' (default), 'arp' or 'tcp:<port>'
+ALTER TABLE devices ADD COLUMN liveness_probe TEXT NOT NULL DEFAULT 'icmp';
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- Why the last wake/shutdown of a device failed, cleared on the next success
+ALTER TABLE devices ADD COLUMN last_action_error TEXT;
+ALTER TABLE devices ADD COLUMN last_action_error_at DATETIME;
\ No newline at end of file
@@ -0,0 +1,9 @@
+-- Devices each user pinned to the top of their dashboard
+CREATE TABLE user_favorites (
+    user_id INTEGER NOT NULL,
+    device_id INTEGER NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, device_id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE CASCADE
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Lets the failed login counter only reflect recent attempts
+ALTER TABLE users ADD COLUMN last_failed_login_at DATETIME;
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- Remember which lifetime a session was issued with so token rotation keeps it.
+-- Sessions from before this change were rotated with the remember-me lifetime.
+ALTER TABLE refresh_tokens ADD COLUMN remember_me BOOLEAN NOT NULL DEFAULT 1;
\ No newline at end of file
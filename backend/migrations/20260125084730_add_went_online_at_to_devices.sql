@@ -0,0 +1,2 @@
+-- When a device last transitioned offline -> online, for "up since" displays
+ALTER TABLE devices ADD COLUMN went_online_at DATETIME;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Accounts refuse logins until this time after too many failures
+ALTER TABLE users ADD COLUMN locked_until DATETIME;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Per-device probe timeout, e.g. for high-latency VPN hosts; NULL uses the global default
+ALTER TABLE devices ADD COLUMN ping_timeout_ms INTEGER;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Devices that were never probed (or have no IP) report NULL, i.e. unknown, instead of offline
+UPDATE devices SET is_online = NULL WHERE ip_address IS NULL OR (is_online = 0 AND last_seen_at IS NULL);
\ No newline at end of file
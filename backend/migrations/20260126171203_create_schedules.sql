@@ -0,0 +1,13 @@
+-- Timed wakes; a 'once' schedule fires at fire_at and is then removed
+CREATE TABLE schedules (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device_id INTEGER NOT NULL,
+    kind TEXT NOT NULL DEFAULT 'once',
+    fire_at DATETIME NOT NULL,
+    created_by INTEGER,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_schedules_fire_at ON schedules(fire_at);
\ No newline at end of file
@@ -0,0 +1,14 @@
+-- Device groups; each device belongs to at most one. Sequential groups wake
+-- members in wake_order, waiting wake_delay_secs before each one
+CREATE TABLE groups (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    sequential_wake BOOLEAN NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+ALTER TABLE devices ADD COLUMN group_id INTEGER REFERENCES groups(id) ON DELETE SET NULL;
+ALTER TABLE devices ADD COLUMN wake_order INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE devices ADD COLUMN wake_delay_secs INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX idx_devices_group_id ON devices(group_id);
\ No newline at end of file
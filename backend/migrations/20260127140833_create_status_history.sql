@@ -0,0 +1,10 @@
+-- Online/offline transitions recorded by the pinger, for availability timelines
+CREATE TABLE status_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device_id INTEGER NOT NULL,
+    state TEXT NOT NULL,               -- 'online' or 'offline'
+    changed_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_status_history_device ON status_history(device_id, changed_at);
\ No newline at end of file
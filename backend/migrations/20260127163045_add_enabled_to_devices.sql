@@ -0,0 +1,5 @@
+-- Disabled devices keep their config but are hidden, not pinged and can't be woken or shut down
+ALTER TABLE devices ADD COLUMN enabled BOOLEAN NOT NULL DEFAULT 1;
+
+DROP INDEX idx_devices_pingable;
+CREATE INDEX idx_devices_pingable ON devices(id, ip_address) WHERE ip_address IS NOT NULL AND enabled = 1;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- UDP ports magic packets are sent to, as a JSON array; NULL uses WOL_DEFAULT_PORT
+ALTER TABLE devices ADD COLUMN wol_ports TEXT;
\ No newline at end of file
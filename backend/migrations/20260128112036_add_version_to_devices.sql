@@ -0,0 +1,2 @@
+-- Bumped on every update_device so concurrent edits can be detected
+ALTER TABLE devices ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Client address (see TRUSTED_PROXIES) of the request that caused each entry
+ALTER TABLE audit_log ADD COLUMN ip_address TEXT;
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- Owning user of a device; NULL means shared. Deleting the user makes the device shared
+ALTER TABLE devices ADD COLUMN owner_id INTEGER REFERENCES users(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_devices_owner_id ON devices(owner_id);
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- When a session was last refreshed, for SESSION_IDLE_MINUTES. NULL for sessions from
+-- before this column, which fall back to created_at
+ALTER TABLE refresh_tokens ADD COLUMN last_used_at DATETIME;
\ No newline at end of file
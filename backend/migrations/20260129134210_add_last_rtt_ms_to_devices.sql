@@ -0,0 +1,2 @@
+-- Best round trip of the most recent successful status check, in milliseconds
+ALTER TABLE devices ADD COLUMN last_rtt_ms INTEGER;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Failed sweeps in a row, for OFFLINE_AFTER_MISSES; reset by any successful probe
+ALTER TABLE devices ADD COLUMN consecutive_misses INTEGER NOT NULL DEFAULT 0;
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- What the device agent last reported about its machine, refreshed on each successful agent call
+ALTER TABLE devices ADD COLUMN agent_os TEXT;
+ALTER TABLE devices ADD COLUMN agent_hostname TEXT;
+ALTER TABLE devices ADD COLUMN agent_version TEXT;
\ No newline at end of file
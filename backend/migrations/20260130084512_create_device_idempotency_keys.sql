@@ -0,0 +1,11 @@
+-- Idempotency-Key headers of POST /api/devices and the device each created, so a
+-- retried create returns the original device instead of a duplicate
+CREATE TABLE device_idempotency_keys (
+    user_id INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    device_id INTEGER NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, key),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE CASCADE
+);
\ No newline at end of file
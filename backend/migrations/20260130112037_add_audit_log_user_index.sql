@@ -0,0 +1,2 @@
+-- Per-user wake statistics aggregate a user's audit entries by action
+CREATE INDEX idx_audit_log_user_action ON audit_log(user_id, action);
\ No newline at end of file
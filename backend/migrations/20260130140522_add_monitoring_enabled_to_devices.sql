@@ -0,0 +1,5 @@
+-- Unmonitored devices are skipped by the pinger; their status is only checked on demand
+ALTER TABLE devices ADD COLUMN monitoring_enabled BOOLEAN NOT NULL DEFAULT 1;
+
+DROP INDEX idx_devices_pingable;
+CREATE INDEX idx_devices_pingable ON devices(id, ip_address) WHERE ip_address IS NOT NULL AND enabled = 1 AND monitoring_enabled = 1;
\ No newline at end of file
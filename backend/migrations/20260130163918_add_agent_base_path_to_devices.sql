@@ -0,0 +1,2 @@
+-- Path prefix for agents served behind a reverse proxy, e.g. /agent; NULL means the root
+ALTER TABLE devices ADD COLUMN agent_base_path TEXT;
\ No newline at end of file
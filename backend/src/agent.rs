@@ -0,0 +1,175 @@
+use crate::auth::AuthUser;
+use crate::config::Config;
+use crate::request_id;
+use reqwest::{Client, Method, RequestBuilder};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+const AGENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Consecutive unreachable-agent errors after which a device's breaker opens
+const BREAKER_FAILURE_THRESHOLD: u32 = 3;
+/// How long an open breaker fast-fails before letting a probe call through
+const BREAKER_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Header naming the user who triggered an agent action
+pub const REQUESTED_BY_HEADER: &str = "X-Requested-By";
+
+static AGENT_CLIENT: OnceLock<Client> = OnceLock::new();
+
+fn client() -> &'static Client {
+    AGENT_CLIENT.get_or_init(|| {
+        Client::builder()
+            .timeout(AGENT_TIMEOUT)
+            .build()
+            .expect("Failed to build agent HTTP client")
+    })
+}
+
+/// `base_path` is the device's `agent_base_path`, for agents a reverse proxy serves
+/// under a prefix; `None` when they listen at the root.
+pub fn agent_url(config: &Config, ip: &str, base_path: Option<&str>, path: &str) -> String {
+    let base_path = base_path.unwrap_or_default().trim_end_matches('/');
+    format!("http://{}:{}{}{}", ip, config.agent_port, base_path, path)
+}
+
+/// Builds a request to the agent running on `ip`, authenticated with
+/// `AGENT_SECRET` as a bearer token when one is configured,
+/// forwarding the current request id for log correlation.
+pub fn request(config: &Config, method: Method, ip: &str, base_path: Option<&str>, path: &str) -> RequestBuilder {
+    let mut builder = client().request(method, agent_url(config, ip, base_path, path));
+    if let Some(id) = request_id::current() {
+        builder = builder.header(request_id::REQUEST_ID_HEADER, id);
+    }
+    match &config.agent_secret {
+        Some(secret) => builder.bearer_auth(secret),
+        None => builder,
+    }
+}
+
+#[derive(Default)]
+struct BreakerEntry {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Per-device circuit breaker for agent calls, so a dead agent doesn't make
+/// every click wait for the full timeout.
+///
+/// After `BREAKER_FAILURE_THRESHOLD` consecutive failures calls are refused
+/// for `BREAKER_COOLDOWN`; the first call after that is let through as a
+/// probe, and its outcome closes or re-opens the breaker.
+#[derive(Default)]
+pub struct CircuitBreaker {
+    entries: Mutex<HashMap<i64, BreakerEntry>>,
+}
+
+impl CircuitBreaker {
+    /// Whether a call to the agent of `device_id` may be attempted now
+    pub fn allow(&self, device_id: i64) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(&device_id) else {
+            return true;
+        };
+        match entry.open_until {
+            Some(until) if Instant::now() < until => false,
+            Some(_) => {
+                // Half-open: this call is the probe, concurrent ones keep fast-failing
+                entry.open_until = Some(Instant::now() + BREAKER_COOLDOWN);
+                true
+            }
+            None => true,
+        }
+    }
+
+    pub fn record_success(&self, device_id: i64) {
+        self.entries.lock().unwrap().remove(&device_id);
+    }
+
+    pub fn record_failure(&self, device_id: i64) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(device_id).or_default();
+        entry.failures += 1;
+        if entry.failures >= BREAKER_FAILURE_THRESHOLD {
+            entry.open_until = Some(Instant::now() + BREAKER_COOLDOWN);
+        }
+    }
+
+    /// Sends `builder`, counting transport errors (unreachable, timed out)
+    /// towards the breaker. Any HTTP answer proves the agent is up.
+    /// Returns `None` without sending while the breaker is open.
+    pub async fn send(&self, device_id: i64, builder: RequestBuilder) -> Option<reqwest::Result<reqwest::Response>> {
+        if !self.allow(device_id) {
+            return None;
+        }
+        let res = builder.send().await;
+        match &res {
+            Ok(_) => self.record_success(device_id),
+            Err(_) => self.record_failure(device_id),
+        }
+        Some(res)
+    }
+}
+
+/// JSON body sent with agent actions so the agent can log who triggered them
+#[derive(Serialize)]
+struct ActionInitiator<'a> {
+    initiated_by: &'a str,
+    user_id: i64,
+}
+
+/// Builds an authenticated action request that tells the agent which user
+/// initiated it, via `X-Requested-By` and a JSON body.
+pub fn action_request(
+    config: &Config,
+    method: Method,
+    ip: &str,
+    base_path: Option<&str>,
+    path: &str,
+    user: &AuthUser,
+) -> RequestBuilder {
+    request(config, method, ip, base_path, path)
+        .header(REQUESTED_BY_HEADER, &user.username)
+        .json(&ActionInitiator {
+            initiated_by: &user.username,
+            user_id: user.id,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{client, CircuitBreaker, BREAKER_FAILURE_THRESHOLD};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn open_breaker_fails_fast_without_connecting() {
+        // An agent that hangs up on every connection
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/ping", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicU32::new(0));
+        tokio::spawn({
+            let connections = connections.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    drop(stream);
+                }
+            }
+        });
+
+        let breaker = CircuitBreaker::default();
+        for _ in 0..BREAKER_FAILURE_THRESHOLD {
+            let res = breaker.send(1, client().get(&url)).await;
+            assert!(matches!(res, Some(Err(_))));
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), BREAKER_FAILURE_THRESHOLD);
+
+        assert!(breaker.send(1, client().get(&url)).await.is_none());
+        assert_eq!(connections.load(Ordering::SeqCst), BREAKER_FAILURE_THRESHOLD);
+        // Other devices' agents are unaffected
+        assert!(breaker.send(2, client().get(&url)).await.is_some());
+    }
+}
@@ -0,0 +1,283 @@
+use crate::audit;
+use crate::db::AppState;
+use crate::error::db_error;
+use crate::auth::AuthUser;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+const DEFAULT_ACTIVITY_LIMIT: i64 = 50;
+const MAX_ACTIVITY_LIMIT: i64 = 200;
+
+// ==========================================
+// 1. DTOs
+// ==========================================
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ActivityQuery {
+    /// Maximum number of events (default 50, at most 200)
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityType {
+    /// A device, ad-hoc, scheduled or scene wake
+    Wake,
+    Login,
+    DeviceOnline,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ActivityEvent {
+    #[serde(rename = "type")]
+    pub kind: ActivityType,
+    /// UTC
+    pub timestamp: NaiveDateTime,
+    pub summary: String,
+    pub user_id: Option<i64>,
+    pub device_id: Option<i64>,
+}
+
+// ==========================================
+// 2. HELPER FUNCTIONS
+// ==========================================
+
+fn wake_summary(action: &str, username: Option<&str>, device_name: Option<&str>, details: Option<&str>) -> String {
+    let who = username.unwrap_or("A deleted user");
+    let device = device_name.unwrap_or("a deleted device");
+    let details = details.map(|d| format!(" ({})", d)).unwrap_or_default();
+    match action {
+        audit::ACTION_DEVICE_WAKE => format!("{} woke {}", who, device),
+        audit::ACTION_SCHEDULED_WAKE => format!("Scheduled wake of {}{}", device, details),
+        audit::ACTION_SCENE_ACTIVATED => format!("{} activated a scene{}", who, details),
+        audit::ACTION_GROUP_WAKE => format!("{} woke a group{}", who, details),
+        _ => format!("{} sent an ad-hoc wake{}", who, details),
+    }
+}
+
+// ==========================================
+// 3. HANDLERS
+// ==========================================
+
+/// GET /api/activity
+/// Recent wakes, logins and devices coming online, newest first. Admins see everything;
+/// other users see their own wakes and logins and the devices they can see.
+#[utoipa::path(
+    get,
+    path = "/api/activity",
+    params(ActivityQuery),
+    tag = "activity",
+    responses(
+        (status = 200, description = "Recent events, newest first", body = Vec<ActivityEvent>),
+        (status = 400, description = "Invalid limit")
+    )
+)]
+pub async fn list_activity(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Query(query): Query<ActivityQuery>,
+) -> impl IntoResponse {
+    let limit = match query.limit {
+        None => DEFAULT_ACTIVITY_LIMIT,
+        Some(l) if l < 1 => return (StatusCode::BAD_REQUEST, "limit must be at least 1").into_response(),
+        Some(l) => l.min(MAX_ACTIVITY_LIMIT),
+    };
+    let is_admin = auth.is_admin();
+
+    // Each source is limited on its own before merging: any event among the newest
+    // `limit` overall is also among the newest `limit` of its own source
+    let wakes = sqlx::query!(
+        r#"SELECT a.action, a.created_at, a.user_id, a.device_id, a.details,
+               u.username as "username?", d.name as "device_name?"
+           FROM audit_log a
+           LEFT JOIN users u ON u.id = a.user_id
+           LEFT JOIN devices d ON d.id = a.device_id
+           WHERE a.action IN (?, ?, ?, ?, ?)
+             AND (? OR a.user_id = ?)
+           ORDER BY a.created_at DESC, a.id DESC
+           LIMIT ?"#,
+        audit::ACTION_DEVICE_WAKE,
+        audit::ACTION_ADHOC_WAKE,
+        audit::ACTION_SCHEDULED_WAKE,
+        audit::ACTION_SCENE_ACTIVATED,
+        audit::ACTION_GROUP_WAKE,
+        is_admin,
+        auth.id,
+        limit
+    )
+    .fetch_all(&state.db)
+    .await;
+    let wakes = match wakes {
+        Ok(w) => w,
+        Err(e) => return db_error(&e, "Failed to fetch activity"),
+    };
+
+    let logins = sqlx::query!(
+        r#"SELECT a.user_id, a.created_at, u.username as "username?"
+           FROM audit_log a
+           LEFT JOIN users u ON u.id = a.user_id
+           WHERE a.action = ?
+             AND (? OR a.user_id = ?)
+           ORDER BY a.created_at DESC, a.id DESC
+           LIMIT ?"#,
+        audit::ACTION_LOGIN,
+        is_admin,
+        auth.id,
+        limit
+    )
+    .fetch_all(&state.db)
+    .await;
+    let logins = match logins {
+        Ok(l) => l,
+        Err(e) => return db_error(&e, "Failed to fetch activity"),
+    };
+
+    let onlines = sqlx::query!(
+        r#"SELECT h.device_id, h.changed_at, d.name
+           FROM status_history h
+           JOIN devices d ON d.id = h.device_id
+           WHERE h.state = 'online'
+             AND (? OR d.owner_id IS NULL OR d.owner_id = ?)
+           ORDER BY h.changed_at DESC, h.id DESC
+           LIMIT ?"#,
+        is_admin,
+        auth.id,
+        limit
+    )
+    .fetch_all(&state.db)
+    .await;
+    let onlines = match onlines {
+        Ok(o) => o,
+        Err(e) => return db_error(&e, "Failed to fetch activity"),
+    };
+
+    let mut events: Vec<ActivityEvent> = wakes
+        .into_iter()
+        .map(|w| ActivityEvent {
+            kind: ActivityType::Wake,
+            timestamp: w.created_at,
+            summary: wake_summary(&w.action, w.username.as_deref(), w.device_name.as_deref(), w.details.as_deref()),
+            user_id: w.user_id,
+            device_id: w.device_id,
+        })
+        .chain(logins.into_iter().map(|l| ActivityEvent {
+            kind: ActivityType::Login,
+            timestamp: l.created_at,
+            summary: format!("{} logged in", l.username.as_deref().unwrap_or("A deleted user")),
+            user_id: l.user_id,
+            device_id: None,
+        }))
+        .chain(onlines.into_iter().map(|o| ActivityEvent {
+            kind: ActivityType::DeviceOnline,
+            timestamp: o.changed_at,
+            summary: format!("{} came online", o.name),
+            user_id: None,
+            device_id: Some(o.device_id),
+        }))
+        .collect();
+    // Stable, so events with the same timestamp keep their source order
+    events.sort_by_key(|e| std::cmp::Reverse(e.timestamp));
+    events.truncate(limit as usize);
+
+    Json(events).into_response()
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        list_activity
+    ),
+    components(
+        schemas(
+            ActivityType,
+            ActivityEvent
+        )
+    ),
+    tags(
+        (name = "activity", description = "Activity feed endpoints")
+    )
+)]
+pub struct ActivityApi;
+
+#[cfg(test)]
+mod tests {
+    use crate::api::users::hash_password;
+    use crate::audit;
+    use crate::test_support::{call, device, state_with, user};
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    async fn activity(state: &crate::db::AppState, token: &str) -> Vec<Value> {
+        let (status, body) = call(state, Method::GET, "/api/activity", Some(token), None).await;
+        assert_eq!(status, StatusCode::OK);
+        serde_json::from_str(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn lists_every_login() {
+        let state = state_with(|config| {
+            config.argon2_memory_kib = 8;
+            config.argon2_iterations = 1;
+            config.argon2_parallelism = 1;
+        })
+        .await;
+        let (id, token) = user(&state, "alice", "user").await;
+        let hash = hash_password(&state.config, "correct horse 1").unwrap();
+        sqlx::query!("UPDATE users SET password_hash = ? WHERE id = ?", hash, id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let credentials = json!({ "username": "alice", "password": "correct horse 1" });
+        for _ in 0..2 {
+            let (status, _) = call(&state, Method::POST, "/api/login", None, Some(credentials.clone())).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let logins: Vec<_> = activity(&state, &token).await.into_iter().filter(|e| e["type"] == "login").collect();
+        assert_eq!(logins.len(), 2);
+        assert!(logins.iter().all(|e| e["user_id"] == id && e["summary"] == "alice logged in"));
+    }
+
+    #[tokio::test]
+    async fn merges_sources_by_time() {
+        let state = state_with(|_| {}).await;
+        let (id, token) = user(&state, "alice", "user").await;
+        let desktop = device(&state, "desktop", Some(id)).await;
+        for (action, minutes_ago) in [(audit::ACTION_LOGIN, 30), (audit::ACTION_DEVICE_WAKE, 20)] {
+            let at = format!("-{minutes_ago} minutes");
+            sqlx::query!(
+                "INSERT INTO audit_log (user_id, action, device_id, created_at) VALUES (?, ?, ?, datetime('now', ?))",
+                id,
+                action,
+                desktop,
+                at
+            )
+            .execute(&state.db)
+            .await
+            .unwrap();
+        }
+        for minutes_ago in [25, 10] {
+            let at = format!("-{minutes_ago} minutes");
+            sqlx::query!(
+                "INSERT INTO status_history (device_id, state, changed_at) VALUES (?, 'online', datetime('now', ?))",
+                desktop,
+                at
+            )
+            .execute(&state.db)
+            .await
+            .unwrap();
+        }
+
+        let types: Vec<_> = activity(&state, &token).await.iter().map(|e| e["type"].as_str().unwrap().to_string()).collect();
+        assert_eq!(types, ["device_online", "wake", "device_online", "login"]);
+    }
+}
@@ -0,0 +1,617 @@
+use crate::api::devices::{
+    exceeds_device_limit, normalize_agent_base_path, normalize_tag, DEVICE_LIMIT_REACHED, parse_wol_ports, validate_ping_timeout,
+    validate_wol_ports, wol_ports_json,
+};
+use crate::api::validation::{check_batch_size, reject_control_chars, reject_control_chars_opt};
+use crate::audit;
+use crate::auth::AdminUser;
+use crate::db::AppState;
+use crate::error::db_error;
+use crate::pinger::LivenessProbe;
+use crate::wol;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Sqlite, Transaction};
+use std::collections::{HashMap, HashSet};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+/// Format version written by this server; restores of newer documents are refused
+pub const BACKUP_VERSION: u32 = 1;
+
+// ==========================================
+// 1. DTOs
+// ==========================================
+
+/// Devices, groups, schedules and tags in one document.
+/// Ids only link entries within the document; restoring assigns new ones.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct BackupDocument {
+    pub version: u32,
+    #[serde(default)]
+    pub groups: Vec<BackupGroup>,
+    #[serde(default)]
+    pub devices: Vec<BackupDevice>,
+    #[serde(default)]
+    pub schedules: Vec<BackupSchedule>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct BackupGroup {
+    pub id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub sequential_wake: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct BackupDevice {
+    pub id: i64,
+    pub name: String,
+    pub mac_address: String,
+    pub ip_address: Option<String>,
+    pub broadcast_addr: Option<String>,
+    pub icon: Option<String>,
+    pub liveness_probe: Option<String>,
+    pub ping_timeout_ms: Option<i64>,
+    /// Refers to `BackupGroup::id`
+    pub group_id: Option<i64>,
+    #[serde(default)]
+    pub wake_order: i64,
+    #[serde(default)]
+    pub wake_delay_secs: i64,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    #[serde(default = "enabled_by_default")]
+    pub monitoring_enabled: bool,
+    pub wol_ports: Option<Vec<u16>>,
+    pub agent_base_path: Option<String>,
+    /// Username of the owning user; `null` for a shared device.
+    /// Restoring fails if no user by that name exists.
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct BackupSchedule {
+    /// Refers to `BackupDevice::id`
+    pub device_id: i64,
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// UTC
+    pub fire_at: chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RestoreQuery {
+    /// Delete all existing devices and groups first (default: add alongside them)
+    pub replace: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RestoreResponse {
+    pub groups: usize,
+    pub devices: usize,
+    pub schedules: usize,
+}
+
+// ==========================================
+// 2. HELPER FUNCTIONS
+// ==========================================
+
+/// Devices from backups that predate `enabled` or `monitoring_enabled` come back enabled and monitored
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// Checks the document on its own, before anything is written
+fn validate(doc: &BackupDocument, lenient_mac: bool) -> Result<(), String> {
+    if doc.version > BACKUP_VERSION {
+        return Err(format!("Unsupported backup version {} (newest supported is {})", doc.version, BACKUP_VERSION));
+    }
+
+    let mut group_ids = HashSet::new();
+    for group in &doc.groups {
+        if !group_ids.insert(group.id) {
+            return Err(format!("Group id {} appears twice", group.id));
+        }
+        if group.name.trim().is_empty() {
+            return Err("Group name must not be empty".to_string());
+        }
+        reject_control_chars("name", &group.name)?;
+    }
+
+    let mut device_ids = HashSet::new();
+    for device in &doc.devices {
+        if !device_ids.insert(device.id) {
+            return Err(format!("Device id {} appears twice", device.id));
+        }
+        reject_control_chars("name", &device.name)?;
+        reject_control_chars_opt("icon", device.icon.as_deref())?;
+        if wol::parse_mac(&device.mac_address, lenient_mac).is_none() {
+            return Err(format!("Device {}: invalid MAC address", device.id));
+        }
+        if wol::broadcast_target(device.broadcast_addr.as_deref()).is_none() {
+            return Err(format!("Device {}: invalid broadcast address", device.id));
+        }
+        if let Some(probe) = &device.liveness_probe {
+            probe.parse::<LivenessProbe>().map_err(|e| format!("Device {}: {}", device.id, e))?;
+        }
+        validate_ping_timeout(device.ping_timeout_ms).map_err(|e| format!("Device {}: {}", device.id, e))?;
+        if let Some(ports) = &device.wol_ports {
+            validate_wol_ports(ports).map_err(|e| format!("Device {}: {}", device.id, e))?;
+        }
+        if let Some(path) = &device.agent_base_path {
+            normalize_agent_base_path(path).map_err(|e| format!("Device {}: {}", device.id, e))?;
+        }
+        if let Some(group_id) = device.group_id
+            && !group_ids.contains(&group_id)
+        {
+            return Err(format!("Device {} refers to unknown group {}", device.id, group_id));
+        }
+    }
+
+    for schedule in &doc.schedules {
+        if !device_ids.contains(&schedule.device_id) {
+            return Err(format!("Schedule refers to unknown device {}", schedule.device_id));
+        }
+        if schedule.kind != "once" {
+            return Err(format!("Unknown schedule type: {}", schedule.kind));
+        }
+    }
+
+    Ok(())
+}
+
+/// Why [`apply`] failed
+enum RestoreError {
+    /// The document doesn't fit this server, e.g. a device owner has no account here
+    Invalid(String),
+    Db(sqlx::Error),
+}
+
+impl From<sqlx::Error> for RestoreError {
+    fn from(e: sqlx::Error) -> Self {
+        RestoreError::Db(e)
+    }
+}
+
+/// Writes the document, mapping its ids to the newly assigned ones
+async fn apply(
+    tx: &mut Transaction<'_, Sqlite>,
+    doc: &BackupDocument,
+    replace: bool,
+    admin_id: i64,
+) -> Result<RestoreResponse, RestoreError> {
+    // Devices would otherwise come back shared, i.e. visible to everyone
+    let mut owner_ids = HashMap::new();
+    for owner in doc.devices.iter().filter_map(|d| d.owner.as_deref()) {
+        if owner_ids.contains_key(owner) {
+            continue;
+        }
+        match sqlx::query_scalar!("SELECT id FROM users WHERE username = ?", owner)
+            .fetch_optional(&mut **tx)
+            .await?
+        {
+            Some(id) => owner_ids.insert(owner, id),
+            None => return Err(RestoreError::Invalid(format!("Device owner {} does not exist", owner))),
+        };
+    }
+
+    if replace {
+        // Tags, favorites and schedules go with their devices
+        sqlx::query!("DELETE FROM devices").execute(&mut **tx).await?;
+        sqlx::query!("DELETE FROM groups").execute(&mut **tx).await?;
+    }
+
+    let mut group_ids = HashMap::new();
+    for group in &doc.groups {
+        let name = group.name.trim();
+        let id = sqlx::query_scalar!(
+            r#"INSERT INTO groups (name, sequential_wake) VALUES (?, ?) RETURNING id as "id!""#,
+            name,
+            group.sequential_wake
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+        group_ids.insert(group.id, id);
+    }
+
+    let mut device_ids = HashMap::new();
+    for device in &doc.devices {
+        let group_id = device.group_id.and_then(|g| group_ids.get(&g).copied());
+        let broadcast_addr = wol::broadcast_target(device.broadcast_addr.as_deref());
+        let liveness_probe = device
+            .liveness_probe
+            .as_deref()
+            .and_then(|p| p.parse::<LivenessProbe>().ok())
+            .unwrap_or_default()
+            .to_string();
+        let wol_ports = device.wol_ports.as_deref().and_then(wol_ports_json);
+        let agent_base_path = device
+            .agent_base_path
+            .as_deref()
+            .and_then(|p| normalize_agent_base_path(p).ok())
+            .flatten();
+        let owner_id = device.owner.as_deref().map(|owner| owner_ids[owner]);
+        let id = sqlx::query_scalar!(
+            r#"INSERT INTO devices (name, mac_address, ip_address, broadcast_addr, icon, liveness_probe, ping_timeout_ms,
+                   is_online, group_id, wake_order, wake_delay_secs, enabled, wol_ports, created_by,
+                   monitoring_enabled, agent_base_path, owner_id)
+               VALUES (?, ?, ?, ?, ?, ?, ?, NULL, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+               RETURNING id as "id!""#,
+            device.name,
+            device.mac_address,
+            device.ip_address,
+            broadcast_addr,
+            device.icon,
+            liveness_probe,
+            device.ping_timeout_ms,
+            group_id,
+            device.wake_order,
+            device.wake_delay_secs,
+            device.enabled,
+            wol_ports,
+            admin_id,
+            device.monitoring_enabled,
+            agent_base_path,
+            owner_id
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+        device_ids.insert(device.id, id);
+
+        for tag in &device.tags {
+            let tag = normalize_tag(tag);
+            if tag.is_empty() {
+                continue;
+            }
+            sqlx::query!("INSERT OR IGNORE INTO device_tags (device_id, tag) VALUES (?, ?)", id, tag)
+                .execute(&mut **tx)
+                .await?;
+        }
+    }
+
+    for schedule in &doc.schedules {
+        let device_id = device_ids[&schedule.device_id];
+        sqlx::query!(
+            "INSERT INTO schedules (device_id, kind, fire_at, created_by) VALUES (?, ?, ?, ?)",
+            device_id,
+            schedule.kind,
+            schedule.fire_at,
+            admin_id
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(RestoreResponse {
+        groups: doc.groups.len(),
+        devices: doc.devices.len(),
+        schedules: doc.schedules.len(),
+    })
+}
+
+// ==========================================
+// 3. HANDLERS
+// ==========================================
+
+/// GET /api/backup
+/// Devices, groups, schedules and tags as one document. Users and secrets are not included.
+#[utoipa::path(
+    get,
+    path = "/api/backup",
+    tag = "backup",
+    responses(
+        (status = 200, description = "Backup document", body = BackupDocument),
+        (status = 403, description = "Admin only")
+    )
+)]
+pub async fn get_backup(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let groups = sqlx::query_as!(
+        BackupGroup,
+        r#"SELECT id as "id!", name, sequential_wake FROM groups ORDER BY id"#
+    )
+    .fetch_all(&state.db)
+    .await;
+    let groups = match groups {
+        Ok(g) => g,
+        Err(e) => return db_error(&e, "Failed to create backup"),
+    };
+
+    let devices = sqlx::query!(
+        r#"SELECT d.id as "id!", d.name, d.mac_address, d.ip_address, d.broadcast_addr, d.icon, d.liveness_probe,
+               d.ping_timeout_ms, d.group_id, d.wake_order, d.wake_delay_secs, d.enabled, d.monitoring_enabled,
+               d.wol_ports, d.agent_base_path, u.username as "owner?"
+           FROM devices d
+           LEFT JOIN users u ON u.id = d.owner_id
+           ORDER BY d.id"#
+    )
+    .fetch_all(&state.db)
+    .await;
+    let devices = match devices {
+        Ok(d) => d,
+        Err(e) => return db_error(&e, "Failed to create backup"),
+    };
+
+    let mut tags = match sqlx::query!("SELECT device_id, tag FROM device_tags ORDER BY tag")
+        .fetch_all(&state.db)
+        .await
+    {
+        Ok(rows) => rows.into_iter().fold(HashMap::<i64, Vec<String>>::new(), |mut tags, row| {
+            tags.entry(row.device_id).or_default().push(row.tag);
+            tags
+        }),
+        Err(e) => return db_error(&e, "Failed to create backup"),
+    };
+
+    let schedules = sqlx::query_as!(
+        BackupSchedule,
+        "SELECT device_id, kind, fire_at FROM schedules ORDER BY fire_at, id"
+    )
+    .fetch_all(&state.db)
+    .await;
+    let schedules = match schedules {
+        Ok(s) => s,
+        Err(e) => return db_error(&e, "Failed to create backup"),
+    };
+
+    let devices = devices
+        .into_iter()
+        .map(|d| BackupDevice {
+            tags: tags.remove(&d.id).unwrap_or_default(),
+            id: d.id,
+            name: d.name,
+            mac_address: d.mac_address,
+            ip_address: d.ip_address,
+            broadcast_addr: d.broadcast_addr,
+            icon: d.icon,
+            liveness_probe: Some(d.liveness_probe),
+            ping_timeout_ms: d.ping_timeout_ms,
+            group_id: d.group_id,
+            wake_order: d.wake_order,
+            wake_delay_secs: d.wake_delay_secs,
+            enabled: d.enabled,
+            monitoring_enabled: d.monitoring_enabled,
+            wol_ports: parse_wol_ports(d.wol_ports.as_deref()),
+            agent_base_path: d.agent_base_path,
+            owner: d.owner,
+        })
+        .collect();
+
+    Json(BackupDocument {
+        version: BACKUP_VERSION,
+        groups,
+        devices,
+        schedules,
+    })
+    .into_response()
+}
+
+/// POST /api/restore
+/// Recreates everything in a backup document, all-or-nothing
+#[utoipa::path(
+    post,
+    path = "/api/restore",
+    params(RestoreQuery),
+    request_body = BackupDocument,
+    tag = "backup",
+    responses(
+        (status = 200, description = "Backup restored", body = RestoreResponse),
+        (status = 403, description = "Admin only"),
+        (status = 409, description = "A group name already exists, or the device limit would be exceeded"),
+        (status = 422, description = "Invalid or unsupported backup document, too many items, or an unknown device owner")
+    )
+)]
+pub async fn restore_backup(
+    admin: AdminUser,
+    State(state): State<AppState>,
+    Query(query): Query<RestoreQuery>,
+    Json(doc): Json<BackupDocument>,
+) -> impl IntoResponse {
+    if let Err(e) = check_batch_size(&state.config, "groups", doc.groups.len())
+        .and_then(|_| check_batch_size(&state.config, "devices", doc.devices.len()))
+        .and_then(|_| check_batch_size(&state.config, "schedules", doc.schedules.len()))
+        .and_then(|_| validate(&doc, state.config.lenient_mac_parsing))
+    {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+    let replace = query.replace.unwrap_or(false);
+
+    let mut tx = match state.db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(&e, "Failed to restore backup"),
+    };
+
+    // Dropping the transaction on any error rolls everything back
+    let summary = match apply(&mut tx, &doc, replace, admin.0.id).await {
+        Ok(s) => s,
+        Err(RestoreError::Invalid(e)) => return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response(),
+        Err(RestoreError::Db(e)) if e.to_string().contains("UNIQUE") => {
+            return (StatusCode::CONFLICT, "A group with that name already exists").into_response();
+        }
+        Err(RestoreError::Db(e)) => return db_error(&e, "Failed to restore backup"),
+    };
+    // After a replace only the restored devices count
+    match exceeds_device_limit(&mut tx, state.config.max_devices).await {
+        Ok(false) => {}
+        Ok(true) => return (StatusCode::CONFLICT, DEVICE_LIMIT_REACHED).into_response(),
+        Err(e) => return db_error(&e, "Failed to restore backup"),
+    }
+
+    if let Err(e) = tx.commit().await {
+        return db_error(&e, "Failed to restore backup");
+    }
+
+    let details = format!(
+        "version={} groups={} devices={} schedules={} replace={}",
+        doc.version, summary.groups, summary.devices, summary.schedules, replace
+    );
+    audit::record(&state.db, Some(admin.0.id), audit::ACTION_RESTORE, None, None, Some(&details)).await;
+
+    Json(summary).into_response()
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_backup, restore_backup),
+    components(
+        schemas(
+            BackupDocument,
+            BackupGroup,
+            BackupDevice,
+            BackupSchedule,
+            RestoreResponse
+        )
+    ),
+    tags(
+        (name = "backup", description = "Backup and restore endpoints")
+    )
+)]
+pub struct BackupApi;
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{call, device, state, state_with, user};
+    use axum::http::{Method, StatusCode};
+
+    #[tokio::test]
+    async fn restore_respects_device_limit() {
+        let state = state_with(|config| config.max_devices = Some(3)).await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        device(&state, "desktop", None).await;
+        device(&state, "server", None).await;
+        let (status, backup) = call(&state, Method::GET, "/api/backup", Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let backup: serde_json::Value = serde_json::from_str(&backup).unwrap();
+
+        // Adding two more would make four
+        let (status, _) = call(&state, Method::POST, "/api/restore", Some(&admin), Some(backup.clone())).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        // Replacing leaves two
+        let (status, body) = call(&state, Method::POST, "/api/restore?replace=true", Some(&admin), Some(backup)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let count = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!: i64" FROM devices"#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[tokio::test]
+    async fn restore_keeps_device_owners() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let (alice_id, _) = user(&state, "alice", "user").await;
+        device(&state, "alice-laptop", Some(alice_id)).await;
+        let (_, backup) = call(&state, Method::GET, "/api/backup", Some(&admin), None).await;
+        let mut backup: serde_json::Value = serde_json::from_str(&backup).unwrap();
+        assert_eq!(backup["devices"][0]["owner"], "alice");
+
+        let (status, body) = call(&state, Method::POST, "/api/restore?replace=true", Some(&admin), Some(backup.clone())).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let owner_id = sqlx::query_scalar!("SELECT owner_id FROM devices")
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(owner_id, Some(alice_id));
+
+        // Rather than coming back shared
+        backup["devices"][0]["owner"] = "bob".into();
+        let (status, _) = call(&state, Method::POST, "/api/restore?replace=true", Some(&admin), Some(backup)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let owner_id = sqlx::query_scalar!("SELECT owner_id FROM devices")
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(owner_id, Some(alice_id));
+    }
+
+    #[tokio::test]
+    async fn backup_round_trips_through_an_empty_database() {
+        let state = state().await;
+        let (admin_id, admin) = user(&state, "admin", "admin").await;
+        let (alice_id, _) = user(&state, "alice", "user").await;
+        let group_id = sqlx::query_scalar!(r#"INSERT INTO groups (name, sequential_wake) VALUES ('rack', 1) RETURNING id as "id!""#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        let nas = device(&state, "nas", Some(alice_id)).await;
+        let server = device(&state, "server", None).await;
+        sqlx::query!("UPDATE devices SET group_id = ?, wake_order = 2, wake_delay_secs = 15 WHERE id = ?", group_id, nas)
+            .execute(&state.db)
+            .await
+            .unwrap();
+        sqlx::query!("UPDATE devices SET group_id = ?, wake_order = 1 WHERE id = ?", group_id, server)
+            .execute(&state.db)
+            .await
+            .unwrap();
+        sqlx::query!("INSERT INTO device_tags (device_id, tag) VALUES (?, 'storage'), (?, 'backup')", nas, nas)
+            .execute(&state.db)
+            .await
+            .unwrap();
+        sqlx::query!(
+            "INSERT INTO schedules (device_id, kind, fire_at, created_by) VALUES (?, 'once', '2099-01-01 07:00:00', ?)",
+            nas,
+            admin_id
+        )
+        .execute(&state.db)
+        .await
+        .unwrap();
+
+        let (status, backup) = call(&state, Method::GET, "/api/backup", Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        sqlx::query!("DELETE FROM devices").execute(&state.db).await.unwrap();
+        sqlx::query!("DELETE FROM groups").execute(&state.db).await.unwrap();
+
+        let backup: serde_json::Value = serde_json::from_str(&backup).unwrap();
+        let (status, body) = call(&state, Method::POST, "/api/restore", Some(&admin), Some(backup)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let summary: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(summary, serde_json::json!({ "groups": 1, "devices": 2, "schedules": 1 }));
+
+        let group = sqlx::query!(r#"SELECT id as "id!", sequential_wake FROM groups WHERE name = 'rack'"#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert!(group.sequential_wake);
+        let devices = sqlx::query!(
+            r#"SELECT id as "id!", name, group_id, wake_order, wake_delay_secs, owner_id FROM devices ORDER BY wake_order"#
+        )
+        .fetch_all(&state.db)
+        .await
+        .unwrap();
+        let restored: Vec<_> = devices
+            .iter()
+            .map(|d| (d.name.as_str(), d.group_id, d.wake_order, d.wake_delay_secs, d.owner_id))
+            .collect();
+        assert_eq!(
+            restored,
+            [("server", Some(group.id), 1, 0, None), ("nas", Some(group.id), 2, 15, Some(alice_id))]
+        );
+
+        let nas = devices[1].id;
+        let tags = sqlx::query_scalar!("SELECT tag FROM device_tags WHERE device_id = ? ORDER BY tag", nas)
+            .fetch_all(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(tags, ["backup", "storage"]);
+        let schedule = sqlx::query!("SELECT device_id, kind, fire_at FROM schedules")
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!((schedule.device_id, schedule.kind.as_str()), (nas, "once"));
+        assert_eq!(schedule.fire_at.to_string(), "2099-01-01 07:00:00");
+    }
+}
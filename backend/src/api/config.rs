@@ -0,0 +1,139 @@
+use crate::audit;
+use crate::auth::{AdminUser, AuthUser};
+use crate::config::Config;
+use crate::db::AppState;
+use axum::{extract::State, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+use utoipa::{OpenApi, ToSchema};
+
+// ==========================================
+// 1. DTOs
+// ==========================================
+
+/// Optional features the frontend shows or hides
+#[derive(Serialize, ToSchema)]
+pub struct FeaturesResponse {
+    /// Shutdown and agent checks through the device agent (`ENABLE_AGENT_CONTROL`)
+    pub agent_control: bool,
+    /// Maintenance mode: status checks and scheduled wakes are paused, wakes and shutdowns refused
+    pub maintenance: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MaintenanceMode {
+    pub enabled: bool,
+}
+
+// ==========================================
+// 3. HANDLERS
+// ==========================================
+
+/// GET /api/config
+/// Effective runtime configuration, without secrets
+#[utoipa::path(
+    get,
+    path = "/api/config",
+    tag = "config",
+    responses(
+        (status = 200, description = "Effective configuration", body = Config),
+        (status = 403, description = "Admin only")
+    )
+)]
+pub async fn get_config(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    Json(state.config.as_ref().clone())
+}
+
+/// GET /api/features
+/// Which optional features are enabled, for any signed-in user
+#[utoipa::path(
+    get,
+    path = "/api/features",
+    tag = "config",
+    responses(
+        (status = 200, description = "Enabled features", body = FeaturesResponse)
+    )
+)]
+pub async fn get_features(
+    _auth: AuthUser,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    Json(FeaturesResponse {
+        agent_control: state.config.agent_control_enabled,
+        maintenance: state.in_maintenance(),
+    })
+}
+
+/// PUT /api/maintenance
+/// Turns maintenance mode on or off, e.g. to keep the server quiet during network work.
+/// While on, status sweeps and scheduled wakes are paused and wakes and shutdowns answer
+/// `503`. It is kept in memory only, so a restart turns it off.
+#[utoipa::path(
+    put,
+    path = "/api/maintenance",
+    request_body = MaintenanceMode,
+    tag = "config",
+    responses(
+        (status = 200, description = "Maintenance mode updated", body = MaintenanceMode),
+        (status = 403, description = "Admin only")
+    )
+)]
+pub async fn set_maintenance(
+    admin: AdminUser,
+    State(state): State<AppState>,
+    Json(payload): Json<MaintenanceMode>,
+) -> impl IntoResponse {
+    let was_enabled = state.maintenance.swap(payload.enabled, Ordering::Relaxed);
+    if was_enabled != payload.enabled {
+        let details = if payload.enabled { "enabled" } else { "disabled" };
+        println!("Maintenance mode {} by user {}", details, admin.0.id);
+        audit::record(&state.db, Some(admin.0.id), audit::ACTION_MAINTENANCE_CHANGED, None, None, Some(details)).await;
+    }
+    Json(payload)
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_config, get_features, set_maintenance),
+    components(schemas(Config, FeaturesResponse, MaintenanceMode)),
+    tags(
+        (name = "config", description = "Server configuration")
+    )
+)]
+pub struct ConfigApi;
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{call, state_with, user};
+    use axum::http::{Method, StatusCode};
+
+    #[tokio::test]
+    async fn config_is_admin_only_and_omits_secrets() {
+        let state = state_with(|config| {
+            config.jwt_secret = "jwt-secret-value".to_string();
+            config.jwt_previous_secrets = vec!["old-jwt-secret-value".to_string()];
+            config.agent_secret = Some("agent-secret-value".to_string());
+            config.metrics_token = Some("metrics-token-value".to_string());
+            config.database_url = "sqlite://secret-path.db".to_string();
+        })
+        .await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let (_, alice) = user(&state, "alice", "user").await;
+
+        let (status, _) = call(&state, Method::GET, "/api/config", Some(&alice), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = call(&state, Method::GET, "/api/config", Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let config: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(config["session_days"], state.config.session_days);
+        assert_eq!(config["sweep_interval_secs"], state.config.sweep_interval_secs);
+        assert_eq!(config["argon2_memory_kib"], state.config.argon2_memory_kib);
+        for secret in ["jwt-secret-value", "old-jwt-secret-value", "agent-secret-value", "metrics-token-value", "secret-path"] {
+            assert!(!body.contains(secret), "{} leaked into {}", secret, body);
+        }
+    }
+}
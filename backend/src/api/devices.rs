@@ -1,14 +1,30 @@
 use crate::db::AppState;
+use crate::error::db_error;
+use crate::events::DeviceStatusEvent;
+use crate::agent;
+use crate::audit;
+use crate::api::pagination::Page;
+use crate::api::validation::{check_batch_size, reject_control_chars, reject_control_chars_opt};
 use crate::auth::{AuthUser, AdminUser};
+use crate::pinger::{self, LivenessProbe};
+use crate::wol;
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
+    http::{header, HeaderMap, Method, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     Json,
 };
+use axum_extra::extract::Query;
 use serde::{Deserialize, Serialize};
-use utoipa::{OpenApi, ToSchema};
-use wake_on_lan::MagicPacket;
+use sqlx::SqliteConnection;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio_stream::{wrappers::{errors::BroadcastStreamRecvError, BroadcastStream}, StreamExt};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
 // ==========================================
 // 1. DTOs
@@ -19,239 +35,2579 @@ pub struct CreateDeviceRequest {
     pub name: String,
     pub mac_address: String,
     pub ip_address: Option<String>,
+    /// Blank or unset uses `DEFAULT_BROADCAST_ADDR` (the global broadcast unless configured)
     pub broadcast_addr: Option<String>,
     pub icon: Option<String>,
+    /// `icmp` (default), `arp` or `tcp:<port>`
+    pub liveness_probe: Option<String>,
+    /// Probe timeout override in milliseconds (50-10000)
+    pub ping_timeout_ms: Option<i64>,
+    /// UDP ports to send the magic packet to, e.g. `[7, 9]`; `WOL_DEFAULT_PORT` when unset
+    pub wol_ports: Option<Vec<u16>>,
+    /// Whether the pinger checks the device's status; `DEFAULT_MONITORING_ENABLED` when unset
+    pub monitoring_enabled: Option<bool>,
+    /// Path prefix the agent is served under, e.g. `/agent`; unset when it listens at the root
+    pub agent_base_path: Option<String>,
 }
 
 #[derive(Deserialize, ToSchema)]
 pub struct UpdateDeviceRequest {
     pub name: Option<String>,
     pub mac_address: Option<String>,
+    /// `null` removes the address
+    #[serde(default, deserialize_with = "explicit_null")]
+    #[schema(value_type = Option<String>)]
+    pub ip_address: Option<Option<String>>,
+    /// Blank switches back to the global broadcast
+    pub broadcast_addr: Option<String>,
+    pub icon: Option<String>,
+    pub liveness_probe: Option<String>,
+    /// `null` switches back to `DEFAULT_PING_TIMEOUT_MS`
+    #[serde(default, deserialize_with = "explicit_null")]
+    #[schema(value_type = Option<i64>)]
+    pub ping_timeout_ms: Option<Option<i64>>,
+    /// An empty list switches back to `WOL_DEFAULT_PORT`
+    pub wol_ports: Option<Vec<u16>>,
+    /// Turning monitoring off forgets the online status, since it's no longer kept up to date
+    pub monitoring_enabled: Option<bool>,
+    /// Blank switches back to the agent listening at the root
+    pub agent_base_path: Option<String>,
+    /// The `version` the edit is based on; the update is refused if the device changed since
+    pub version: Option<i64>,
+}
+
+/// Overrides for a cloned device; anything omitted is copied,
+/// except the MAC (left blank) and IP address (left unset)
+#[derive(Deserialize, ToSchema, Default)]
+pub struct CloneDeviceRequest {
+    pub name: Option<String>,
+    pub mac_address: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+/// Reachability as last observed by the pinger
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceStatus {
+    Online,
+    Offline,
+    /// No IP address, an unparseable one, or not probed yet
+    Unknown,
+}
+
+impl DeviceStatus {
+    fn new(is_online: Option<bool>, ip_address: Option<&str>) -> Self {
+        match (is_online, ip_address) {
+            (_, None) | (None, _) => DeviceStatus::Unknown,
+            (Some(true), Some(_)) => DeviceStatus::Online,
+            (Some(false), Some(_)) => DeviceStatus::Offline,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeviceResponse {
+    pub id: i64,
+    pub name: String,
+    pub mac_address: String,
     pub ip_address: Option<String>,
     pub broadcast_addr: Option<String>,
     pub icon: Option<String>,
+    /// `false` while the status is unknown; see `status`
+    pub is_online: bool,
+    pub status: DeviceStatus,
+    pub last_seen_at: Option<chrono::NaiveDateTime>,
+    /// Fastest answer of the most recent successful status check
+    pub last_rtt_ms: Option<i64>,
+    /// When the device last came online; unset while offline
+    pub went_online_at: Option<chrono::NaiveDateTime>,
+    /// Seconds since `went_online_at`, computed per response; null unless online
+    pub online_for_secs: Option<i64>,
+    pub liveness_probe: String,
+    /// Probe timeout override; the global default applies when unset
+    pub ping_timeout_ms: Option<i64>,
+    /// Magic packet ports; `WOL_DEFAULT_PORT` applies when unset
+    pub wol_ports: Option<Vec<u16>>,
+    /// Why the last wake/shutdown failed; cleared by the next success
+    pub last_action_error: Option<String>,
+    pub last_action_error_at: Option<chrono::NaiveDateTime>,
+    pub tags: Vec<String>,
+    /// Whether the calling user pinned this device
+    pub is_favorite: bool,
+    /// Disabled devices are hidden by default, not pinged and can't be woken or shut down
+    pub enabled: bool,
+    /// Whether the pinger checks the device's status; otherwise it's only checked on demand
+    /// and after wakes
+    pub monitoring_enabled: bool,
+    /// Increases with every update; send it back with `PUT /api/devices/{id}` to detect concurrent edits
+    pub version: i64,
+    /// Owning user; `null` for shared devices
+    pub owner_id: Option<i64>,
+    /// Group the device belongs to, if any
+    pub group_id: Option<i64>,
+    /// Operating system, hostname and agent version as last reported by the device agent
+    pub agent_os: Option<String>,
+    pub agent_hostname: Option<String>,
+    pub agent_version: Option<String>,
+    /// Path prefix the agent is served under; agent URLs are `http://{ip}:{AGENT_PORT}{agent_base_path}/...`
+    pub agent_base_path: Option<String>,
+    /// Whether the calling user may wake this device
+    pub can_wake: bool,
+    /// Whether the calling user may edit or delete this device
+    pub can_manage: bool,
+}
+
+/// A `devices` row as selected by the device queries
+struct DeviceRow {
+    id: i64,
+    name: String,
+    mac_address: String,
+    ip_address: Option<String>,
+    broadcast_addr: Option<String>,
+    icon: Option<String>,
+    is_online: Option<bool>,
+    last_seen_at: Option<chrono::NaiveDateTime>,
+    went_online_at: Option<chrono::NaiveDateTime>,
+    liveness_probe: String,
+    ping_timeout_ms: Option<i64>,
+    last_action_error: Option<String>,
+    last_action_error_at: Option<chrono::NaiveDateTime>,
+    enabled: bool,
+    monitoring_enabled: bool,
+    wol_ports: Option<String>,
+    version: i64,
+    owner_id: Option<i64>,
+    group_id: Option<i64>,
+    last_rtt_ms: Option<i64>,
+    agent_os: Option<String>,
+    agent_hostname: Option<String>,
+    agent_version: Option<String>,
+    agent_base_path: Option<String>,
+}
+
+impl DeviceRow {
+    fn into_response(self, tags: Vec<String>, is_favorite: bool, viewer: &AuthUser) -> DeviceResponse {
+        let status = DeviceStatus::new(self.is_online, self.ip_address.as_deref());
+        let online_for_secs = match (status, self.went_online_at) {
+            (DeviceStatus::Online, Some(since)) => {
+                Some((chrono::Utc::now().naive_utc() - since).num_seconds().max(0))
+            }
+            _ => None,
+        };
+        DeviceResponse {
+            id: self.id,
+            name: self.name,
+            mac_address: self.mac_address,
+            ip_address: self.ip_address,
+            broadcast_addr: self.broadcast_addr,
+            icon: self.icon,
+            is_online: self.is_online.unwrap_or(false),
+            status,
+            last_seen_at: self.last_seen_at,
+            last_rtt_ms: self.last_rtt_ms,
+            went_online_at: self.went_online_at,
+            online_for_secs,
+            liveness_probe: self.liveness_probe,
+            ping_timeout_ms: self.ping_timeout_ms,
+            wol_ports: parse_wol_ports(self.wol_ports.as_deref()),
+            last_action_error: self.last_action_error,
+            last_action_error_at: self.last_action_error_at,
+            tags,
+            is_favorite,
+            enabled: self.enabled,
+            monitoring_enabled: self.monitoring_enabled,
+            version: self.version,
+            can_wake: self.enabled && can_access_device(viewer, self.owner_id),
+            can_manage: viewer.is_admin(),
+            owner_id: self.owner_id,
+            group_id: self.group_id,
+            agent_os: self.agent_os,
+            agent_hostname: self.agent_hostname,
+            agent_version: self.agent_version,
+            agent_base_path: self.agent_base_path,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DeviceStatusRequest {
+    pub ids: Vec<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeviceStatusResponse {
+    pub id: i64,
+    pub is_online: bool,
+    pub status: DeviceStatus,
+    pub last_seen_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Result of an on-demand probe
+#[derive(Serialize, ToSchema)]
+pub struct PingResponse {
+    pub status: DeviceStatus,
+    pub last_seen_at: Option<chrono::NaiveDateTime>,
+    /// How long the device took to answer; null when it didn't
+    pub rtt_ms: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AgentStatusResponse {
+    pub reachable: bool,
+    pub authenticated: bool,
+    pub version: Option<String>,
+    pub os: Option<String>,
+    pub hostname: Option<String>,
+}
+
+/// Machine details an agent may include in its responses (see Agents.md)
+#[derive(Deserialize, Default)]
+struct AgentInfo {
+    #[serde(alias = "agent_version")]
+    version: Option<String>,
+    os: Option<String>,
+    hostname: Option<String>,
+}
+
+/// Wake a machine that is not stored as a device
+#[derive(Deserialize, ToSchema)]
+pub struct AdHocWakeRequest {
+    pub mac: String,
+    /// Defaults to 255.255.255.255
+    pub broadcast: Option<String>,
+    /// Defaults to `WOL_DEFAULT_PORT` (9)
+    pub port: Option<u16>,
+}
+
+/// Body of `POST /api/devices/{id}/schedules`
+#[derive(Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CreateScheduleRequest {
+    /// Wake once at `fire_at`, after which the schedule is removed
+    Once { fire_at: chrono::DateTime<chrono::Utc> },
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ScheduleResponse {
+    pub id: i64,
+    pub device_id: i64,
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// UTC
+    pub fire_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryQuery {
+    /// Only transitions at or after this time
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only transitions at or before this time
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateDeviceEnabledRequest {
+    pub enabled: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateDeviceOwnerRequest {
+    /// New owner; `null` makes the device shared
+    pub owner_id: Option<i64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateDeviceGroupRequest {
+    /// New group; `null` removes the device from its group
+    pub group_id: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StatusTransition {
+    /// `online` or `offline`
+    pub state: String,
+    /// UTC
+    pub changed_at: chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WakeQuery {
+    /// Wait until the device answers its liveness probe before responding
+    pub verify: Option<bool>,
+}
+
+/// Returned instead of the plain-text confirmation when the packet went out
+/// but probably won't reach the device
+#[derive(Serialize, ToSchema)]
+pub struct WakeWarningResponse {
+    /// Always `sent`
+    pub status: String,
+    pub warning: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WakeTimeoutResponse {
+    pub error: String,
+    /// How long we waited for the device to come online
+    pub timeout_secs: u64,
+}
+
+/// Step-by-step outcome of a test wake
+#[derive(Serialize, ToSchema)]
+pub struct TestWakeResponse {
+    pub packet_sent: bool,
+    /// Liveness probes run while waiting for the device
+    pub ping_attempts: u32,
+    pub came_online: bool,
+    /// Duration of the probe the device answered
+    pub rtt_ms: Option<u64>,
+    /// Why the packet could not be sent or the device did not come online
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema, Default)]
+pub struct WakeAndReadyRequest {
+    /// Port that must accept TCP connections once the device is up (default 22)
+    pub service_port: Option<u16>,
+    /// Limit for all stages together (default 120, at most 600)
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadyStage {
+    /// Sending the magic packet
+    Wake,
+    /// Waiting for the device's liveness probe
+    Online,
+    /// Waiting for the service port to accept connections
+    ServiceReady,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReadyStageResult {
+    pub stage: ReadyStage,
+    pub ok: bool,
+    /// Probes run during the stage
+    pub attempts: u32,
+    pub elapsed_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WakeAndReadyResponse {
+    /// Whether every stage succeeded
+    pub ready: bool,
+    pub service_port: u16,
+    /// Stages that ran, in order; a failed stage is the last one
+    pub stages: Vec<ReadyStageResult>,
+}
+
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PreflightSeverity {
+    /// The wake would be refused or fail
+    Error,
+    /// The packet would be sent, but may not arrive or can't be verified
+    Warning,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PreflightIssue {
+    pub severity: PreflightSeverity,
+    /// Device field the issue is about
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WakePreflightResponse {
+    /// Whether a wake would send a packet, i.e. there are no errors
+    pub ok: bool,
+    /// Address the packet would be sent to
+    pub target: Option<String>,
+    /// Ports the packet would be sent to
+    pub ports: Vec<u16>,
+    pub issues: Vec<PreflightIssue>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AddTagRequest {
+    pub tag: String,
+}
+
+#[derive(Deserialize, ToSchema, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMatch {
+    /// Device must carry at least one of the requested tags
+    #[default]
+    Any,
+    /// Device must carry every requested tag
+    All,
+}
+
+#[derive(Deserialize, ToSchema, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceSort {
+    /// By id, i.e. creation order
+    #[default]
+    Id,
+    /// The calling user's favorites first, then by id
+    Favorite,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListDevicesQuery {
+    /// Filter by tag (repeatable)
+    #[serde(default)]
+    pub tag: Vec<String>,
+    /// How multiple tags are combined (default: any)
+    pub tag_match: Option<TagMatch>,
+    /// Sort order (default: id)
+    pub sort: Option<DeviceSort>,
+    /// Comma-separated list of fields to return, e.g. `id,name,is_online`
+    pub fields: Option<String>,
+    /// Maximum number of devices to return
+    pub limit: Option<i64>,
+    /// Number of devices to skip (requires `limit`)
+    pub offset: Option<i64>,
+    /// Matches part of the name, the start of the IP address or the start of the MAC address
+    pub search: Option<String>,
+    /// Start of the MAC address; `:`/`-` delimiters are ignored
+    pub mac_prefix: Option<String>,
+    /// Exact IP address
+    pub ip: Option<String>,
+    /// Also list disabled devices (default: false)
+    pub include_disabled: Option<bool>,
+}
+
+// ==========================================
+// 2. HELPER FUNCTIONS
+// ==========================================
+
+/// Tells an explicit `null` (`Some(None)`) apart from an omitted field (`None`, via
+/// `#[serde(default)]`), for updates where `null` clears a value
+fn explicit_null<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Escapes `%`, `_` and `\` so `value` matches literally in a `LIKE ... ESCAPE '\'` pattern
+fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// Uppercase hex digits of a (partial) MAC address with delimiters removed,
+/// or `None` when it contains anything else
+fn normalize_mac_prefix(value: &str) -> Option<String> {
+    let hex: String = value.chars().filter(|c| *c != ':' && *c != '-').collect();
+    if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(hex.to_ascii_uppercase())
+}
+
+/// Fields of `DeviceResponse` that may be requested via `?fields=`
+const DEVICE_FIELDS: &[&str] = &[
+    "id",
+    "name",
+    "mac_address",
+    "ip_address",
+    "broadcast_addr",
+    "icon",
+    "is_online",
+    "status",
+    "last_seen_at",
+    "last_rtt_ms",
+    "went_online_at",
+    "online_for_secs",
+    "liveness_probe",
+    "ping_timeout_ms",
+    "wol_ports",
+    "last_action_error",
+    "last_action_error_at",
+    "tags",
+    "is_favorite",
+    "enabled",
+    "monitoring_enabled",
+    "version",
+    "owner_id",
+    "group_id",
+    "agent_os",
+    "agent_hostname",
+    "agent_version",
+    "agent_base_path",
+    "can_wake",
+    "can_manage",
+];
+
+fn parse_fields(fields: &str) -> Result<Vec<String>, String> {
+    let mut parsed = Vec::new();
+    for field in fields.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        if !DEVICE_FIELDS.contains(&field) {
+            return Err(format!("Unknown field: {}", field));
+        }
+        parsed.push(field.to_string());
+    }
+    if parsed.is_empty() {
+        return Err("No fields requested".to_string());
+    }
+    Ok(parsed)
+}
+
+fn project_fields(device: &DeviceResponse, fields: &[String]) -> serde_json::Value {
+    let mut value = serde_json::to_value(device).unwrap_or_default();
+    if let Some(object) = value.as_object_mut() {
+        object.retain(|key, _| fields.contains(key));
+    }
+    value
+}
+
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+async fn fetch_device_tags(state: &AppState, device_id: i64) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT tag FROM device_tags WHERE device_id = ? ORDER BY tag",
+        device_id
+    )
+    .fetch_all(&state.db)
+    .await
+}
+
+async fn fetch_favorite_ids(state: &AppState, user_id: i64) -> Result<HashSet<i64>, sqlx::Error> {
+    let rows = sqlx::query_scalar!("SELECT device_id FROM user_favorites WHERE user_id = ?", user_id)
+        .fetch_all(&state.db)
+        .await?;
+    Ok(rows.into_iter().collect())
+}
+
+async fn is_favorite(state: &AppState, user_id: i64, device_id: i64) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT device_id FROM user_favorites WHERE user_id = ? AND device_id = ?",
+        user_id,
+        device_id
+    )
+    .fetch_optional(&state.db)
+    .await?;
+    Ok(row.is_some())
+}
+
+/// Whether `viewer` may see and use a device with this owner: admins any device,
+/// everyone else shared devices and their own
+pub fn can_access_device(viewer: &AuthUser, owner_id: Option<i64>) -> bool {
+    viewer.is_admin() || owner_id.is_none_or(|owner| owner == viewer.id)
+}
+
+/// Loads device `id` and checks that `viewer` may use it. Devices owned by someone
+/// else answer `404` like missing ones, so their ids don't leak.
+async fn authorize_device(state: &AppState, viewer: &AuthUser, id: i64) -> Result<(), Response> {
+    match sqlx::query_scalar!("SELECT owner_id FROM devices WHERE id = ?", id)
+        .fetch_optional(&state.db)
+        .await
+    {
+        Ok(Some(owner_id)) if can_access_device(viewer, owner_id) => Ok(()),
+        Ok(_) => Err((StatusCode::NOT_FOUND, "Device not found").into_response()),
+        Err(e) => Err(db_error(&e, "Database error")),
+    }
+}
+
+async fn fetch_all_device_tags(state: &AppState) -> Result<HashMap<i64, Vec<String>>, sqlx::Error> {
+    let rows = sqlx::query!("SELECT device_id, tag FROM device_tags ORDER BY tag")
+        .fetch_all(&state.db)
+        .await?;
+
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for row in rows {
+        tags.entry(row.device_id).or_default().push(row.tag);
+    }
+    Ok(tags)
+}
+
+/// Checks an `agent_base_path` and brings it into its stored form: `None` when blank,
+/// otherwise without trailing slashes so paths can be appended directly.
+pub fn normalize_agent_base_path(path: &str) -> Result<Option<String>, String> {
+    let path = path.trim();
+    if path.is_empty() {
+        return Ok(None);
+    }
+    if !path.starts_with('/') {
+        return Err("agent_base_path must start with /".to_string());
+    }
+    if path.len() > MAX_AGENT_BASE_PATH_LEN
+        || path.chars().any(|c| c.is_whitespace() || c.is_control() || matches!(c, '?' | '#'))
+    {
+        return Err(format!(
+            "agent_base_path must be a URL path of at most {} characters, without query or fragment",
+            MAX_AGENT_BASE_PATH_LEN
+        ));
+    }
+    let path = path.trim_end_matches('/');
+    Ok((!path.is_empty()).then(|| path.to_string()))
+}
+
+pub fn validate_ping_timeout(ping_timeout_ms: Option<i64>) -> Result<(), String> {
+    match ping_timeout_ms {
+        Some(ms) if !(pinger::MIN_PING_TIMEOUT_MS..=pinger::MAX_PING_TIMEOUT_MS).contains(&ms) => Err(format!(
+            "ping_timeout_ms must be between {} and {}",
+            pinger::MIN_PING_TIMEOUT_MS,
+            pinger::MAX_PING_TIMEOUT_MS
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Message for device creation refused by `MAX_DEVICES`
+pub const DEVICE_LIMIT_REACHED: &str = "Device limit reached";
+
+/// Whether there are more devices than `MAX_DEVICES`. Checked inside the transaction
+/// that added them: it holds SQLite's write lock, so concurrent inserts can't slip past.
+pub async fn exceeds_device_limit(conn: &mut SqliteConnection, max_devices: Option<i64>) -> Result<bool, sqlx::Error> {
+    let Some(max) = max_devices else {
+        return Ok(false);
+    };
+    let count = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!: i64" FROM devices"#)
+        .fetch_one(conn)
+        .await?;
+    Ok(count > max)
+}
+
+/// Most ports a device may list in `wol_ports`
+const MAX_WOL_PORTS: usize = 4;
+/// Longest `agent_base_path` accepted
+const MAX_AGENT_BASE_PATH_LEN: usize = 255;
+
+/// Lets a client retry `POST /api/devices` without creating a duplicate
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+/// How long a key keeps returning the device it created
+const IDEMPOTENCY_KEY_TTL_HOURS: i64 = 24;
+
+/// Defaults and bound for `POST /api/devices/{id}/wake-and-ready`
+const DEFAULT_SERVICE_PORT: u16 = 22;
+const DEFAULT_READY_TIMEOUT_SECS: u64 = 120;
+const MAX_READY_TIMEOUT_SECS: u64 = 600;
+
+pub fn validate_wol_ports(ports: &[u16]) -> Result<(), String> {
+    if ports.len() > MAX_WOL_PORTS {
+        return Err(format!("wol_ports may list at most {} ports", MAX_WOL_PORTS));
+    }
+    let mut seen = HashSet::new();
+    for &port in ports {
+        if port == 0 {
+            return Err("wol_ports must not contain port 0".to_string());
+        }
+        if !seen.insert(port) {
+            return Err(format!("Port {} is listed twice in wol_ports", port));
+        }
+    }
+    Ok(())
+}
+
+/// `devices.wol_ports` value for a validated list; empty means "use the default"
+pub fn wol_ports_json(ports: &[u16]) -> Option<String> {
+    if ports.is_empty() {
+        return None;
+    }
+    serde_json::to_string(ports).ok()
+}
+
+pub fn parse_wol_ports(stored: Option<&str>) -> Option<Vec<u16>> {
+    stored.and_then(|s| serde_json::from_str(s).ok())
+}
+
+/// Ports to wake a device on: its own list, or `WOL_DEFAULT_PORT`
+fn effective_wol_ports(state: &AppState, stored: Option<&str>) -> Vec<u16> {
+    parse_wol_ports(stored)
+        .filter(|ports| !ports.is_empty())
+        .unwrap_or_else(|| vec![state.config.wol_default_port])
+}
+
+/// Everything `wake_device` would refuse or warn about for a device, without sending anything
+fn wake_preflight_issues(
+    enabled: bool,
+    mac_address: &str,
+    lenient_mac: bool,
+    ip_address: Option<&str>,
+    broadcast_addr: Option<&str>,
+    wol_ports: Option<&str>,
+) -> Vec<PreflightIssue> {
+    let mut issues = Vec::new();
+    let mut issue = |severity, field: &str, message: &str| {
+        issues.push(PreflightIssue { severity, field: field.to_string(), message: message.to_string() });
+    };
+
+    if !enabled {
+        issue(PreflightSeverity::Error, "enabled", "Device disabled");
+    }
+    if wol::parse_mac(mac_address, lenient_mac).is_none() {
+        issue(PreflightSeverity::Error, "mac_address", "Invalid MAC address format");
+    }
+    match wol::broadcast_target(broadcast_addr) {
+        None => issue(PreflightSeverity::Error, "broadcast_addr", "Invalid broadcast address"),
+        Some(wol::GLOBAL_BROADCAST) if ip_address.is_none() => issue(
+            PreflightSeverity::Warning,
+            "broadcast_addr",
+            "No broadcast or IP configured; the packet only reaches the server's own subnet",
+        ),
+        Some(_) => {}
+    }
+    match ip_address {
+        None => issue(PreflightSeverity::Warning, "ip_address", "No IP address; the wake can't be verified"),
+        Some(ip) if ip.parse::<std::net::IpAddr>().is_err() => issue(
+            PreflightSeverity::Warning,
+            "ip_address",
+            "Invalid IP address; the wake can't be verified",
+        ),
+        Some(_) => {}
+    }
+    if let Some(stored) = wol_ports
+        && parse_wol_ports(Some(stored)).is_none_or(|ports| validate_wol_ports(&ports).is_err())
+    {
+        issue(PreflightSeverity::Warning, "wol_ports", "Invalid WoL ports; WOL_DEFAULT_PORT is used instead");
+    }
+    issues
+}
+
+/// Caches what an agent reported about its machine; fields it left out keep their old value.
+async fn store_agent_info(state: &AppState, id: i64, info: &AgentInfo) {
+    if info.version.is_none() && info.os.is_none() && info.hostname.is_none() {
+        return;
+    }
+    let result = sqlx::query!(
+        "UPDATE devices
+         SET agent_os = COALESCE(?, agent_os),
+             agent_hostname = COALESCE(?, agent_hostname),
+             agent_version = COALESCE(?, agent_version)
+         WHERE id = ?",
+        info.os,
+        info.hostname,
+        info.version,
+        id
+    )
+    .execute(&state.db)
+    .await;
+
+    if let Err(e) = result {
+        println!("Failed to store agent info of device {}: {}", id, e);
+    }
+}
+
+/// The device an earlier create with the same `Idempotency-Key` made, if the key hasn't
+/// expired and the device still exists. Expired keys are purged on the way.
+async fn fetch_idempotent_device(state: &AppState, user_id: i64, key: &str) -> Result<Option<DeviceRow>, sqlx::Error> {
+    let cutoff = format!("-{} hours", IDEMPOTENCY_KEY_TTL_HOURS);
+    sqlx::query!("DELETE FROM device_idempotency_keys WHERE created_at < datetime('now', ?)", cutoff)
+        .execute(&state.db)
+        .await?;
+
+    sqlx::query_as!(
+        DeviceRow,
+        r#"SELECT d.id as "id!", d.name as "name!", d.mac_address as "mac_address!", d.ip_address as "ip_address?",
+               d.broadcast_addr as "broadcast_addr?", d.icon as "icon?", d.is_online as "is_online?",
+               d.last_seen_at as "last_seen_at?", d.went_online_at, d.liveness_probe as "liveness_probe!", d.ping_timeout_ms,
+               d.last_action_error, d.last_action_error_at, d.enabled as "enabled!: bool", d.monitoring_enabled as "monitoring_enabled!: bool", d.wol_ports, d.version, d.owner_id,
+               d.group_id, d.last_rtt_ms, d.agent_os, d.agent_hostname, d.agent_version,
+               d.agent_base_path
+           FROM device_idempotency_keys k
+           JOIN devices d ON d.id = k.device_id
+           WHERE k.user_id = ? AND k.key = ?"#,
+        user_id,
+        key
+    )
+    .fetch_optional(&state.db)
+    .await
+}
+
+/// `201 Created` for a new device, with its URL in `Location`
+fn created(resp: DeviceResponse) -> Response {
+    let location = format!("/api/devices/{}", resp.id);
+    (StatusCode::CREATED, [(header::LOCATION, location)], Json(resp)).into_response()
+}
+
+/// Persists the outcome of a wake/shutdown: `Some(error)` on failure, `None` clears it.
+pub async fn record_action_result(state: &AppState, id: i64, error: Option<&str>) {
+    let _ = sqlx::query!(
+        "UPDATE devices SET last_action_error = ?, last_action_error_at = CASE WHEN ? IS NULL THEN NULL ELSE CURRENT_TIMESTAMP END WHERE id = ?",
+        error,
+        error,
+        id
+    )
+    .execute(&state.db)
+    .await;
+}
+
+/// Sends a magic packet to a stored device and records the outcome as its last action result.
+pub async fn send_device_wake(
+    state: &AppState,
+    id: i64,
+    mac_address: &str,
+    broadcast_addr: Option<&str>,
+    wol_ports: Option<&str>,
+) -> Result<(), String> {
+    let ports = effective_wol_ports(state, wol_ports);
+    let result = match (wol::parse_mac(mac_address, state.config.lenient_mac_parsing), wol::broadcast_target(broadcast_addr)) {
+        (Some(mac), Some(target)) => state.wake_sender.send(&mac, target, &ports, state.config.wol_source_port)
+            .await
+            .map_err(|e| format!("Failed to send WoL: {}", e)),
+        (None, _) => Err("Invalid MAC address format in DB".to_string()),
+        (_, None) => Err("Invalid broadcast address in DB".to_string()),
+    };
+    record_action_result(state, id, result.as_ref().err().map(String::as_str)).await;
+    result
+}
+
+/// Records `message` as the device's last action error and returns it as the response.
+async fn action_failed(state: &AppState, id: i64, status: StatusCode, message: String) -> Response {
+    record_action_result(state, id, Some(&message)).await;
+    (status, message).into_response()
+}
+
+// ==========================================
+// 3. HANDLERS
+// ==========================================
+
+/// GET /api/devices
+/// Non-admins only see devices they own and shared ones
+#[utoipa::path(
+    get,
+    path = "/api/devices",
+    params(ListDevicesQuery),
+    tag = "devices",
+    responses(
+        (status = 200, description = "List all devices", body = [DeviceResponse],
+            headers(
+                ("X-Total-Count" = i64, description = "Number of matching devices"),
+                ("X-Page-Limit" = i64, description = "Requested limit, when paginating"),
+                ("X-Page-Offset" = i64, description = "Requested offset, when paginating")
+            )),
+        (status = 400, description = "Unknown field, invalid pagination or invalid MAC prefix")
+    )
+)]
+pub async fn list_devices(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Query(query): Query<ListDevicesQuery>,
+) -> impl IntoResponse {
+    let fields = match query.fields.as_deref().map(parse_fields).transpose() {
+        Ok(f) => f,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let page = match Page::new(query.limit, query.offset) {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let mut tags: Vec<String> = query.tag.iter().map(|t| normalize_tag(t)).collect();
+    // Repeated tags would otherwise raise the match count `all` requires
+    tags.sort();
+    tags.dedup();
+    let tag_count = tags.len() as i64;
+    let tags_json = serde_json::to_string(&tags).unwrap_or_else(|_| "[]".to_string());
+    // 'any' needs one matching tag per device, 'all' needs every requested tag
+    let required_matches = match query.tag_match.unwrap_or_default() {
+        TagMatch::Any => 1,
+        TagMatch::All => tag_count,
+    };
+
+    let search = query.search.as_deref().map(str::trim).filter(|s| !s.is_empty());
+    let name_pattern = search.map(|s| format!("%{}%", escape_like(s)));
+    let ip_pattern = search.map(|s| format!("{}%", escape_like(s)));
+    // Only searches that look like part of a MAC address are compared against it
+    let search_mac = search.and_then(normalize_mac_prefix).map(|hex| format!("{}%", hex));
+    let mac_prefix = match query.mac_prefix.as_deref().map(str::trim) {
+        Some(prefix) => match normalize_mac_prefix(prefix) {
+            Some(hex) => Some(format!("{}%", hex)),
+            None => return (StatusCode::BAD_REQUEST, "Invalid MAC prefix").into_response(),
+        },
+        None => None,
+    };
+
+    let include_disabled = query.include_disabled.unwrap_or(false);
+    let is_admin = auth.is_admin();
+
+    let favorites_first = query.sort.unwrap_or_default() == DeviceSort::Favorite;
+    let limit = page.sql_limit();
+    let offset = page.sql_offset();
+    let devices = sqlx::query_as!(
+        DeviceRow,
+        r#"SELECT 
+            id as "id!", name as "name!", mac_address as "mac_address!", ip_address as "ip_address?",
+            broadcast_addr as "broadcast_addr?", icon as "icon?", is_online as "is_online?",
+            last_seen_at as "last_seen_at?", went_online_at, liveness_probe as "liveness_probe!", ping_timeout_ms,
+            last_action_error, last_action_error_at, enabled as "enabled!: bool", monitoring_enabled as "monitoring_enabled!: bool", wol_ports, version, owner_id, group_id, last_rtt_ms,
+                agent_os, agent_hostname, agent_version, agent_base_path
+           FROM devices
+           WHERE (? = 0 OR id IN (
+               SELECT device_id FROM device_tags
+               WHERE tag IN (SELECT value FROM json_each(?))
+               GROUP BY device_id
+               HAVING COUNT(*) >= ?
+           ))
+           AND (? IS NULL
+               OR name LIKE ? ESCAPE '\'
+               OR ip_address LIKE ? ESCAPE '\'
+               OR (? IS NOT NULL AND upper(replace(replace(mac_address, ':', ''), '-', '')) LIKE ?))
+           AND (? IS NULL OR ip_address = ?)
+           AND (? IS NULL OR upper(replace(replace(mac_address, ':', ''), '-', '')) LIKE ?)
+           AND (? OR enabled = 1)
+           AND (? OR owner_id IS NULL OR owner_id = ?)
+           ORDER BY (? AND id IN (SELECT device_id FROM user_favorites WHERE user_id = ?)) DESC, id
+           LIMIT ? OFFSET ?"#,
+        tag_count,
+        tags_json,
+        required_matches,
+        search,
+        name_pattern,
+        ip_pattern,
+        search_mac,
+        search_mac,
+        query.ip,
+        query.ip,
+        mac_prefix,
+        mac_prefix,
+        include_disabled,
+        is_admin,
+        auth.id,
+        favorites_first,
+        auth.id,
+        limit,
+        offset
+    )
+    .fetch_all(&state.db)
+    .await;
+
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!: i64" FROM devices
+           WHERE (? = 0 OR id IN (
+               SELECT device_id FROM device_tags
+               WHERE tag IN (SELECT value FROM json_each(?))
+               GROUP BY device_id
+               HAVING COUNT(*) >= ?
+           ))
+           AND (? IS NULL
+               OR name LIKE ? ESCAPE '\'
+               OR ip_address LIKE ? ESCAPE '\'
+               OR (? IS NOT NULL AND upper(replace(replace(mac_address, ':', ''), '-', '')) LIKE ?))
+           AND (? IS NULL OR ip_address = ?)
+           AND (? IS NULL OR upper(replace(replace(mac_address, ':', ''), '-', '')) LIKE ?)
+           AND (? OR enabled = 1)
+           AND (? OR owner_id IS NULL OR owner_id = ?)"#,
+        tag_count,
+        tags_json,
+        required_matches,
+        search,
+        name_pattern,
+        ip_pattern,
+        search_mac,
+        search_mac,
+        query.ip,
+        query.ip,
+        mac_prefix,
+        mac_prefix,
+        include_disabled,
+        is_admin,
+        auth.id
+    )
+    .fetch_one(&state.db)
+    .await;
+    let total = match total {
+        Ok(t) => t,
+        Err(e) => return db_error(&e, "Failed to fetch devices"),
+    };
+    let headers = page.headers(total);
+
+    let mut device_tags = match fetch_all_device_tags(&state).await {
+        Ok(t) => t,
+        Err(e) => return db_error(&e, "Failed to fetch devices"),
+    };
+    let favorites = match fetch_favorite_ids(&state, auth.id).await {
+        Ok(f) => f,
+        Err(e) => return db_error(&e, "Failed to fetch devices"),
+    };
+
+    match devices {
+        Ok(rows) => {
+            let res: Vec<DeviceResponse> = rows.into_iter().map(|row| {
+                let tags = device_tags.remove(&row.id).unwrap_or_default();
+                let is_favorite = favorites.contains(&row.id);
+                row.into_response(tags, is_favorite, &auth)
+            }).collect();
+
+            match fields {
+                Some(fields) => {
+                    let projected: Vec<serde_json::Value> = res
+                        .iter()
+                        .map(|device| project_fields(device, &fields))
+                        .collect();
+                    (headers, Json(projected)).into_response()
+                }
+                None => (headers, Json(res)).into_response(),
+            }
+        },
+        Err(e) => db_error(&e, "Failed to fetch devices"),
+    }
+}
+
+/// GET /api/devices/events
+/// Server-sent events stream of device status changes
+#[utoipa::path(
+    get,
+    path = "/api/devices/events",
+    tag = "devices",
+    responses(
+        (status = 200, description = "`device_status` events; a `lagged` event carries the number of missed events, after which clients should re-fetch /api/devices",
+            content_type = "text/event-stream", body = DeviceStatusEvent)
+    )
+)]
+pub async fn device_events(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    // The receiver is dropped with the stream when the client disconnects
+    let stream = BroadcastStream::new(state.events.subscribe()).filter_map(move |msg| {
+        let event = match msg {
+            Ok(status) if !can_access_device(&auth, status.owner_id) => return None,
+            Ok(status) => Event::default().event("device_status").json_data(&status).ok()?,
+            Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                Event::default().event("lagged").data(missed.to_string())
+            }
+        };
+        Some(Ok::<_, Infallible>(event))
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// POST /api/devices/status
+/// Status of just the requested devices; unknown ids and other users' devices are omitted
+#[utoipa::path(
+    post,
+    path = "/api/devices/status",
+    request_body = DeviceStatusRequest,
+    tag = "devices",
+    responses(
+        (status = 200, description = "Status of the known devices among the requested ids", body = [DeviceStatusResponse]),
+        (status = 422, description = "Too many ids")
+    )
+)]
+pub async fn device_status(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Json(payload): Json<DeviceStatusRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = check_batch_size(&state.config, "ids", payload.ids.len()) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+
+    let ids_json = serde_json::to_string(&payload.ids).unwrap_or_else(|_| "[]".to_string());
+    let is_admin = auth.is_admin();
+    let rows = sqlx::query!(
+        r#"SELECT id as "id!", ip_address, is_online, last_seen_at FROM devices
+           WHERE id IN (SELECT value FROM json_each(?))
+             AND (? OR owner_id IS NULL OR owner_id = ?)
+           ORDER BY id"#,
+        ids_json,
+        is_admin,
+        auth.id
+    )
+    .fetch_all(&state.db)
+    .await;
+
+    match rows {
+        Ok(rows) => {
+            let res: Vec<DeviceStatusResponse> = rows.into_iter().map(|row| DeviceStatusResponse {
+                id: row.id,
+                is_online: row.is_online.unwrap_or(false),
+                status: DeviceStatus::new(row.is_online, row.ip_address.as_deref()),
+                last_seen_at: row.last_seen_at,
+            }).collect();
+            Json(res).into_response()
+        }
+        Err(e) => db_error(&e, "Failed to fetch device status"),
+    }
+}
+
+/// POST /api/devices
+#[utoipa::path(
+    post,
+    path = "/api/devices",
+    params(
+        ("Idempotency-Key" = Option<String>, Header,
+            description = "Retrying with the same key within 24 hours returns the device the first request created")
+    ),
+    request_body = CreateDeviceRequest,
+    tag = "devices",
+    responses(
+        (status = 201, description = "Device created, or the one created earlier with the same Idempotency-Key", body = DeviceResponse,
+            headers(("Location" = String, description = "URL of the created device"))),
+        (status = 400, description = "Invalid liveness probe, ping timeout, broadcast address, WoL ports or Idempotency-Key"),
+        (status = 409, description = "Device limit reached"),
+        (status = 422, description = "Control characters in name or icon"),
+        (status = 500, description = "Server error")
+    )
+)]
+pub async fn create_device(
+    admin: AdminUser,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateDeviceRequest>,
+) -> impl IntoResponse {
+    let idempotency_key = match headers.get(IDEMPOTENCY_KEY_HEADER).map(|v| v.to_str()) {
+        None => None,
+        Some(Ok(key)) if (1..=MAX_IDEMPOTENCY_KEY_LEN).contains(&key.len()) => Some(key.to_string()),
+        Some(_) => return (StatusCode::BAD_REQUEST, "Invalid Idempotency-Key header").into_response(),
+    };
+    if let Some(key) = &idempotency_key {
+        match fetch_idempotent_device(&state, admin.0.id, key).await {
+            Ok(Some(dev)) => {
+                let tags = match fetch_device_tags(&state, dev.id).await {
+                    Ok(t) => t,
+                    Err(e) => return db_error(&e, "Failed to create device"),
+                };
+                let favorite = match is_favorite(&state, admin.0.id, dev.id).await {
+                    Ok(f) => f,
+                    Err(e) => return db_error(&e, "Failed to create device"),
+                };
+                return created(dev.into_response(tags, favorite, &admin.0));
+            }
+            Ok(None) => {}
+            Err(e) => return db_error(&e, "Failed to create device"),
+        }
+    }
+
+    if let Err(e) = reject_control_chars("name", &payload.name)
+        .and_then(|_| reject_control_chars_opt("icon", payload.icon.as_deref()))
+    {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+    let broadcast_addr = match payload.broadcast_addr.as_deref().map(str::trim).filter(|addr| !addr.is_empty()) {
+        None => state.config.default_broadcast_addr.to_string(),
+        Some(addr) => match wol::broadcast_target(Some(addr)) {
+            Some(target) => target.to_string(),
+            None => return (StatusCode::BAD_REQUEST, "Invalid broadcast address").into_response(),
+        },
+    };
+    let liveness_probe = match payload.liveness_probe.as_deref().map(str::parse::<LivenessProbe>).transpose() {
+        Ok(p) => p.unwrap_or_default().to_string(),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if let Err(e) = validate_ping_timeout(payload.ping_timeout_ms) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let wol_ports = payload.wol_ports.unwrap_or_default();
+    if let Err(e) = validate_wol_ports(&wol_ports) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let wol_ports = wol_ports_json(&wol_ports);
+    let monitoring_enabled = payload.monitoring_enabled.unwrap_or(state.config.default_monitoring_enabled);
+    let agent_base_path = match payload.agent_base_path.as_deref().map(normalize_agent_base_path).transpose() {
+        Ok(path) => path.flatten(),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let mut tx = match state.db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(&e, "Failed to create device"),
+    };
+
+    // The `MAX_DEVICES` check is part of the insert, so concurrent creates can't both slip under it
+    let max_devices = state.config.max_devices;
+    let result = sqlx::query_as!(
+        DeviceRow,
+        r#"
+            INSERT INTO devices (name, mac_address, ip_address, broadcast_addr, icon, liveness_probe, ping_timeout_ms, is_online, wol_ports,
+                monitoring_enabled, agent_base_path)
+            SELECT ?, ?, ?, ?, ?, ?, ?, NULL, ?, ?, ?
+            WHERE ? IS NULL OR (SELECT COUNT(*) FROM devices) < ?
+            RETURNING id as "id!", name as "name!", mac_address as "mac_address!", ip_address as "ip_address?",
+                broadcast_addr as "broadcast_addr?", icon as "icon?", is_online as "is_online?",
+                last_seen_at as "last_seen_at?", went_online_at, liveness_probe as "liveness_probe!", ping_timeout_ms,
+                last_action_error, last_action_error_at, enabled as "enabled!: bool", monitoring_enabled as "monitoring_enabled!: bool", wol_ports, version, owner_id, group_id, last_rtt_ms,
+                agent_os, agent_hostname, agent_version, agent_base_path
+        "#,
+        payload.name,
+        payload.mac_address,
+        payload.ip_address,
+        broadcast_addr,
+        payload.icon,
+        liveness_probe,
+        payload.ping_timeout_ms,
+        wol_ports,
+        monitoring_enabled,
+        agent_base_path,
+        max_devices,
+        max_devices
+    )
+    .fetch_optional(&mut *tx)
+    .await;
+
+    let dev = match result {
+        Ok(Some(dev)) => dev,
+        Ok(None) => return (StatusCode::CONFLICT, DEVICE_LIMIT_REACHED).into_response(),
+        Err(e) => return db_error(&e, "Failed to create device"),
+    };
+
+    if let Some(key) = &idempotency_key {
+        let claimed = sqlx::query!(
+            "INSERT INTO device_idempotency_keys (user_id, key, device_id) VALUES (?, ?, ?)
+             ON CONFLICT (user_id, key) DO NOTHING",
+            admin.0.id,
+            key,
+            dev.id
+        )
+        .execute(&mut *tx)
+        .await;
+        match claimed {
+            Ok(r) if r.rows_affected() == 1 => {}
+            // A concurrent request with the same key got there first: drop this device, return theirs
+            Ok(_) => {
+                drop(tx);
+                return match fetch_idempotent_device(&state, admin.0.id, key).await {
+                    Ok(Some(dev)) => created(dev.into_response(Vec::new(), false, &admin.0)),
+                    Ok(None) => (StatusCode::CONFLICT, "Idempotency-Key is in use").into_response(),
+                    Err(e) => db_error(&e, "Failed to create device"),
+                };
+            }
+            Err(e) => return db_error(&e, "Failed to create device"),
+        }
+    }
+    if let Err(e) = tx.commit().await {
+        return db_error(&e, "Failed to create device");
+    }
+
+    created(dev.into_response(Vec::new(), false, &admin.0))
+}
+
+/// POST /api/devices/:id/clone
+/// Copies a device's configuration and tags into a new device.
+/// Runtime state (online status, last action error, favorites) is not copied.
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/clone",
+    params(
+        ("id" = i64, Path, description = "Device to clone")
+    ),
+    request_body(content = Option<CloneDeviceRequest>, description = "Optional overrides"),
+    tag = "devices",
+    responses(
+        (status = 201, description = "Device cloned", body = DeviceResponse,
+            headers(("Location" = String, description = "URL of the created device"))),
+        (status = 404, description = "Device not found"),
+        (status = 409, description = "Device limit reached")
+    )
+)]
+pub async fn clone_device(
+    admin: AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    payload: Option<Json<CloneDeviceRequest>>,
+) -> impl IntoResponse {
+    let payload = payload.map(|Json(p)| p).unwrap_or_default();
+    if let Err(e) = reject_control_chars_opt("name", payload.name.as_deref()) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+    let mac_address = payload.mac_address.unwrap_or_default();
+
+    let mut tx = match state.db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(&e, "Failed to clone device"),
+    };
+
+    let result = sqlx::query_as!(
+        DeviceRow,
+        r#"
+            INSERT INTO devices (name, mac_address, ip_address, broadcast_addr, icon, liveness_probe, ping_timeout_ms, is_online,
+                group_id, wake_order, wake_delay_secs, wol_ports, monitoring_enabled, agent_base_path, owner_id)
+            SELECT COALESCE(?, name || ' (copy)'), ?, ?, broadcast_addr, icon, liveness_probe, ping_timeout_ms, NULL,
+                group_id, wake_order, wake_delay_secs, wol_ports, monitoring_enabled, agent_base_path, owner_id
+            FROM devices WHERE id = ?
+            RETURNING id as "id!", name as "name!", mac_address as "mac_address!", ip_address as "ip_address?",
+                broadcast_addr as "broadcast_addr?", icon as "icon?", is_online as "is_online?",
+                last_seen_at as "last_seen_at?", went_online_at, liveness_probe as "liveness_probe!", ping_timeout_ms,
+                last_action_error, last_action_error_at, enabled as "enabled!: bool", monitoring_enabled as "monitoring_enabled!: bool", wol_ports, version, owner_id, group_id, last_rtt_ms,
+                agent_os, agent_hostname, agent_version, agent_base_path
+        "#,
+        payload.name,
+        mac_address,
+        payload.ip_address,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await;
+
+    let dev = match result {
+        Ok(Some(dev)) => dev,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => return db_error(&e, "Failed to clone device"),
+    };
+
+    let copied = sqlx::query!(
+        "INSERT INTO device_tags (device_id, tag) SELECT ?, tag FROM device_tags WHERE device_id = ?",
+        dev.id,
+        id
+    )
+    .execute(&mut *tx)
+    .await;
+
+    if let Err(e) = copied {
+        return db_error(&e, "Failed to clone device");
+    }
+    match exceeds_device_limit(&mut tx, state.config.max_devices).await {
+        Ok(false) => {}
+        Ok(true) => return (StatusCode::CONFLICT, DEVICE_LIMIT_REACHED).into_response(),
+        Err(e) => return db_error(&e, "Failed to clone device"),
+    }
+    if let Err(e) = tx.commit().await {
+        return db_error(&e, "Failed to clone device");
+    }
+
+    let tags = match fetch_device_tags(&state, dev.id).await {
+        Ok(t) => t,
+        Err(e) => return db_error(&e, "Failed to clone device"),
+    };
+    created(dev.into_response(tags, false, &admin.0))
+}
+
+/// PUT /api/devices/:id
+#[utoipa::path(
+    put,
+    path = "/api/devices/{id}",
+    params(
+        ("id" = i64, Path, description = "Device ID")
+    ),
+    request_body = UpdateDeviceRequest,
+    tag = "devices",
+    responses(
+        (status = 200, description = "Device updated", body = DeviceResponse),
+        (status = 400, description = "Invalid liveness probe, ping timeout, broadcast address, WoL ports or agent base path"),
+        (status = 404, description = "Device not found"),
+        (status = 409, description = "Device was modified since `version`"),
+        (status = 422, description = "Control characters in name or icon"),
+        (status = 500, description = "Server error")
+    )
+)]
+pub async fn update_device(
+    admin: AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<UpdateDeviceRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = reject_control_chars_opt("name", payload.name.as_deref())
+        .and_then(|_| reject_control_chars_opt("icon", payload.icon.as_deref()))
+    {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+    let liveness_probe = match payload.liveness_probe.as_deref().map(str::parse::<LivenessProbe>).transpose() {
+        Ok(p) => p.map(|p| p.to_string()),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if let Err(e) = validate_ping_timeout(payload.ping_timeout_ms.flatten()) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let ip_address_given = payload.ip_address.is_some();
+    let ip_address = payload.ip_address.flatten();
+    let ping_timeout_given = payload.ping_timeout_ms.is_some();
+    let ping_timeout_ms = payload.ping_timeout_ms.flatten();
+    // A blank address switches back to the global broadcast
+    let broadcast_addr = match payload.broadcast_addr.as_deref().map(|addr| wol::broadcast_target(Some(addr))) {
+        Some(Some(target)) => Some(target.to_string()),
+        Some(None) => return (StatusCode::BAD_REQUEST, "Invalid broadcast address").into_response(),
+        None => None,
+    };
+    if let Some(ports) = &payload.wol_ports
+        && let Err(e) = validate_wol_ports(ports)
+    {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let wol_ports_given = payload.wol_ports.is_some();
+    let wol_ports = payload.wol_ports.as_deref().and_then(wol_ports_json);
+    let agent_base_path_given = payload.agent_base_path.is_some();
+    let agent_base_path = match payload.agent_base_path.as_deref().map(normalize_agent_base_path).transpose() {
+        Ok(path) => path.flatten(),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let result = sqlx::query_as!(
+        DeviceRow,
+        r#"
+            UPDATE devices 
+            SET 
+                name = COALESCE(?, name),
+                mac_address = COALESCE(?, mac_address),
+                ip_address = CASE WHEN ? THEN ? ELSE ip_address END,
+                broadcast_addr = COALESCE(?, broadcast_addr),
+                icon = COALESCE(?, icon),
+                liveness_probe = COALESCE(?, liveness_probe),
+                ping_timeout_ms = CASE WHEN ? THEN ? ELSE ping_timeout_ms END,
+                wol_ports = CASE WHEN ? THEN ? ELSE wol_ports END,
+                monitoring_enabled = COALESCE(?, monitoring_enabled),
+                is_online = CASE WHEN ? = 0 THEN NULL ELSE is_online END,
+                went_online_at = CASE WHEN ? = 0 THEN NULL ELSE went_online_at END,
+                agent_base_path = CASE WHEN ? THEN ? ELSE agent_base_path END,
+                version = version + 1
+            WHERE id = ? AND (? IS NULL OR version = ?)
+            RETURNING id as "id!", name as "name!", mac_address as "mac_address!", ip_address as "ip_address?",
+                broadcast_addr as "broadcast_addr?", icon as "icon?", is_online as "is_online?",
+                last_seen_at as "last_seen_at?", went_online_at, liveness_probe as "liveness_probe!", ping_timeout_ms,
+                last_action_error, last_action_error_at, enabled as "enabled!: bool", monitoring_enabled as "monitoring_enabled!: bool", wol_ports, version, owner_id, group_id, last_rtt_ms,
+                agent_os, agent_hostname, agent_version, agent_base_path
+        "#,
+        payload.name,
+        payload.mac_address,
+        ip_address_given,
+        ip_address,
+        broadcast_addr,
+        payload.icon,
+        liveness_probe,
+        ping_timeout_given,
+        ping_timeout_ms,
+        wol_ports_given,
+        wol_ports,
+        payload.monitoring_enabled,
+        payload.monitoring_enabled,
+        payload.monitoring_enabled,
+        agent_base_path_given,
+        agent_base_path,
+        id,
+        payload.version,
+        payload.version
+    )
+    .fetch_optional(&state.db)
+    .await;
+
+    match result {
+        Ok(Some(dev)) => {
+            let tags = match fetch_device_tags(&state, dev.id).await {
+                Ok(t) => t,
+                Err(e) => return db_error(&e, "Failed to update device"),
+            };
+            let favorite = match is_favorite(&state, admin.0.id, dev.id).await {
+                Ok(f) => f,
+                Err(e) => return db_error(&e, "Failed to update device"),
+            };
+            let resp = dev.into_response(tags, favorite, &admin.0);
+            (StatusCode::OK, Json(resp)).into_response()
+        },
+        Ok(None) => {
+            // Either the device is gone or someone else updated it first
+            match sqlx::query!("SELECT id FROM devices WHERE id = ?", id)
+                .fetch_optional(&state.db)
+                .await
+            {
+                Ok(Some(_)) => (StatusCode::CONFLICT, "Device was modified").into_response(),
+                Ok(None) => (StatusCode::NOT_FOUND, "Device not found").into_response(),
+                Err(e) => db_error(&e, "Failed to update device"),
+            }
+        }
+        Err(e) => db_error(&e, "Failed to update device"),
+    }
+}
+
+/// DELETE /api/devices/:id
+#[utoipa::path(
+    delete,
+    path = "/api/devices/{id}",
+    params(
+        ("id" = i64, Path, description = "Device ID")
+    ),
+    tag = "devices",
+    responses(
+        (status = 200, description = "Device deleted"),
+        (status = 404, description = "Device not found")
+    )
+)]
+pub async fn delete_device(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    let result = sqlx::query!("DELETE FROM devices WHERE id = ?", id)
+        .execute(&state.db)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Device deleted").into_response(),
+        Err(e) => db_error(&e, "Failed to delete device"),
+    }
+}
+
+/// POST /api/devices/:id/tags
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/tags",
+    params(
+        ("id" = i64, Path, description = "Device ID")
+    ),
+    request_body = AddTagRequest,
+    tag = "devices",
+    responses(
+        (status = 200, description = "Tag attached, returns the device's tags", body = [String]),
+        (status = 400, description = "Invalid tag"),
+        (status = 404, description = "Device not found")
+    )
+)]
+pub async fn add_device_tag(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<AddTagRequest>,
+) -> impl IntoResponse {
+    let tag = normalize_tag(&payload.tag);
+    if tag.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Tag must not be empty").into_response();
+    }
+
+    let device = sqlx::query!("SELECT id FROM devices WHERE id = ?", id)
+        .fetch_optional(&state.db)
+        .await;
+
+    match device {
+        Ok(Some(_)) => {}
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
+    }
+
+    let result = sqlx::query!(
+        "INSERT OR IGNORE INTO device_tags (device_id, tag) VALUES (?, ?)",
+        id,
+        tag
+    )
+    .execute(&state.db)
+    .await;
+
+    if let Err(e) = result {
+        return db_error(&e, "Failed to add tag");
+    }
+
+    match fetch_device_tags(&state, id).await {
+        Ok(tags) => Json(tags).into_response(),
+        Err(e) => db_error(&e, "Database error"),
+    }
+}
+
+/// DELETE /api/devices/:id/tags/:tag
+#[utoipa::path(
+    delete,
+    path = "/api/devices/{id}/tags/{tag}",
+    params(
+        ("id" = i64, Path, description = "Device ID"),
+        ("tag" = String, Path, description = "Tag to remove")
+    ),
+    tag = "devices",
+    responses(
+        (status = 200, description = "Tag removed"),
+        (status = 404, description = "Tag not attached to device")
+    )
+)]
+pub async fn remove_device_tag(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path((id, tag)): Path<(i64, String)>,
+) -> impl IntoResponse {
+    let tag = normalize_tag(&tag);
+
+    let result = sqlx::query!(
+        "DELETE FROM device_tags WHERE device_id = ? AND tag = ?",
+        id,
+        tag
+    )
+    .execute(&state.db)
+    .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Tag not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Tag removed").into_response(),
+        Err(e) => db_error(&e, "Failed to remove tag"),
+    }
+}
+
+/// POST /api/devices/:id/favorite
+/// Pins the device for the calling user
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/favorite",
+    params(
+        ("id" = i64, Path, description = "Device ID")
+    ),
+    tag = "devices",
+    responses(
+        (status = 200, description = "Device added to favorites"),
+        (status = 404, description = "Device not found")
+    )
+)]
+pub async fn add_favorite(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize_device(&state, &auth, id).await {
+        return response;
+    }
+
+    let result = sqlx::query!(
+        "INSERT OR IGNORE INTO user_favorites (user_id, device_id) VALUES (?, ?)",
+        auth.id,
+        id
+    )
+    .execute(&state.db)
+    .await;
+
+    match result {
+        Ok(_) => (StatusCode::OK, "Device added to favorites").into_response(),
+        Err(e) => db_error(&e, "Failed to add favorite"),
+    }
+}
+
+/// DELETE /api/devices/:id/favorite
+/// Unpins the device for the calling user
+#[utoipa::path(
+    delete,
+    path = "/api/devices/{id}/favorite",
+    params(
+        ("id" = i64, Path, description = "Device ID")
+    ),
+    tag = "devices",
+    responses(
+        (status = 200, description = "Device removed from favorites"),
+        (status = 404, description = "Device not found")
+    )
+)]
+pub async fn remove_favorite(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize_device(&state, &auth, id).await {
+        return response;
+    }
+
+    let result = sqlx::query!(
+        "DELETE FROM user_favorites WHERE user_id = ? AND device_id = ?",
+        auth.id,
+        id
+    )
+    .execute(&state.db)
+    .await;
+
+    match result {
+        Ok(_) => (StatusCode::OK, "Device removed from favorites").into_response(),
+        Err(e) => db_error(&e, "Failed to remove favorite"),
+    }
+}
+
+/// GET /api/devices/:id/schedules
+/// Lists the device's pending schedules, soonest first
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/schedules",
+    params(
+        ("id" = i64, Path, description = "Device ID")
+    ),
+    tag = "devices",
+    responses(
+        (status = 200, description = "Pending schedules", body = Vec<ScheduleResponse>),
+        (status = 404, description = "Device not found")
+    )
+)]
+pub async fn list_schedules(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize_device(&state, &auth, id).await {
+        return response;
+    }
+
+    let result = sqlx::query_as!(
+        ScheduleResponse,
+        r#"SELECT id as "id!", device_id, kind, fire_at, created_at
+           FROM schedules WHERE device_id = ? ORDER BY fire_at"#,
+        id
+    )
+    .fetch_all(&state.db)
+    .await;
+
+    match result {
+        Ok(schedules) => Json(schedules).into_response(),
+        Err(e) => db_error(&e, "Failed to fetch schedules"),
+    }
+}
+
+/// PUT /api/devices/:id/status
+/// Enables or disables a device. Disabling forgets its online status, since it's no longer probed.
+#[utoipa::path(
+    put,
+    path = "/api/devices/{id}/status",
+    params(
+        ("id" = i64, Path, description = "Device ID")
+    ),
+    request_body = UpdateDeviceEnabledRequest,
+    tag = "devices",
+    responses(
+        (status = 200, description = "Status updated"),
+        (status = 404, description = "Device not found")
+    )
+)]
+pub async fn update_device_enabled(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<UpdateDeviceEnabledRequest>,
+) -> impl IntoResponse {
+    let result = sqlx::query!(
+        "UPDATE devices
+         SET enabled = ?,
+             is_online = CASE WHEN ? THEN is_online ELSE NULL END,
+             went_online_at = CASE WHEN ? THEN went_online_at ELSE NULL END
+         WHERE id = ?",
+        payload.enabled,
+        payload.enabled,
+        payload.enabled,
+        id
+    )
+    .execute(&state.db)
+    .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Status updated").into_response(),
+        Err(e) => db_error(&e, "Failed to update status"),
+    }
+}
+
+/// PUT /api/devices/:id/owner
+/// Hands a device to another user, or makes it shared
+#[utoipa::path(
+    put,
+    path = "/api/devices/{id}/owner",
+    params(
+        ("id" = i64, Path, description = "Device ID")
+    ),
+    request_body = UpdateDeviceOwnerRequest,
+    tag = "devices",
+    responses(
+        (status = 200, description = "Owner updated"),
+        (status = 404, description = "Device or user not found")
+    )
+)]
+pub async fn update_device_owner(
+    admin: AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<UpdateDeviceOwnerRequest>,
+) -> impl IntoResponse {
+    if let Some(owner_id) = payload.owner_id {
+        match sqlx::query_scalar!("SELECT id FROM users WHERE id = ?", owner_id)
+            .fetch_optional(&state.db)
+            .await
+        {
+            Ok(Some(_)) => {}
+            Ok(None) => return (StatusCode::NOT_FOUND, "User not found").into_response(),
+            Err(e) => return db_error(&e, "Database error"),
+        }
+    }
+
+    let previous = match sqlx::query_scalar!("SELECT owner_id FROM devices WHERE id = ?", id)
+        .fetch_optional(&state.db)
+        .await
+    {
+        Ok(Some(owner)) => owner,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
+    };
+
+    let result = sqlx::query!("UPDATE devices SET owner_id = ? WHERE id = ?", payload.owner_id, id)
+        .execute(&state.db)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Ok(_) => {
+            let owner = |o: Option<i64>| o.map_or("shared".to_string(), |user_id| user_id.to_string());
+            let details = format!("from={} to={}", owner(previous), owner(payload.owner_id));
+            audit::record(&state.db, Some(admin.0.id), audit::ACTION_OWNER_CHANGED, payload.owner_id, Some(id), Some(&details)).await;
+            (StatusCode::OK, "Owner updated").into_response()
+        }
+        Err(e) => db_error(&e, "Failed to update owner"),
+    }
+}
+
+/// PUT /api/devices/:id/group
+/// Moves a device to another group, appended to its wake order, or out of its group.
+/// Staying in the same group keeps its position and delay.
+#[utoipa::path(
+    put,
+    path = "/api/devices/{id}/group",
+    params(
+        ("id" = i64, Path, description = "Device ID")
+    ),
+    request_body = UpdateDeviceGroupRequest,
+    tag = "devices",
+    responses(
+        (status = 200, description = "Group updated", body = DeviceResponse),
+        (status = 404, description = "Device or group not found")
+    )
+)]
+pub async fn update_device_group(
+    admin: AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<UpdateDeviceGroupRequest>,
+) -> impl IntoResponse {
+    if let Some(group_id) = payload.group_id {
+        match sqlx::query_scalar!("SELECT id FROM groups WHERE id = ?", group_id)
+            .fetch_optional(&state.db)
+            .await
+        {
+            Ok(Some(_)) => {}
+            Ok(None) => return (StatusCode::NOT_FOUND, "Group not found").into_response(),
+            Err(e) => return db_error(&e, "Database error"),
+        }
+    }
+
+    // SET expressions all see the row as it was before the update
+    let result = sqlx::query_as!(
+        DeviceRow,
+        r#"
+            UPDATE devices
+            SET wake_order = CASE WHEN group_id IS ? THEN wake_order
+                    ELSE COALESCE((SELECT MAX(wake_order) + 1 FROM devices WHERE group_id = ?), 0) END,
+                wake_delay_secs = CASE WHEN group_id IS ? THEN wake_delay_secs ELSE 0 END,
+                group_id = ?
+            WHERE id = ?
+            RETURNING id as "id!", name as "name!", mac_address as "mac_address!", ip_address as "ip_address?",
+                broadcast_addr as "broadcast_addr?", icon as "icon?", is_online as "is_online?",
+                last_seen_at as "last_seen_at?", went_online_at, liveness_probe as "liveness_probe!", ping_timeout_ms,
+                last_action_error, last_action_error_at, enabled as "enabled!: bool", monitoring_enabled as "monitoring_enabled!: bool", wol_ports, version, owner_id, group_id, last_rtt_ms,
+                agent_os, agent_hostname, agent_version, agent_base_path
+        "#,
+        payload.group_id,
+        payload.group_id,
+        payload.group_id,
+        payload.group_id,
+        id
+    )
+    .fetch_optional(&state.db)
+    .await;
+
+    match result {
+        Ok(Some(dev)) => {
+            let tags = match fetch_device_tags(&state, dev.id).await {
+                Ok(t) => t,
+                Err(e) => return db_error(&e, "Failed to update group"),
+            };
+            let favorite = match is_favorite(&state, admin.0.id, dev.id).await {
+                Ok(f) => f,
+                Err(e) => return db_error(&e, "Failed to update group"),
+            };
+            Json(dev.into_response(tags, favorite, &admin.0)).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => db_error(&e, "Failed to update group"),
+    }
+}
+
+/// GET /api/devices/:id/history
+/// Online/offline transitions, oldest first; only the most recent ones are kept
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/history",
+    params(
+        ("id" = i64, Path, description = "Device ID"),
+        HistoryQuery
+    ),
+    tag = "devices",
+    responses(
+        (status = 200, description = "Transitions in the window", body = Vec<StatusTransition>),
+        (status = 400, description = "from is after to"),
+        (status = 404, description = "Device not found")
+    )
+)]
+pub async fn device_history(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(query): Query<HistoryQuery>,
+) -> impl IntoResponse {
+    if let (Some(from), Some(to)) = (query.from, query.to)
+        && from > to
+    {
+        return (StatusCode::BAD_REQUEST, "from must not be after to").into_response();
+    }
+
+    if let Err(response) = authorize_device(&state, &auth, id).await {
+        return response;
+    }
+
+    let from = query.from.map(|t| t.naive_utc());
+    let to = query.to.map(|t| t.naive_utc());
+    let result = sqlx::query_as!(
+        StatusTransition,
+        r#"SELECT state, changed_at FROM status_history
+           WHERE device_id = ?
+             AND (? IS NULL OR changed_at >= datetime(?))
+             AND (? IS NULL OR changed_at <= datetime(?))
+           ORDER BY changed_at, id"#,
+        id,
+        from,
+        from,
+        to,
+        to
+    )
+    .fetch_all(&state.db)
+    .await;
+
+    match result {
+        Ok(history) => Json(history).into_response(),
+        Err(e) => db_error(&e, "Failed to fetch history"),
+    }
+}
+
+/// POST /api/devices/:id/schedules
+/// Schedules a wake; currently only one-shot (`"type": "once"`) schedules exist
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/schedules",
+    params(
+        ("id" = i64, Path, description = "Device ID")
+    ),
+    request_body = CreateScheduleRequest,
+    tag = "devices",
+    responses(
+        (status = 201, description = "Schedule created", body = ScheduleResponse),
+        (status = 404, description = "Device not found"),
+        (status = 422, description = "fire_at is not in the future")
+    )
+)]
+pub async fn create_schedule(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<CreateScheduleRequest>,
+) -> impl IntoResponse {
+    let CreateScheduleRequest::Once { fire_at } = payload;
+    if fire_at <= chrono::Utc::now() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "fire_at must be in the future").into_response();
+    }
+
+    if let Err(response) = authorize_device(&state, &auth, id).await {
+        return response;
+    }
+
+    let fire_at = fire_at.naive_utc();
+    let result = sqlx::query_as!(
+        ScheduleResponse,
+        r#"INSERT INTO schedules (device_id, kind, fire_at, created_by)
+           VALUES (?, 'once', datetime(?), ?)
+           RETURNING id as "id!", device_id, kind, fire_at, created_at"#,
+        id,
+        fire_at,
+        auth.id
+    )
+    .fetch_one(&state.db)
+    .await;
+
+    match result {
+        Ok(schedule) => (StatusCode::CREATED, Json(schedule)).into_response(),
+        Err(e) => db_error(&e, "Failed to create schedule"),
+    }
 }
 
-#[derive(Serialize, ToSchema)]
-pub struct DeviceResponse {
-    pub id: i64,
-    pub name: String,
-    pub mac_address: String,
-    pub ip_address: Option<String>,
-    pub broadcast_addr: Option<String>,
-    pub icon: Option<String>,
-    pub is_online: bool,
-    pub last_seen_at: Option<chrono::NaiveDateTime>,
-}
+/// DELETE /api/devices/:id/schedules/:schedule_id
+/// Cancels a pending schedule
+#[utoipa::path(
+    delete,
+    path = "/api/devices/{id}/schedules/{schedule_id}",
+    params(
+        ("id" = i64, Path, description = "Device ID"),
+        ("schedule_id" = i64, Path, description = "Schedule ID")
+    ),
+    tag = "devices",
+    responses(
+        (status = 200, description = "Schedule cancelled"),
+        (status = 404, description = "Device or schedule not found")
+    )
+)]
+pub async fn delete_schedule(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path((id, schedule_id)): Path<(i64, i64)>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize_device(&state, &auth, id).await {
+        return response;
+    }
 
-// ==========================================
-// 2. HANDLERS
-// ==========================================
+    let result = sqlx::query!(
+        "DELETE FROM schedules WHERE id = ? AND device_id = ?",
+        schedule_id,
+        id
+    )
+    .execute(&state.db)
+    .await;
 
-/// GET /api/devices
+    match result {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Schedule not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Schedule cancelled").into_response(),
+        Err(e) => db_error(&e, "Failed to cancel schedule"),
+    }
+}
+
+/// POST /api/devices/:id/wake
 #[utoipa::path(
-    get,
-    path = "/api/devices",
+    post,
+    path = "/api/devices/{id}/wake",
+    params(
+        ("id" = i64, Path, description = "Device ID"),
+        WakeQuery
+    ),
     tag = "devices",
     responses(
-        (status = 200, description = "List all devices", body = [DeviceResponse])
+        (status = 200, description = "Wake signal sent, or device online when verifying. \
+            JSON with a warning when the device has neither a broadcast nor an IP address", body = WakeWarningResponse),
+        (status = 400, description = "Device has no IP address to verify against, or an invalid stored MAC or broadcast address"),
+        (status = 404, description = "Device not found"),
+        (status = 500, description = "Failed to send packet"),
+        (status = 503, description = "Maintenance mode"),
+        (status = 504, description = "Device did not come online in time", body = WakeTimeoutResponse)
     )
 )]
-pub async fn list_devices(
-    _auth: AuthUser,
-    State(state): State<AppState>
+pub async fn wake_device(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(query): Query<WakeQuery>,
 ) -> impl IntoResponse {
-    let devices = sqlx::query!(
-        r#"SELECT 
-            id, name, mac_address, ip_address, broadcast_addr, 
-            icon, is_online, last_seen_at 
-           FROM devices"#
+    if state.in_maintenance() {
+        return (StatusCode::SERVICE_UNAVAILABLE, MAINTENANCE_MODE).into_response();
+    }
+
+    if let Err(response) = authorize_device(&state, &auth, id).await {
+        return response;
+    }
+
+    // 1. Get device details
+    let device = sqlx::query!(
+        "SELECT mac_address, broadcast_addr, ip_address, liveness_probe, ping_timeout_ms, enabled, wol_ports FROM devices WHERE id = ?",
+        id
     )
-    .fetch_all(&state.db)
+    .fetch_optional(&state.db)
     .await;
 
-    match devices {
-        Ok(rows) => {
-            let res: Vec<DeviceResponse> = rows.into_iter().map(|row| DeviceResponse {
-                id: row.id,
-                name: row.name,
-                mac_address: row.mac_address,
-                ip_address: row.ip_address,
-                broadcast_addr: row.broadcast_addr,
-                icon: row.icon,
-                is_online: row.is_online.unwrap_or(false),
-                last_seen_at: row.last_seen_at,
-            }).collect();
-            Json(res).into_response()
-        },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch devices").into_response(),
+    let device = match device {
+        Ok(Some(d)) => d,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
+    };
+    if !device.enabled {
+        return (StatusCode::CONFLICT, "Device disabled").into_response();
+    }
+
+    // 2. Parse MAC address
+    let mac_array = match wol::parse_mac(&device.mac_address, state.config.lenient_mac_parsing) {
+        Some(mac) => mac,
+        None => return action_failed(&state, id, StatusCode::BAD_REQUEST, "Invalid MAC address format in DB".to_string()).await,
+    };
+
+    // 3. Send Packet
+    let Some(target) = wol::broadcast_target(device.broadcast_addr.as_deref()) else {
+        return action_failed(&state, id, StatusCode::BAD_REQUEST, "Invalid broadcast address in DB".to_string()).await;
+    };
+    let verify_ip = if query.verify.unwrap_or(false) {
+        match device.ip_address.as_deref().and_then(|ip| ip.parse().ok()) {
+            Some(ip) => Some(ip),
+            None => return (StatusCode::BAD_REQUEST, "Device has no IP address").into_response(),
+        }
+    } else {
+        None
+    };
+
+    let ports = effective_wol_ports(&state, device.wol_ports.as_deref());
+    if let Err(e) = state.wake_sender.send(&mac_array, target, &ports, state.config.wol_source_port).await {
+        return action_failed(&state, id, StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to send WoL: {}", e)).await;
+    }
+    audit::record(&state.db, Some(auth.id), audit::ACTION_DEVICE_WAKE, None, Some(id), None).await;
+
+    let Some(ip) = verify_ip else {
+        record_action_result(&state, id, None).await;
+        // Without either, the packet only reaches devices on the server's own subnet
+        if target == wol::GLOBAL_BROADCAST && device.ip_address.is_none() {
+            return Json(WakeWarningResponse {
+                status: "sent".to_string(),
+                warning: "No broadcast or IP configured; sent to global broadcast".to_string(),
+            })
+            .into_response();
+        }
+        return (StatusCode::OK, "Wake signal sent").into_response();
+    };
+
+    // 4. Wait for the device to answer its liveness probe
+    let probe = device.liveness_probe.parse().unwrap_or_default();
+    let timeout = state.config.wake_verify_timeout();
+    let probe_timeout = pinger::probe_timeout(&state.config, device.ping_timeout_ms);
+    let report = pinger::probe_until_alive(state.prober.as_ref(), ip, probe, probe_timeout, timeout, state.config.wake_verify_interval()).await;
+    if report.rtt.is_some() {
+        pinger::record_liveness(&state, id, report.rtt).await;
+        record_action_result(&state, id, None).await;
+        (StatusCode::OK, "Device is online").into_response()
+    } else {
+        record_action_result(&state, id, Some("Device did not come online")).await;
+        let resp = WakeTimeoutResponse {
+            error: "Device did not come online".to_string(),
+            timeout_secs: timeout.as_secs(),
+        };
+        (StatusCode::GATEWAY_TIMEOUT, Json(resp)).into_response()
     }
 }
 
-/// POST /api/devices
+/// POST /api/devices/:id/test-wake
+/// Wakes the device and waits for it to answer its liveness probe, reporting each step.
+/// Meant for checking a device's setup end to end; the outcome is in the body, not the status.
 #[utoipa::path(
     post,
-    path = "/api/devices",
-    request_body = CreateDeviceRequest,
+    path = "/api/devices/{id}/test-wake",
+    params(
+        ("id" = i64, Path, description = "Device ID")
+    ),
     tag = "devices",
     responses(
-        (status = 201, description = "Device created", body = DeviceResponse),
-        (status = 500, description = "Server error")
+        (status = 200, description = "Test finished, successfully or not", body = TestWakeResponse),
+        (status = 400, description = "Device has no IP address to verify against, or an invalid stored MAC or broadcast address"),
+        (status = 404, description = "Device not found"),
+        (status = 409, description = "Device disabled"),
+        (status = 503, description = "Maintenance mode")
     )
 )]
-pub async fn create_device(
-    _admin: AdminUser,
+pub async fn test_wake(
+    auth: AuthUser,
     State(state): State<AppState>,
-    Json(payload): Json<CreateDeviceRequest>,
+    Path(id): Path<i64>,
 ) -> impl IntoResponse {
-    let broadcast_addr = payload.broadcast_addr.unwrap_or_else(|| "255.255.255.255".to_string());
-    
-    let result = sqlx::query!(
-        r#"
-            INSERT INTO devices (name, mac_address, ip_address, broadcast_addr, icon)
-            VALUES (?, ?, ?, ?, ?)
-            RETURNING id as "id!", name, mac_address, ip_address, broadcast_addr, icon, is_online, last_seen_at
-        "#,
-        payload.name,
-        payload.mac_address,
-        payload.ip_address,
-        broadcast_addr,
-        payload.icon
+    if state.in_maintenance() {
+        return (StatusCode::SERVICE_UNAVAILABLE, MAINTENANCE_MODE).into_response();
+    }
+
+    if let Err(response) = authorize_device(&state, &auth, id).await {
+        return response;
+    }
+
+    let device = sqlx::query!(
+        "SELECT mac_address, broadcast_addr, ip_address, liveness_probe, ping_timeout_ms, enabled, wol_ports FROM devices WHERE id = ?",
+        id
     )
-    .fetch_one(&state.db)
+    .fetch_optional(&state.db)
     .await;
 
-    match result {
-        Ok(dev) => {
-            let resp = DeviceResponse {
-                id: dev.id,
-                name: dev.name,
-                mac_address: dev.mac_address,
-                ip_address: dev.ip_address,
-                broadcast_addr: dev.broadcast_addr,
-                icon: dev.icon,
-                is_online: dev.is_online,
-                last_seen_at: dev.last_seen_at,
-            };
-            (StatusCode::CREATED, Json(resp)).into_response()
-        }
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create device").into_response(),
+    let device = match device {
+        Ok(Some(d)) => d,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
+    };
+    if !device.enabled {
+        return (StatusCode::CONFLICT, "Device disabled").into_response();
+    }
+    let Some(mac_array) = wol::parse_mac(&device.mac_address, state.config.lenient_mac_parsing) else {
+        return (StatusCode::BAD_REQUEST, "Invalid MAC address format in DB").into_response();
+    };
+    let Some(target) = wol::broadcast_target(device.broadcast_addr.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid broadcast address in DB").into_response();
+    };
+    let Some(ip) = device.ip_address.as_deref().and_then(|ip| ip.parse().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Device has no IP address").into_response();
+    };
+
+    let ports = effective_wol_ports(&state, device.wol_ports.as_deref());
+    if let Err(e) = state.wake_sender.send(&mac_array, target, &ports, state.config.wol_source_port).await {
+        let error = format!("Failed to send WoL: {}", e);
+        record_action_result(&state, id, Some(&error)).await;
+        return Json(TestWakeResponse {
+            packet_sent: false,
+            ping_attempts: 0,
+            came_online: false,
+            rtt_ms: None,
+            error: Some(error),
+        })
+        .into_response();
     }
+
+    let probe = device.liveness_probe.parse().unwrap_or_default();
+    let probe_timeout = pinger::probe_timeout(&state.config, device.ping_timeout_ms);
+    let report = pinger::probe_until_alive(
+        state.prober.as_ref(),
+        ip,
+        probe,
+        probe_timeout,
+        state.config.wake_verify_timeout(),
+        state.config.wake_verify_interval(),
+    )
+    .await;
+
+    let error = if report.rtt.is_some() {
+        pinger::record_liveness(&state, id, report.rtt).await;
+        None
+    } else {
+        Some("Device did not come online".to_string())
+    };
+    record_action_result(&state, id, error.as_deref()).await;
+
+    Json(TestWakeResponse {
+        packet_sent: true,
+        ping_attempts: report.attempts,
+        came_online: report.rtt.is_some(),
+        rtt_ms: report.rtt.map(|rtt| rtt.as_millis() as u64),
+        error,
+    })
+    .into_response()
 }
 
-/// PUT /api/devices/:id
+/// POST /api/devices/:id/wake-and-ready
+/// Wakes the device, waits for its liveness probe, then for a service port (SSH by default)
+/// to accept connections, all within one timeout. The outcome is in the body, not the status.
 #[utoipa::path(
-    put,
-    path = "/api/devices/{id}",
+    post,
+    path = "/api/devices/{id}/wake-and-ready",
     params(
         ("id" = i64, Path, description = "Device ID")
     ),
-    request_body = UpdateDeviceRequest,
+    request_body(content = Option<WakeAndReadyRequest>, description = "Optional service port and timeout"),
     tag = "devices",
     responses(
-        (status = 200, description = "Device updated", body = DeviceResponse),
+        (status = 200, description = "Finished, successfully or not", body = WakeAndReadyResponse),
+        (status = 400, description = "Invalid port or timeout, no IP address, or an invalid stored MAC or broadcast address"),
         (status = 404, description = "Device not found"),
-        (status = 500, description = "Server error")
+        (status = 409, description = "Device disabled"),
+        (status = 503, description = "Maintenance mode")
     )
 )]
-pub async fn update_device(
-    _admin: AdminUser,
+pub async fn wake_and_ready(
+    auth: AuthUser,
     State(state): State<AppState>,
     Path(id): Path<i64>,
-    Json(payload): Json<UpdateDeviceRequest>,
+    payload: Option<Json<WakeAndReadyRequest>>,
 ) -> impl IntoResponse {
-    let result = sqlx::query!(
-        r#"
-            UPDATE devices 
-            SET 
-                name = COALESCE(?, name),
-                mac_address = COALESCE(?, mac_address),
-                ip_address = COALESCE(?, ip_address),
-                broadcast_addr = COALESCE(?, broadcast_addr),
-                icon = COALESCE(?, icon)
-            WHERE id = ?
-            RETURNING id as "id!", name, mac_address, ip_address, broadcast_addr, icon, is_online, last_seen_at
-        "#,
-        payload.name,
-        payload.mac_address,
-        payload.ip_address,
-        payload.broadcast_addr,
-        payload.icon,
+    if state.in_maintenance() {
+        return (StatusCode::SERVICE_UNAVAILABLE, MAINTENANCE_MODE).into_response();
+    }
+
+    let payload = payload.map(|Json(p)| p).unwrap_or_default();
+    let service_port = payload.service_port.unwrap_or(DEFAULT_SERVICE_PORT);
+    if service_port == 0 {
+        return (StatusCode::BAD_REQUEST, "service_port must not be 0").into_response();
+    }
+    let timeout_secs = payload.timeout_secs.unwrap_or(DEFAULT_READY_TIMEOUT_SECS);
+    if !(1..=MAX_READY_TIMEOUT_SECS).contains(&timeout_secs) {
+        return (StatusCode::BAD_REQUEST, format!("timeout_secs must be between 1 and {}", MAX_READY_TIMEOUT_SECS)).into_response();
+    }
+
+    if let Err(response) = authorize_device(&state, &auth, id).await {
+        return response;
+    }
+
+    let device = sqlx::query!(
+        "SELECT mac_address, broadcast_addr, ip_address, liveness_probe, ping_timeout_ms, enabled, wol_ports FROM devices WHERE id = ?",
         id
     )
     .fetch_optional(&state.db)
     .await;
 
-    match result {
-        Ok(Some(dev)) => {
-            let resp = DeviceResponse {
-                id: dev.id,
-                name: dev.name,
-                mac_address: dev.mac_address,
-                ip_address: dev.ip_address,
-                broadcast_addr: dev.broadcast_addr,
-                icon: dev.icon,
-                is_online: dev.is_online.unwrap_or(false),
-                last_seen_at: dev.last_seen_at,
-            };
-            (StatusCode::OK, Json(resp)).into_response()
-        },
-        Ok(None) => (StatusCode::NOT_FOUND, "Device not found").into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update device").into_response(),
+    let device = match device {
+        Ok(Some(d)) => d,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
+    };
+    if !device.enabled {
+        return (StatusCode::CONFLICT, "Device disabled").into_response();
+    }
+    let Some(mac_array) = wol::parse_mac(&device.mac_address, state.config.lenient_mac_parsing) else {
+        return (StatusCode::BAD_REQUEST, "Invalid MAC address format in DB").into_response();
+    };
+    let Some(target) = wol::broadcast_target(device.broadcast_addr.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid broadcast address in DB").into_response();
+    };
+    let Some(ip) = device.ip_address.as_deref().and_then(|ip| ip.parse().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Device has no IP address").into_response();
+    };
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(timeout_secs);
+    let mut stages = Vec::new();
+    let finish = |stages: Vec<ReadyStageResult>| {
+        Json(WakeAndReadyResponse {
+            ready: stages.iter().all(|s| s.ok),
+            service_port,
+            stages,
+        })
+        .into_response()
+    };
+
+    // 1. Wake
+    let started = tokio::time::Instant::now();
+    let ports = effective_wol_ports(&state, device.wol_ports.as_deref());
+    let sent = state.wake_sender.send(&mac_array, target, &ports, state.config.wol_source_port).await;
+    let error = sent.err().map(|e| format!("Failed to send WoL: {}", e));
+    stages.push(ReadyStageResult {
+        stage: ReadyStage::Wake,
+        ok: error.is_none(),
+        attempts: 1,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+        error: error.clone(),
+    });
+    if let Some(error) = error {
+        record_action_result(&state, id, Some(&error)).await;
+        return finish(stages);
+    }
+    audit::record(&state.db, Some(auth.id), audit::ACTION_DEVICE_WAKE, None, Some(id), None).await;
+
+    // 2. Online, then 3. service port, each with whatever time is left
+    let probe = device.liveness_probe.parse().unwrap_or_default();
+    let probe_timeout = pinger::probe_timeout(&state.config, device.ping_timeout_ms);
+    for (stage, probe) in [(ReadyStage::Online, probe), (ReadyStage::ServiceReady, LivenessProbe::Tcp(service_port))] {
+        let started = tokio::time::Instant::now();
+        let remaining = deadline.saturating_duration_since(started);
+        let report = pinger::probe_until_alive(state.prober.as_ref(), ip, probe, probe_timeout, remaining, state.config.wake_verify_interval()).await;
+        if stage == ReadyStage::Online && report.rtt.is_some() {
+            pinger::record_liveness(&state, id, report.rtt).await;
+        }
+        let error = match (report.rtt, stage) {
+            (Some(_), _) => None,
+            (None, ReadyStage::Online) => Some("Device did not come online".to_string()),
+            (None, _) => Some(format!("Port {} did not open", service_port)),
+        };
+        stages.push(ReadyStageResult {
+            stage,
+            ok: error.is_none(),
+            attempts: report.attempts,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            error: error.clone(),
+        });
+        if error.is_some() {
+            record_action_result(&state, id, error.as_deref()).await;
+            return finish(stages);
+        }
     }
+
+    record_action_result(&state, id, None).await;
+    finish(stages)
 }
 
-/// DELETE /api/devices/:id
+/// GET /api/devices/:id/wake-preflight
+/// Checks everything a wake depends on and lists the problems, without sending a packet
 #[utoipa::path(
-    delete,
-    path = "/api/devices/{id}",
+    get,
+    path = "/api/devices/{id}/wake-preflight",
     params(
         ("id" = i64, Path, description = "Device ID")
     ),
     tag = "devices",
     responses(
-        (status = 200, description = "Device deleted"),
+        (status = 200, description = "Check results", body = WakePreflightResponse),
         (status = 404, description = "Device not found")
     )
 )]
-pub async fn delete_device(
-    _admin: AdminUser,
+pub async fn wake_preflight(
+    auth: AuthUser,
     State(state): State<AppState>,
     Path(id): Path<i64>,
 ) -> impl IntoResponse {
-    let result = sqlx::query!("DELETE FROM devices WHERE id = ?", id)
-        .execute(&state.db)
-        .await;
+    if let Err(response) = authorize_device(&state, &auth, id).await {
+        return response;
+    }
+
+    let device = sqlx::query!(
+        "SELECT mac_address, broadcast_addr, ip_address, enabled, wol_ports FROM devices WHERE id = ?",
+        id
+    )
+    .fetch_optional(&state.db)
+    .await;
+
+    let device = match device {
+        Ok(Some(d)) => d,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
+    };
+
+    let issues = wake_preflight_issues(
+        device.enabled,
+        &device.mac_address,
+        state.config.lenient_mac_parsing,
+        device.ip_address.as_deref(),
+        device.broadcast_addr.as_deref(),
+        device.wol_ports.as_deref(),
+    );
+    Json(WakePreflightResponse {
+        ok: !issues.iter().any(|i| i.severity == PreflightSeverity::Error),
+        target: wol::broadcast_target(device.broadcast_addr.as_deref()).map(str::to_string),
+        ports: effective_wol_ports(&state, device.wol_ports.as_deref()),
+        issues,
+    })
+    .into_response()
+}
+
+/// POST /api/wake
+/// Sends a magic packet to an arbitrary MAC without touching the devices table
+#[utoipa::path(
+    post,
+    path = "/api/wake",
+    request_body = AdHocWakeRequest,
+    tag = "devices",
+    responses(
+        (status = 200, description = "Wake signal sent"),
+        (status = 422, description = "Invalid MAC, broadcast address or port"),
+        (status = 500, description = "Failed to send packet"),
+        (status = 503, description = "Maintenance mode")
+    )
+)]
+pub async fn wake_mac(
+    admin: AdminUser,
+    State(state): State<AppState>,
+    Json(payload): Json<AdHocWakeRequest>,
+) -> impl IntoResponse {
+    if state.in_maintenance() {
+        return (StatusCode::SERVICE_UNAVAILABLE, MAINTENANCE_MODE).into_response();
+    }
+
+    let Some(mac) = wol::parse_mac(&payload.mac, state.config.lenient_mac_parsing) else {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "Invalid MAC address").into_response();
+    };
+    let Some(target) = wol::broadcast_target(payload.broadcast.as_deref()) else {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "Invalid broadcast address").into_response();
+    };
+    let port = payload.port.unwrap_or(state.config.wol_default_port);
+    if port == 0 {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "Invalid port").into_response();
+    }
+
+    if let Err(e) = state.wake_sender.send(&mac, target, &[port], state.config.wol_source_port).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to send WoL: {}", e)).into_response();
+    }
+
+    let details = format!("mac={} target={}:{}", payload.mac, target, port);
+    audit::record(&state.db, Some(admin.0.id), audit::ACTION_ADHOC_WAKE, None, None, Some(&details)).await;
+
+    (StatusCode::OK, "Wake signal sent").into_response()
+}
+
+/// Message for agent calls refused by the circuit breaker
+const AGENT_UNAVAILABLE: &str = "Agent temporarily unavailable";
+const AGENT_CONTROL_DISABLED: &str = "Agent control disabled";
+/// Message for wakes and shutdowns refused while maintenance mode is on
+pub const MAINTENANCE_MODE: &str = "Maintenance mode";
+
+/// POST /api/devices/:id/shutdown
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/shutdown",
+    params(
+        ("id" = i64, Path, description = "Device ID")
+    ),
+    tag = "devices",
+    responses(
+        (status = 200, description = "Shutdown signal sent"),
+        (status = 404, description = "Device not found"),
+        (status = 501, description = "Agent control disabled (`ENABLE_AGENT_CONTROL`)"),
+        (status = 502, description = "Failed to contact agent"),
+        (status = 503, description = "Agent temporarily unavailable after repeated failures, or maintenance mode"),
+        (status = 504, description = "Agent timed out")
+    )
+)]
+pub async fn shutdown_device(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if state.in_maintenance() {
+        return (StatusCode::SERVICE_UNAVAILABLE, MAINTENANCE_MODE).into_response();
+    }
+    if !state.config.agent_control_enabled {
+        return (StatusCode::NOT_IMPLEMENTED, AGENT_CONTROL_DISABLED).into_response();
+    }
+
+    if let Err(response) = authorize_device(&state, &auth, id).await {
+        return response;
+    }
+
+    // 1. Get device details
+    let device = sqlx::query!(
+        "SELECT ip_address, enabled, agent_base_path FROM devices WHERE id = ?",
+        id
+    )
+    .fetch_optional(&state.db)
+    .await;
 
-    match result {
-        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Device not found").into_response(),
-        Ok(_) => (StatusCode::OK, "Device deleted").into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete device").into_response(),
+    let device = match device {
+        Ok(Some(d)) => d,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
+    };
+    if !device.enabled {
+        return (StatusCode::CONFLICT, "Device disabled").into_response();
+    }
+
+    let ip = match device.ip_address {
+        Some(ip) => ip,
+        None => return action_failed(&state, id, StatusCode::BAD_REQUEST, "Device has no IP address".to_string()).await,
+    };
+
+    // 2. Call the agent
+    let request = agent::action_request(&state.config, Method::POST, &ip, device.agent_base_path.as_deref(), "/shutdown", &auth);
+    let Some(res) = state.agent_breaker.send(id, request).await else {
+        return action_failed(&state, id, StatusCode::SERVICE_UNAVAILABLE, AGENT_UNAVAILABLE.to_string()).await;
+    };
+
+    let (status, message) = match res {
+        Ok(r) if r.status().is_success() => {
+            store_agent_info(&state, id, &r.json::<AgentInfo>().await.unwrap_or_default()).await;
+            (StatusCode::OK, "Shutdown signal sent")
+        }
+        Ok(_) => (StatusCode::BAD_GATEWAY, "Agent returned error"),
+        Err(e) if e.is_timeout() => (StatusCode::GATEWAY_TIMEOUT, "Agent timed out"),
+        Err(_) => (StatusCode::BAD_GATEWAY, "Failed to contact agent"),
+    };
+
+    if status.is_success() {
+        record_action_result(&state, id, None).await;
+        (status, message).into_response()
+    } else {
+        action_failed(&state, id, status, message.to_string()).await
     }
 }
 
-/// POST /api/devices/:id/wake
+/// POST /api/devices/:id/ping
+/// Probes the device right away instead of waiting for the next sweep and stores the result
 #[utoipa::path(
     post,
-    path = "/api/devices/{id}/wake",
+    path = "/api/devices/{id}/ping",
     params(
         ("id" = i64, Path, description = "Device ID")
     ),
     tag = "devices",
     responses(
-        (status = 200, description = "Wake signal sent"),
+        (status = 200, description = "Fresh status", body = PingResponse),
+        (status = 400, description = "Device has no IP address"),
         (status = 404, description = "Device not found"),
-        (status = 500, description = "Failed to send packet")
+        (status = 409, description = "Device disabled"),
+        (status = 503, description = "ICMP probes are unavailable (see `PING_MODE`)")
     )
 )]
-pub async fn wake_device(
-    _auth: AuthUser,
+pub async fn ping_device(
+    auth: AuthUser,
     State(state): State<AppState>,
     Path(id): Path<i64>,
 ) -> impl IntoResponse {
-    // 1. Get device details
+    if let Err(response) = authorize_device(&state, &auth, id).await {
+        return response;
+    }
+
     let device = sqlx::query!(
-        "SELECT mac_address, broadcast_addr FROM devices WHERE id = ?",
+        "SELECT ip_address, liveness_probe, ping_timeout_ms, enabled FROM devices WHERE id = ?",
         id
     )
     .fetch_optional(&state.db)
@@ -260,60 +2616,72 @@ pub async fn wake_device(
     let device = match device {
         Ok(Some(d)) => d,
         Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
-        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
     };
-
-    // 2. Parse MAC address
-    let mac_bytes: Vec<u8> = device.mac_address
-        .split(|c| c == ':' || c == '-')
-        .filter_map(|s| u8::from_str_radix(s, 16).ok())
-        .collect();
-
-    if mac_bytes.len() != 6 {
-         return (StatusCode::BAD_REQUEST, "Invalid MAC address format in DB").into_response();
+    if !device.enabled {
+        return (StatusCode::CONFLICT, "Device disabled").into_response();
+    }
+    let Some(ip) = device.ip_address.as_deref().and_then(|ip| ip.parse().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Device has no IP address").into_response();
+    };
+    let probe = device.liveness_probe.parse().unwrap_or_default();
+    if probe == pinger::LivenessProbe::Icmp && !pinger::icmp_available() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "ICMP probes are unavailable").into_response();
     }
 
-    let mut mac_array = [0u8; 6];
-    mac_array.copy_from_slice(&mac_bytes);
+    let rtt = pinger::probe_status(state.prober.as_ref(), &state.config, ip, probe, pinger::probe_timeout(&state.config, device.ping_timeout_ms)).await;
+    pinger::record_liveness(&state, id, rtt).await;
 
-    let magic_packet = MagicPacket::new(&mac_array);
-    
-    // 3. Send Packet
-    let res = if let Some(b_addr) = device.broadcast_addr {
-         // Try to send to specific broadcast address + port 9
-         magic_packet.send_to((b_addr.as_str(), 9), ("0.0.0.0", 0))
-    } else {
-         magic_packet.send()
+    let last_seen_at = match sqlx::query_scalar!("SELECT last_seen_at FROM devices WHERE id = ?", id)
+        .fetch_one(&state.db)
+        .await
+    {
+        Ok(t) => t,
+        Err(e) => return db_error(&e, "Database error"),
     };
 
-    match res {
-        Ok(_) => (StatusCode::OK, "Wake signal sent").into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to send WoL: {}", e)).into_response(),
-    }
+    Json(PingResponse {
+        status: if rtt.is_some() { DeviceStatus::Online } else { DeviceStatus::Offline },
+        last_seen_at,
+        rtt_ms: rtt.map(|rtt| rtt.as_millis() as u64),
+    })
+    .into_response()
 }
 
-/// POST /api/devices/:id/shutdown
+/// POST /api/devices/:id/agent/ping
+/// Checks that the device's agent is reachable and accepts our secret
 #[utoipa::path(
     post,
-    path = "/api/devices/{id}/shutdown",
+    path = "/api/devices/{id}/agent/ping",
     params(
         ("id" = i64, Path, description = "Device ID")
     ),
     tag = "devices",
     responses(
-        (status = 200, description = "Shutdown signal sent"),
+        (status = 200, description = "Agent answered", body = AgentStatusResponse),
+        (status = 400, description = "Device has no IP address"),
         (status = 404, description = "Device not found"),
-        (status = 502, description = "Failed to contact agent")
+        (status = 501, description = "Agent control disabled (`ENABLE_AGENT_CONTROL`)"),
+        (status = 502, description = "Failed to contact agent"),
+        (status = 503, description = "Agent temporarily unavailable after repeated failures"),
+        (status = 504, description = "Agent timed out")
     )
 )]
-pub async fn shutdown_device(
-    _auth: AuthUser,
+pub async fn ping_agent(
+    auth: AuthUser,
     State(state): State<AppState>,
     Path(id): Path<i64>,
 ) -> impl IntoResponse {
-    // 1. Get device details
+    if !state.config.agent_control_enabled {
+        return (StatusCode::NOT_IMPLEMENTED, AGENT_CONTROL_DISABLED).into_response();
+    }
+
+    if let Err(response) = authorize_device(&state, &auth, id).await {
+        return response;
+    }
+
     let device = sqlx::query!(
-        "SELECT ip_address FROM devices WHERE id = ?",
+        "SELECT ip_address, agent_base_path FROM devices WHERE id = ?",
         id
     )
     .fetch_optional(&state.db)
@@ -322,7 +2690,7 @@ pub async fn shutdown_device(
     let device = match device {
         Ok(Some(d)) => d,
         Ok(None) => return (StatusCode::NOT_FOUND, "Device not found").into_response(),
-        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
     };
 
     let ip = match device.ip_address {
@@ -330,30 +2698,37 @@ pub async fn shutdown_device(
         None => return (StatusCode::BAD_REQUEST, "Device has no IP address").into_response(),
     };
 
-    // 2. Call the agent
-    let client = reqwest::Client::new();
-    // Assuming the agent runs on port 3001 and has a /shutdown endpoint
-    // We should probably store the agent port in the DB or config, but hardcoding 3001 for now as per spec
-    let url = format!("http://{}:3001/shutdown", ip);
-    
-    // NOTE: Auth token/secret is not yet implemented in DB.
-    // For now we'll send a dummy token or no token if the agent doesn't enforce it yet.
-    // Spec says: Authorization: Bearer <SHARED_SECRET>
-    // Let's assume a default secret for now or skip if not ready.
-    
-    let res = client.post(&url)
-        // .header("Authorization", "Bearer secret") 
-        .send()
-        .await;
+    let request = agent::request(&state.config, Method::GET, &ip, device.agent_base_path.as_deref(), "/health");
+    let Some(res) = state.agent_breaker.send(id, request).await else {
+        return (StatusCode::SERVICE_UNAVAILABLE, AGENT_UNAVAILABLE).into_response();
+    };
 
     match res {
-        Ok(r) => {
-            if r.status().is_success() {
-                 (StatusCode::OK, "Shutdown signal sent").into_response()
-            } else {
-                 (StatusCode::BAD_GATEWAY, "Agent returned error").into_response()
-            }
+        Ok(r) if r.status() == reqwest::StatusCode::UNAUTHORIZED || r.status() == reqwest::StatusCode::FORBIDDEN => {
+            Json(AgentStatusResponse {
+                reachable: true,
+                authenticated: false,
+                version: None,
+                os: None,
+                hostname: None,
+            })
+            .into_response()
         }
+        Ok(r) if r.status().is_success() => {
+            // The health body is optional; older agents may answer with plain text
+            let info = r.json::<AgentInfo>().await.unwrap_or_default();
+            store_agent_info(&state, id, &info).await;
+            Json(AgentStatusResponse {
+                reachable: true,
+                authenticated: true,
+                version: info.version,
+                os: info.os,
+                hostname: info.hostname,
+            })
+            .into_response()
+        }
+        Ok(_) => (StatusCode::BAD_GATEWAY, "Agent returned error").into_response(),
+        Err(e) if e.is_timeout() => (StatusCode::GATEWAY_TIMEOUT, "Agent timed out").into_response(),
         Err(_) => (StatusCode::BAD_GATEWAY, "Failed to contact agent").into_response(),
     }
 }
@@ -363,17 +2738,64 @@ pub async fn shutdown_device(
 #[openapi(
     paths(
         list_devices,
+        device_events,
+        device_status,
         create_device,
+        clone_device,
         update_device,
         delete_device,
+        add_device_tag,
+        remove_device_tag,
+        add_favorite,
+        remove_favorite,
+        update_device_enabled,
+        update_device_owner,
+        update_device_group,
+        device_history,
+        list_schedules,
+        create_schedule,
+        delete_schedule,
         wake_device,
-        shutdown_device
+        test_wake,
+        wake_preflight,
+        wake_and_ready,
+        ping_device,
+        wake_mac,
+        shutdown_device,
+        ping_agent
     ),
     components(
         schemas(
             CreateDeviceRequest,
             UpdateDeviceRequest,
-            DeviceResponse
+            CloneDeviceRequest,
+            DeviceResponse,
+            DeviceStatus,
+            AddTagRequest,
+            TagMatch,
+            DeviceSort,
+            AgentStatusResponse,
+            DeviceStatusEvent,
+            DeviceStatusRequest,
+            DeviceStatusResponse,
+            WakeWarningResponse,
+            WakeTimeoutResponse,
+            TestWakeResponse,
+            PreflightSeverity,
+            PreflightIssue,
+            WakePreflightResponse,
+            WakeAndReadyRequest,
+            ReadyStage,
+            ReadyStageResult,
+            WakeAndReadyResponse,
+            PingResponse,
+            AdHocWakeRequest,
+            CreateScheduleRequest,
+            ScheduleResponse,
+            UpdateDeviceEnabledRequest,
+            UpdateDeviceOwnerRequest,
+            UpdateDeviceGroupRequest,
+            StatusTransition
         )
     ),
     tags(
@@ -381,3 +2803,1107 @@ pub async fn shutdown_device(
     )
 )]
 pub struct DeviceApi;
+
+#[cfg(test)]
+mod tests {
+    use crate::db::AppState;
+    use crate::pinger::LivenessProbe;
+    use crate::test_support::{app, call, device, recording_state, send, state, state_with, user, RecordingSender, ScriptedProber};
+    use axum::body::{to_bytes, Body};
+    use axum::http::{header, Method, Request, StatusCode};
+    use axum::response::IntoResponse;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn other_users_cannot_use_a_device() {
+        let state = state().await;
+        let (owner_id, owner) = user(&state, "owner", "user").await;
+        let (_, other) = user(&state, "other", "user").await;
+        let id = device(&state, "desktop", Some(owner_id)).await;
+
+        let schedule = json!({ "type": "once", "fire_at": "2099-01-01T07:00:00Z" });
+        let routes = [
+            (Method::POST, format!("/api/devices/{id}/wake"), None),
+            (Method::POST, format!("/api/devices/{id}/test-wake"), None),
+            (Method::POST, format!("/api/devices/{id}/wake-and-ready"), None),
+            (Method::GET, format!("/api/devices/{id}/wake-preflight"), None),
+            (Method::POST, format!("/api/devices/{id}/shutdown"), None),
+            (Method::POST, format!("/api/devices/{id}/ping"), None),
+            (Method::POST, format!("/api/devices/{id}/agent/ping"), None),
+            (Method::GET, format!("/api/devices/{id}/history"), None),
+            (Method::GET, format!("/api/devices/{id}/schedules"), None),
+            (Method::POST, format!("/api/devices/{id}/schedules"), Some(schedule)),
+            (Method::DELETE, format!("/api/devices/{id}/schedules/1"), None),
+            (Method::POST, format!("/api/devices/{id}/favorite"), None),
+            (Method::DELETE, format!("/api/devices/{id}/favorite"), None),
+        ];
+        for (method, uri, body) in routes {
+            let (status, _) = call(&state, method.clone(), &uri, Some(&other), body).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{method} {uri}");
+        }
+
+        let (status, _) = call(&state, Method::GET, &format!("/api/devices/{id}/history"), Some(&owner), None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    /// Ids of the devices `GET /api/devices?{query}` returns
+    async fn listed(state: &AppState, token: &str, query: &str) -> Vec<i64> {
+        let (status, body) = call(state, Method::GET, &format!("/api/devices?{query}"), Some(token), None).await;
+        assert_eq!(status, StatusCode::OK, "{query}");
+        let devices: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        devices.iter().map(|d| d["id"].as_i64().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn filters_by_tags() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let server = device(&state, "server", None).await;
+        let desktop = device(&state, "desktop", None).await;
+        for (id, tag) in [(server, "Lab"), (server, "critical"), (desktop, "lab")] {
+            let uri = format!("/api/devices/{id}/tags");
+            let (status, _) = call(&state, Method::POST, &uri, Some(&admin), Some(json!({ "tag": tag }))).await;
+            assert!(status.is_success(), "{tag}: {status}");
+        }
+
+        assert_eq!(listed(&state, &admin, "tag=lab&tag=critical&tag_match=all").await, [server]);
+        assert_eq!(listed(&state, &admin, "tag=lab&tag=critical&tag_match=any").await, [server, desktop]);
+        assert_eq!(listed(&state, &admin, "tag=lab&tag=lab&tag_match=all").await, [server, desktop]);
+        assert_eq!(listed(&state, &admin, "tag=Lab&tag=lab&tag_match=all").await, [server, desktop]);
+    }
+
+    #[tokio::test]
+    async fn status_and_group_wake_leave_out_other_users_devices() {
+        let state = state().await;
+        let (owner_id, owner) = user(&state, "owner", "user").await;
+        let (_, other) = user(&state, "other", "user").await;
+        let id = device(&state, "desktop", Some(owner_id)).await;
+        let group_id = sqlx::query_scalar!(r#"INSERT INTO groups (name) VALUES ('office') RETURNING id as "id!""#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        sqlx::query!("UPDATE devices SET group_id = ? WHERE id = ?", group_id, id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let ids = json!({ "ids": [id] });
+        let (_, body) = call(&state, Method::POST, "/api/devices/status", Some(&other), Some(ids.clone())).await;
+        assert_eq!(body, "[]");
+        let (_, body) = call(&state, Method::POST, "/api/devices/status", Some(&owner), Some(ids)).await;
+        assert!(body.contains(&format!("\"id\":{id}")));
+
+        let uri = format!("/api/groups/{group_id}/wake");
+        let (status, body) = call(&state, Method::POST, &uri, Some(&other), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "[]");
+    }
+
+    #[tokio::test]
+    async fn preflight_follows_lenient_mac_parsing() {
+        for (lenient, mac_ok) in [(false, false), (true, true)] {
+            let state = state_with(|config| config.lenient_mac_parsing = lenient).await;
+            let (_, admin) = user(&state, "admin", "admin").await;
+            let id = device(&state, "desktop", None).await;
+            sqlx::query!("UPDATE devices SET mac_address = 'aabb.ccdd.eeff' WHERE id = ?", id)
+                .execute(&state.db)
+                .await
+                .unwrap();
+
+            let (status, body) = call(&state, Method::GET, &format!("/api/devices/{id}/wake-preflight"), Some(&admin), None).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(!body.contains("mac_address"), mac_ok, "lenient={lenient}: {body}");
+        }
+    }
+
+    /// `POST /api/devices` named `name`, with an optional `Idempotency-Key`
+    async fn create(state: &AppState, token: &str, name: &str, key: Option<&str>) -> (StatusCode, String) {
+        let mut request = Request::post("/api/devices")
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(key) = key {
+            request = request.header("idempotency-key", key);
+        }
+        let body = json!({ "name": name, "mac_address": "AA:BB:CC:DD:EE:FF" }).to_string();
+        send(state, request.body(Body::from(body)).unwrap()).await
+    }
+
+    async fn device_count(state: &AppState) -> i64 {
+        sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!: i64" FROM devices"#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn repeated_idempotency_key_returns_the_first_device() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+
+        let (status, first) = create(&state, &admin, "desktop", Some("retry-1")).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, second) = create(&state, &admin, "desktop", Some("retry-1")).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = |body: &str| serde_json::from_str::<serde_json::Value>(body).unwrap()["id"].clone();
+        assert_eq!(id(&first), id(&second));
+        assert_eq!(device_count(&state).await, 1);
+
+        let (status, _) = create(&state, &admin, "desktop", Some("retry-2")).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(device_count(&state).await, 2);
+    }
+
+    #[tokio::test]
+    async fn create_stops_at_device_limit() {
+        let state = state_with(|config| config.max_devices = Some(2)).await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+
+        for name in ["one", "two"] {
+            let (status, _) = create(&state, &admin, name, None).await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+        let (status, body) = create(&state, &admin, "three", None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body, super::DEVICE_LIMIT_REACHED);
+        assert_eq!(device_count(&state).await, 2);
+    }
+
+    #[tokio::test]
+    async fn clone_stops_at_device_limit() {
+        let state = state_with(|config| config.max_devices = Some(2)).await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "desktop", None).await;
+
+        let uri = format!("/api/devices/{id}/clone");
+        let (status, _) = call(&state, Method::POST, &uri, Some(&admin), None).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = call(&state, Method::POST, &uri, Some(&admin), None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(device_count(&state).await, 2);
+    }
+
+    #[tokio::test]
+    async fn create_rejects_control_characters_in_name() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+
+        for name in ["two\nlines", "nul\0byte"] {
+            let (status, _) = create(&state, &admin, name, None).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{name:?}");
+        }
+        let (status, _) = create(&state, &admin, "Rack\t2", None).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_wake_reports_a_scripted_bring_up() {
+        let sender = Arc::new(RecordingSender::default());
+        let prober = ScriptedProber::new([false, false, true]);
+        let state = AppState {
+            wake_sender: sender.clone(),
+            prober: prober.clone(),
+            ..state_with(|config| config.wake_verify_interval_secs = 0).await
+        };
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "desktop", None).await;
+        sqlx::query!("UPDATE devices SET ip_address = '192.0.2.10' WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let (status, body) = call(&state, Method::POST, &format!("/api/devices/{id}/test-wake"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(report["packet_sent"], true);
+        assert_eq!(report["came_online"], true);
+        assert_eq!(report["ping_attempts"], 3);
+        assert_eq!(report["error"], serde_json::Value::Null);
+
+        assert_eq!(sender.sent.lock().unwrap().len(), 1);
+        assert_eq!(prober.probes.lock().unwrap().len(), 3);
+        let is_online = sqlx::query_scalar!(r#"SELECT is_online as "is_online: bool" FROM devices WHERE id = ?"#, id)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(is_online, Some(true));
+    }
+
+    #[tokio::test]
+    async fn ping_stores_a_successful_probe() {
+        let prober = ScriptedProber::new([true]);
+        let state = AppState { prober: prober.clone(), ..state().await };
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "nas", None).await;
+        sqlx::query!("UPDATE devices SET ip_address = '192.0.2.20', liveness_probe = 'tcp:445' WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let (status, body) = call(&state, Method::POST, &format!("/api/devices/{id}/ping"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let ping: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(ping["status"], "online");
+        assert!(ping["last_seen_at"].is_string());
+
+        let stored = sqlx::query!(
+            r#"SELECT is_online as "is_online: bool", last_seen_at, last_rtt_ms FROM devices WHERE id = ?"#,
+            id
+        )
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+        assert_eq!(stored.is_online, Some(true));
+        assert!(stored.last_seen_at.is_some());
+        assert!(stored.last_rtt_ms.is_some());
+        let probes = prober.probes.lock().unwrap();
+        assert_eq!(probes.len(), state.config.ping_count as usize);
+        assert!(probes.iter().all(|&(ip, probe, _)| ip.to_string() == "192.0.2.20" && probe == LivenessProbe::Tcp(445)));
+    }
+
+    #[tokio::test]
+    async fn wake_and_ready_passes_every_stage() {
+        let sender = Arc::new(RecordingSender::default());
+        // Online on the second liveness probe, the service port on the second connect
+        let prober = ScriptedProber::new([false, true, false, true]);
+        let state = AppState {
+            wake_sender: sender.clone(),
+            prober: prober.clone(),
+            ..state_with(|config| config.wake_verify_interval_secs = 0).await
+        };
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "server", None).await;
+        sqlx::query!("UPDATE devices SET ip_address = '192.0.2.30' WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let uri = format!("/api/devices/{id}/wake-and-ready");
+        let (status, body) = call(&state, Method::POST, &uri, Some(&admin), Some(json!({ "service_port": 8080 }))).await;
+        assert_eq!(status, StatusCode::OK);
+        let ready: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(ready["ready"], true);
+        assert_eq!(ready["service_port"], 8080);
+        let stages: Vec<_> = ready["stages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| (s["stage"].as_str().unwrap(), s["ok"].as_bool().unwrap(), s["attempts"].as_u64().unwrap()))
+            .collect();
+        assert_eq!(stages, [("wake", true, 1), ("online", true, 2), ("service_ready", true, 2)]);
+
+        assert_eq!(sender.sent.lock().unwrap().len(), 1);
+        let probes: Vec<_> = prober.probes.lock().unwrap().iter().map(|&(_, probe, _)| probe).collect();
+        assert_eq!(
+            probes,
+            [LivenessProbe::Icmp, LivenessProbe::Icmp, LivenessProbe::Tcp(8080), LivenessProbe::Tcp(8080)]
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_agent_control_never_contacts_the_agent() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let agent_port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(AtomicU32::new(0));
+        tokio::spawn({
+            let connections = connections.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    drop(stream);
+                }
+            }
+        });
+
+        for enabled in [false, true] {
+            let state = state_with(|config| {
+                config.agent_control_enabled = enabled;
+                config.agent_port = agent_port;
+            })
+            .await;
+            let (_, admin) = user(&state, "admin", "admin").await;
+            let id = device(&state, "desktop", None).await;
+            sqlx::query!("UPDATE devices SET ip_address = '127.0.0.1' WHERE id = ?", id)
+                .execute(&state.db)
+                .await
+                .unwrap();
+
+            let (status, _) = call(&state, Method::POST, &format!("/api/devices/{id}/shutdown"), Some(&admin), None).await;
+            if enabled {
+                // The agent hangs up, but was contacted
+                assert_eq!(status, StatusCode::BAD_GATEWAY);
+                assert_eq!(connections.load(Ordering::SeqCst), 1);
+            } else {
+                assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+                let (status, _) = call(&state, Method::POST, &format!("/api/devices/{id}/agent/ping"), Some(&admin), None).await;
+                assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+                assert_eq!(connections.load(Ordering::SeqCst), 0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn clone_keeps_the_owner() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let (alice_id, _) = user(&state, "alice", "user").await;
+        let (_, bob) = user(&state, "bob", "user").await;
+        let id = device(&state, "alice-laptop", Some(alice_id)).await;
+
+        let body = json!({ "mac_address": "11:22:33:44:55:66" });
+        let (status, body) = call(&state, Method::POST, &format!("/api/devices/{id}/clone"), Some(&admin), Some(body)).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let clone: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(clone["owner_id"], alice_id);
+
+        assert!(listed(&state, &bob, "").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_with_a_stale_version_conflicts() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "desktop", None).await;
+        let version = sqlx::query_scalar!("SELECT version FROM devices WHERE id = ?", id)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+
+        let uri = format!("/api/devices/{id}");
+        let (status, body) = call(&state, Method::PUT, &uri, Some(&admin), Some(json!({ "name": "first", "version": version }))).await;
+        assert_eq!(status, StatusCode::OK);
+        let updated: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(updated["version"], version + 1);
+
+        // A second edit based on the same version lost the race
+        let (status, body) = call(&state, Method::PUT, &uri, Some(&admin), Some(json!({ "name": "second", "version": version }))).await;
+        assert_eq!((status, body.as_str()), (StatusCode::CONFLICT, "Device was modified"));
+        let name = sqlx::query_scalar!("SELECT name FROM devices WHERE id = ?", id)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(name, "first");
+
+        let (status, _) = call(&state, Method::PUT, "/api/devices/999", Some(&admin), Some(json!({ "version": version }))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn update_clears_ip_address_and_ping_timeout_with_null() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "server", None).await;
+        let uri = format!("/api/devices/{id}");
+
+        let set = json!({ "ip_address": "192.168.1.10", "ping_timeout_ms": 500 });
+        let (status, body) = call(&state, Method::PUT, &uri, Some(&admin), Some(set)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        // Omitted fields keep their value
+        let rename = json!({ "name": "renamed" });
+        let (_, body) = call(&state, Method::PUT, &uri, Some(&admin), Some(rename)).await;
+        let updated: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(updated["ip_address"], "192.168.1.10");
+        assert_eq!(updated["ping_timeout_ms"], 500);
+
+        let clear = json!({ "ip_address": null, "ping_timeout_ms": null });
+        let (status, body) = call(&state, Method::PUT, &uri, Some(&admin), Some(clear)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let updated: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert!(updated["ip_address"].is_null());
+        assert!(updated["ping_timeout_ms"].is_null());
+        assert_eq!(updated["name"], "renamed");
+    }
+
+    #[tokio::test]
+    async fn create_points_location_at_the_new_device() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let request = Request::post("/api/devices")
+            .header(header::AUTHORIZATION, format!("Bearer {admin}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "name": "server", "mac_address": "AA:BB:CC:DD:EE:FF" }).to_string()))
+            .unwrap();
+        let response = app(&state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers()[header::LOCATION].to_str().unwrap().to_string();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(location, format!("/api/devices/{}", created["id"]));
+    }
+
+    #[tokio::test]
+    async fn list_returns_only_requested_fields() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        device(&state, "server", None).await;
+
+        let (status, body) = call(&state, Method::GET, "/api/devices?fields=id,name,is_online", Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let devices: Vec<serde_json::Map<String, serde_json::Value>> = serde_json::from_str(&body).unwrap();
+        let mut keys: Vec<&str> = devices[0].keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, ["id", "is_online", "name"]);
+
+        let (status, _) = call(&state, Method::GET, "/api/devices?fields=id,password", Some(&admin), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn agent_ping_reports_health_and_authentication() {
+        // An agent that only answers its health check for the right secret
+        let agent = axum::Router::new().route(
+            "/health",
+            axum::routing::get(|headers: axum::http::HeaderMap| async move {
+                if headers.get(header::AUTHORIZATION).is_some_and(|value| value == "Bearer agent-secret") {
+                    axum::Json(json!({ "version": "1.2.3", "os": "linux", "hostname": "desktop" })).into_response()
+                } else {
+                    StatusCode::UNAUTHORIZED.into_response()
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let agent_port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, agent).await });
+
+        for secret in ["agent-secret", "wrong-secret"] {
+            let state = state_with(|config| {
+                config.agent_control_enabled = true;
+                config.agent_port = agent_port;
+                config.agent_secret = Some(secret.to_string());
+            })
+            .await;
+            let (_, admin) = user(&state, "admin", "admin").await;
+            let id = device(&state, "desktop", None).await;
+            sqlx::query!("UPDATE devices SET ip_address = '127.0.0.1' WHERE id = ?", id)
+                .execute(&state.db)
+                .await
+                .unwrap();
+
+            let (status, body) = call(&state, Method::POST, &format!("/api/devices/{id}/agent/ping"), Some(&admin), None).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let agent: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(agent["reachable"], true);
+            if secret == "agent-secret" {
+                assert_eq!(agent["authenticated"], true);
+                assert_eq!(agent["version"], "1.2.3");
+                assert_eq!(agent["hostname"], "desktop");
+            } else {
+                assert_eq!(agent["authenticated"], false);
+                assert!(agent["version"].is_null());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_tells_the_agent_who_asked() {
+        // An agent that remembers the shutdown request it got
+        let received = Arc::new(std::sync::Mutex::new(None));
+        let agent = axum::Router::new().route(
+            "/shutdown",
+            axum::routing::post({
+                let received = received.clone();
+                move |headers: axum::http::HeaderMap, body: String| async move {
+                    *received.lock().unwrap() = Some((headers, body));
+                    StatusCode::OK
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let agent_port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, agent).await });
+
+        let state = state_with(|config| {
+            config.agent_control_enabled = true;
+            config.agent_port = agent_port;
+            config.agent_secret = Some("agent-secret".to_string());
+        })
+        .await;
+        let (alice_id, alice) = user(&state, "alice", "user").await;
+        let id = device(&state, "desktop", Some(alice_id)).await;
+        sqlx::query!("UPDATE devices SET ip_address = '127.0.0.1' WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let (status, body) = call(&state, Method::POST, &format!("/api/devices/{id}/shutdown"), Some(&alice), None).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let (headers, body) = received.lock().unwrap().take().expect("Agent was not called");
+        assert_eq!(headers["x-requested-by"], "alice");
+        assert_eq!(headers[header::AUTHORIZATION], "Bearer agent-secret");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body, json!({ "initiated_by": "alice", "user_id": alice_id }));
+    }
+
+    #[tokio::test]
+    async fn verified_wake_gives_up_after_the_configured_timeout() {
+        let state = state_with(|config| {
+            config.wake_verify_timeout_secs = 1;
+            config.wake_verify_interval_secs = 1;
+        })
+        .await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "desktop", None).await;
+        sqlx::query!("UPDATE devices SET ip_address = '192.0.2.10' WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        // The default prober never sees the device come up
+        let started = std::time::Instant::now();
+        let (status, body) = call(&state, Method::POST, &format!("/api/devices/{id}/wake?verify=true"), Some(&admin), None).await;
+        assert!(started.elapsed() < std::time::Duration::from_secs(5), "Waited {:?}", started.elapsed());
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["timeout_secs"], 1);
+    }
+
+    #[tokio::test]
+    async fn pages_carry_their_position_in_headers() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        for name in ["a", "b", "c", "d", "e"] {
+            device(&state, name, None).await;
+        }
+        let all = listed(&state, &admin, "").await;
+
+        let request = Request::get("/api/devices?limit=2&offset=2")
+            .header(header::AUTHORIZATION, format!("Bearer {admin}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app(&state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-total-count"], "5");
+        assert_eq!(response.headers()["x-page-limit"], "2");
+        assert_eq!(response.headers()["x-page-offset"], "2");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let page: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let ids: Vec<i64> = page.iter().map(|d| d["id"].as_i64().unwrap()).collect();
+        assert_eq!(ids, all[2..4]);
+    }
+
+    #[tokio::test]
+    async fn failed_wakes_are_kept_until_the_next_success() {
+        let state = AppState { wake_sender: RecordingSender::failing(1), ..state().await };
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "desktop", None).await;
+        let uri = format!("/api/devices/{id}/wake");
+
+        let (status, _) = call(&state, Method::POST, &uri, Some(&admin), None).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        let listed: Vec<serde_json::Value> =
+            serde_json::from_str(&call(&state, Method::GET, "/api/devices", Some(&admin), None).await.1).unwrap();
+        assert!(listed[0]["last_action_error"].as_str().unwrap().starts_with("Failed to send WoL"));
+        assert!(!listed[0]["last_action_error_at"].is_null());
+
+        let (status, _) = call(&state, Method::POST, &uri, Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let listed: Vec<serde_json::Value> =
+            serde_json::from_str(&call(&state, Method::GET, "/api/devices", Some(&admin), None).await.1).unwrap();
+        assert!(listed[0]["last_action_error"].is_null());
+        assert!(listed[0]["last_action_error_at"].is_null());
+    }
+
+    #[tokio::test]
+    async fn favorites_are_per_user_and_sort_first() {
+        let state = state().await;
+        let (_, alice) = user(&state, "alice", "user").await;
+        let (_, bob) = user(&state, "bob", "user").await;
+        let first = device(&state, "a", None).await;
+        let second = device(&state, "b", None).await;
+        let favorites = |token: &str| {
+            let (state, token) = (state.clone(), token.to_string());
+            async move {
+                let (_, body) = call(&state, Method::GET, "/api/devices?sort=favorite", Some(&token), None).await;
+                let devices: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+                devices.iter().map(|d| (d["id"].as_i64().unwrap(), d["is_favorite"].as_bool().unwrap())).collect::<Vec<_>>()
+            }
+        };
+
+        let uri = format!("/api/devices/{second}/favorite");
+        let (status, _) = call(&state, Method::POST, &uri, Some(&alice), None).await;
+        assert!(status.is_success());
+        assert_eq!(favorites(&alice).await, [(second, true), (first, false)]);
+        assert_eq!(favorites(&bob).await, [(first, false), (second, false)]);
+
+        let (status, _) = call(&state, Method::DELETE, &uri, Some(&alice), None).await;
+        assert!(status.is_success());
+        assert_eq!(favorites(&alice).await, [(first, false), (second, false)]);
+    }
+
+    #[tokio::test]
+    async fn clone_copies_config_but_not_state() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "lab-pc", None).await;
+        sqlx::query!(
+            "UPDATE devices SET ip_address = '192.168.1.20', broadcast_addr = '192.168.1.255', icon = 'desktop',
+                liveness_probe = 'tcp:22', ping_timeout_ms = 500, is_online = 1,
+                last_seen_at = CURRENT_TIMESTAMP, went_online_at = CURRENT_TIMESTAMP
+             WHERE id = ?",
+            id
+        )
+        .execute(&state.db)
+        .await
+        .unwrap();
+        call(&state, Method::POST, &format!("/api/devices/{id}/tags"), Some(&admin), Some(json!({ "tag": "lab" }))).await;
+
+        let (status, body) = call(&state, Method::POST, &format!("/api/devices/{id}/clone"), Some(&admin), Some(json!({}))).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let clone: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_ne!(clone["id"], id);
+        assert_eq!(clone["name"], "lab-pc (copy)");
+        assert_eq!(clone["mac_address"], "");
+        assert!(clone["ip_address"].is_null());
+        assert_eq!(clone["broadcast_addr"], "192.168.1.255");
+        assert_eq!(clone["icon"], "desktop");
+        assert_eq!(clone["liveness_probe"], "tcp:22");
+        assert_eq!(clone["ping_timeout_ms"], 500);
+        assert_eq!(clone["tags"], json!(["lab"]));
+        assert_eq!(clone["is_online"], false);
+        assert!(clone["last_seen_at"].is_null());
+        assert!(clone["went_online_at"].is_null());
+    }
+
+    #[tokio::test]
+    async fn event_stream_reports_status_changes_of_visible_devices() {
+        use tokio_stream::StreamExt;
+
+        let state = state().await;
+        let (alice_id, alice) = user(&state, "alice", "user").await;
+        let (bob_id, _) = user(&state, "bob", "user").await;
+        let hidden = device(&state, "bobs-pc", Some(bob_id)).await;
+        let visible = device(&state, "alices-pc", Some(alice_id)).await;
+
+        let request = Request::get("/api/devices/events")
+            .header(header::AUTHORIZATION, format!("Bearer {alice}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app(&state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+        let mut stream = response.into_body().into_data_stream();
+
+        crate::pinger::record_liveness(&state, hidden, Some(std::time::Duration::from_millis(1))).await;
+        crate::pinger::record_liveness(&state, visible, Some(std::time::Duration::from_millis(1))).await;
+
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), stream.next())
+            .await
+            .expect("No event within 5s")
+            .unwrap()
+            .unwrap();
+        let frame = String::from_utf8(frame.to_vec()).unwrap();
+        assert!(frame.starts_with("event: device_status\n"), "{frame}");
+        assert!(frame.contains(&format!("\"device_id\":{visible},")), "{frame}");
+        assert!(frame.contains("\"is_online\":true"), "{frame}");
+    }
+
+    #[tokio::test]
+    async fn batch_status_covers_exactly_the_known_ids() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let online = device(&state, "server", None).await;
+        let offline = device(&state, "desktop", None).await;
+        let not_asked = device(&state, "laptop", None).await;
+        sqlx::query!(
+            "UPDATE devices SET ip_address = '192.0.2.1', is_online = (id = ?), last_seen_at = CURRENT_TIMESTAMP WHERE id = ?",
+            online,
+            online
+        )
+        .execute(&state.db)
+        .await
+        .unwrap();
+
+        let ids = json!({ "ids": [offline, 9999, online] });
+        let (status, body) = call(&state, Method::POST, "/api/devices/status", Some(&admin), Some(ids)).await;
+        assert_eq!(status, StatusCode::OK);
+        let statuses: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        let ids: Vec<i64> = statuses.iter().map(|s| s["id"].as_i64().unwrap()).collect();
+        assert_eq!(ids, [online, offline]);
+        assert!(!ids.contains(&not_asked));
+        assert_eq!(statuses[0]["is_online"], true);
+        assert!(!statuses[0]["last_seen_at"].is_null());
+        assert_eq!(statuses[1]["is_online"], false);
+    }
+
+    #[tokio::test]
+    async fn ad_hoc_wake_sends_without_a_device() {
+        let sender = Arc::new(RecordingSender::default());
+        let state = AppState { wake_sender: sender.clone(), ..state().await };
+        let (_, admin) = user(&state, "admin", "admin").await;
+
+        let wake = json!({ "mac": "aa:bb:cc:dd:ee:01", "broadcast": "192.168.1.255", "port": 7 });
+        let (status, body) = call(&state, Method::POST, "/api/wake", Some(&admin), Some(wake)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        {
+            let sent = sender.sent.lock().unwrap();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0].mac, [0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01]);
+            assert_eq!(sent[0].target.to_string(), "192.168.1.255");
+            assert_eq!(sent[0].ports, [7]);
+        }
+        let device_ids = sqlx::query_scalar!("SELECT device_id FROM audit_log WHERE action = ?", crate::audit::ACTION_ADHOC_WAKE)
+            .fetch_all(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(device_ids, [None]);
+
+        let (status, _) = call(&state, Method::POST, "/api/wake", Some(&admin), Some(json!({ "mac": "not-a-mac" }))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(sender.sent.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn online_for_secs_is_derived_for_online_devices() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let online = device(&state, "server", None).await;
+        let offline = device(&state, "desktop", None).await;
+        sqlx::query!(
+            "UPDATE devices SET ip_address = '192.0.2.1', is_online = (id = ?),
+                went_online_at = CASE WHEN id = ? THEN datetime('now', '-90 minutes') END",
+            online,
+            online
+        )
+        .execute(&state.db)
+        .await
+        .unwrap();
+
+        let (_, body) = call(&state, Method::GET, "/api/devices", Some(&admin), None).await;
+        let devices: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        let online_for = |id: i64| devices.iter().find(|d| d["id"] == id).unwrap()["online_for_secs"].clone();
+        let secs = online_for(online).as_i64().unwrap();
+        assert!((90 * 60..90 * 60 + 5).contains(&secs), "{secs}");
+        assert!(online_for(offline).is_null());
+    }
+
+    #[tokio::test]
+    async fn finds_devices_by_partial_mac_or_exact_ip() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let nas = device(&state, "nas", None).await;
+        let printer = device(&state, "printer", None).await;
+        sqlx::query!(
+            "UPDATE devices SET mac_address = CASE id WHEN ? THEN '00:11:22:AA:BB:CC' ELSE '00:11:33:AA:BB:CC' END,
+                ip_address = CASE id WHEN ? THEN '192.168.1.10' ELSE '192.168.1.100' END",
+            nas,
+            nas
+        )
+        .execute(&state.db)
+        .await
+        .unwrap();
+
+        // Delimiters and case don't matter
+        assert_eq!(listed(&state, &admin, "mac_prefix=00-11-22").await, [nas]);
+        assert_eq!(listed(&state, &admin, "mac_prefix=001133a").await, [printer]);
+        assert_eq!(listed(&state, &admin, "mac_prefix=00:11").await, [nas, printer]);
+        // Exact, so .10 doesn't also match .100
+        assert_eq!(listed(&state, &admin, "ip=192.168.1.10").await, [nas]);
+        assert_eq!(listed(&state, &admin, "search=00:11:22").await, [nas]);
+    }
+
+    #[tokio::test]
+    async fn blank_broadcast_address_uses_global_broadcast() {
+        let (state, sender) = recording_state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        // Stored before broadcast addresses were validated
+        let id = device(&state, "nas", None).await;
+        sqlx::query!("UPDATE devices SET broadcast_addr = '  ' WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let (status, _) = call(&state, Method::POST, &format!("/api/devices/{id}/wake"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(sender.sent.lock().unwrap()[0].target, "255.255.255.255");
+
+        let blank = json!({"name": "pc", "mac_address": "00:11:22:33:44:55", "broadcast_addr": ""});
+        let (status, body) = call(&state, Method::POST, "/api/devices", Some(&admin), Some(blank)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let created: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(created["broadcast_addr"], "255.255.255.255");
+
+        let invalid = json!({"broadcast_addr": "not-an-ip"});
+        let (status, _) = call(&state, Method::PUT, &format!("/api/devices/{id}"), Some(&admin), Some(invalid)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn batch_requests_over_the_limit_are_rejected() {
+        let state = state_with(|config| config.max_batch_items = 2).await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+
+        let (status, body) = call(&state, Method::POST, "/api/devices/status", Some(&admin), Some(json!({ "ids": [1, 2, 3] }))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body.starts_with("Too many items"), "{body}");
+
+        let (status, _) = call(&state, Method::POST, "/api/devices/status", Some(&admin), Some(json!({ "ids": [1, 2] }))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn devices_without_ports_use_the_configured_default() {
+        let sender = Arc::new(RecordingSender::default());
+        let state = AppState { wake_sender: sender.clone(), ..state_with(|config| config.wol_default_port = 7).await };
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "nas", None).await;
+
+        let (status, _) = call(&state, Method::POST, &format!("/api/devices/{id}/wake"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(sender.sent.lock().unwrap()[0].ports, [7]);
+    }
+
+    #[tokio::test]
+    async fn wake_warns_without_broadcast_or_ip() {
+        let (state, _) = recording_state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "nas", None).await;
+        let uri = format!("/api/devices/{id}/wake");
+
+        let (status, body) = call(&state, Method::POST, &uri, Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(response["status"], "sent");
+        assert_eq!(response["warning"], "No broadcast or IP configured; sent to global broadcast");
+
+        sqlx::query!("UPDATE devices SET ip_address = '192.168.1.10' WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+        let (status, body) = call(&state, Method::POST, &uri, Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body.contains("warning"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn disabled_devices_are_hidden_and_cannot_be_woken() {
+        let (state, sender) = recording_state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let active = device(&state, "desktop", None).await;
+        let rma = device(&state, "rma", None).await;
+
+        let status_uri = format!("/api/devices/{rma}/status");
+        let (status, _) = call(&state, Method::PUT, &status_uri, Some(&admin), Some(json!({ "enabled": false }))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert_eq!(listed(&state, &admin, "").await, [active]);
+        assert_eq!(listed(&state, &admin, "include_disabled=true").await, [active, rma]);
+
+        let (status, body) = call(&state, Method::POST, &format!("/api/devices/{rma}/wake"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body, "Device disabled");
+        assert!(sender.sent.lock().unwrap().is_empty());
+
+        let (status, _) = call(&state, Method::PUT, &status_uri, Some(&admin), Some(json!({ "enabled": true }))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call(&state, Method::POST, &format!("/api/devices/{rma}/wake"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn wake_sends_to_every_listed_port() {
+        let (state, sender) = recording_state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+
+        let duplicate = json!({"name": "nas", "mac_address": "00:11:22:33:44:55", "wol_ports": [9, 9]});
+        let (status, _) = call(&state, Method::POST, "/api/devices", Some(&admin), Some(duplicate)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let payload = json!({"name": "nas", "mac_address": "00:11:22:33:44:55", "broadcast_addr": "192.168.1.255", "wol_ports": [7, 9]});
+        let (status, body) = call(&state, Method::POST, "/api/devices", Some(&admin), Some(payload)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = serde_json::from_str::<serde_json::Value>(&body).unwrap()["id"].as_i64().unwrap();
+
+        let (status, _) = call(&state, Method::POST, &format!("/api/devices/{id}/wake"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let sent = sender.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].target, "192.168.1.255");
+        assert_eq!(sent[0].ports, [7, 9]);
+    }
+
+    #[tokio::test]
+    async fn admins_transfer_ownership() {
+        let state = state().await;
+        let (admin_id, admin) = user(&state, "admin", "admin").await;
+        let (alice_id, alice) = user(&state, "alice", "user").await;
+        let (bob_id, _) = user(&state, "bob", "user").await;
+        let id = device(&state, "laptop", Some(alice_id)).await;
+        let uri = format!("/api/devices/{id}/owner");
+        let owner = || async {
+            sqlx::query_scalar!("SELECT owner_id FROM devices WHERE id = ?", id)
+                .fetch_one(&state.db)
+                .await
+                .unwrap()
+        };
+
+        let (status, _) = call(&state, Method::PUT, &uri, Some(&alice), Some(json!({ "owner_id": null }))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = call(&state, Method::PUT, &uri, Some(&admin), Some(json!({ "owner_id": 999 }))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(owner().await, Some(alice_id));
+
+        let (status, _) = call(&state, Method::PUT, &uri, Some(&admin), Some(json!({ "owner_id": bob_id }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(owner().await, Some(bob_id));
+        let entry = sqlx::query!("SELECT user_id, details FROM audit_log WHERE action = ?", crate::audit::ACTION_OWNER_CHANGED)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(entry.user_id, Some(admin_id));
+        assert_eq!(entry.details.unwrap(), format!("from={alice_id} to={bob_id}"));
+
+        let (status, _) = call(&state, Method::PUT, &uri, Some(&admin), Some(json!({ "owner_id": null }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(owner().await, None);
+    }
+
+    #[tokio::test]
+    async fn new_devices_inherit_the_default_broadcast() {
+        let state = state_with(|config| config.default_broadcast_addr = "192.168.1.255".parse().unwrap()).await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+
+        for (broadcast, expected) in [(None, "192.168.1.255"), (Some(""), "192.168.1.255"), (Some("10.0.0.255"), "10.0.0.255")] {
+            let payload = json!({"name": "pc", "mac_address": "00:11:22:33:44:55", "broadcast_addr": broadcast});
+            let (status, body) = call(&state, Method::POST, "/api/devices", Some(&admin), Some(payload)).await;
+            assert_eq!(status, StatusCode::CREATED);
+            let created: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(created["broadcast_addr"], expected, "{broadcast:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn devices_move_between_groups() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let mut groups = Vec::new();
+        for name in ["office", "lab"] {
+            let id = sqlx::query_scalar!(r#"INSERT INTO groups (name) VALUES (?) RETURNING id as "id!""#, name)
+                .fetch_one(&state.db)
+                .await
+                .unwrap();
+            groups.push(id);
+        }
+        let id = device(&state, "desktop", None).await;
+        let uri = format!("/api/devices/{id}/group");
+        let members = |group_id: i64| {
+            let state = state.clone();
+            async move {
+                sqlx::query_scalar!(r#"SELECT id as "id!" FROM devices WHERE group_id = ?"#, group_id)
+                    .fetch_all(&state.db)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        for group_id in [groups[0], groups[1]] {
+            let (status, body) = call(&state, Method::PUT, &uri, Some(&admin), Some(json!({ "group_id": group_id }))).await;
+            assert_eq!(status, StatusCode::OK);
+            let updated: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(updated["group_id"], group_id);
+        }
+        assert!(members(groups[0]).await.is_empty());
+        assert_eq!(members(groups[1]).await, [id]);
+
+        let (status, _) = call(&state, Method::PUT, &uri, Some(&admin), Some(json!({ "group_id": 999 }))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(members(groups[1]).await, [id]);
+
+        let (status, body) = call(&state, Method::PUT, &uri, Some(&admin), Some(json!({ "group_id": null }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(serde_json::from_str::<serde_json::Value>(&body).unwrap()["group_id"].is_null());
+        assert!(members(groups[1]).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn agent_metadata_is_stored_on_the_device() {
+        let agent = axum::Router::new().route(
+            "/health",
+            axum::routing::get(|| async { axum::Json(json!({ "agent_version": "2.0.1", "os": "Windows 11", "hostname": "gaming-pc" })) }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let agent_port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, agent).await });
+
+        let state = state_with(|config| {
+            config.agent_control_enabled = true;
+            config.agent_port = agent_port;
+        })
+        .await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "gaming-pc", None).await;
+        sqlx::query!("UPDATE devices SET ip_address = '127.0.0.1' WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let (status, _) = call(&state, Method::POST, &format!("/api/devices/{id}/agent/ping"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, body) = call(&state, Method::GET, "/api/devices", Some(&admin), None).await;
+        let devices: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let device = &devices[0];
+        assert_eq!(device["agent_os"], "Windows 11");
+        assert_eq!(device["agent_hostname"], "gaming-pc");
+        assert_eq!(device["agent_version"], "2.0.1");
+    }
+
+    #[tokio::test]
+    async fn wakes_are_refused_in_maintenance_mode() {
+        let (state, sender) = recording_state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "desktop", None).await;
+
+        let (status, _) = call(&state, Method::PUT, "/api/maintenance", Some(&admin), Some(json!({ "enabled": true }))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = call(&state, Method::GET, "/api/features", Some(&admin), None).await;
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap()["maintenance"], true);
+
+        let (status, body) = call(&state, Method::POST, &format!("/api/devices/{id}/wake"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, "Maintenance mode");
+        assert!(sender.sent.lock().unwrap().is_empty());
+
+        call(&state, Method::PUT, "/api/maintenance", Some(&admin), Some(json!({ "enabled": false }))).await;
+        let (status, _) = call(&state, Method::POST, &format!("/api/devices/{id}/wake"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn agent_requests_include_the_base_path() {
+        // Only answers under the proxy's prefix
+        let agent = axum::Router::new().route("/agent/health", axum::routing::get(|| async { axum::Json(json!({ "version": "1.0" })) }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let agent_port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, agent).await });
+
+        let state = state_with(|config| {
+            config.agent_control_enabled = true;
+            config.agent_port = agent_port;
+        })
+        .await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "desktop", None).await;
+        let uri = format!("/api/devices/{id}");
+        let relative = json!({ "ip_address": "127.0.0.1", "agent_base_path": "agent" });
+        let (status, _) = call(&state, Method::PUT, &uri, Some(&admin), Some(relative)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let prefixed = json!({ "ip_address": "127.0.0.1", "agent_base_path": "/agent/" });
+        let (status, body) = call(&state, Method::PUT, &uri, Some(&admin), Some(prefixed)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap()["agent_base_path"], "/agent");
+        assert_eq!(
+            crate::agent::agent_url(&state.config, "127.0.0.1", Some("/agent"), "/health"),
+            format!("http://127.0.0.1:{agent_port}/agent/health")
+        );
+
+        let (status, body) = call(&state, Method::POST, &format!("{uri}/agent/ping"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let agent: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(agent["reachable"], true);
+        assert_eq!(agent["version"], "1.0");
+    }
+}
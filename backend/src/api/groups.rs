@@ -0,0 +1,821 @@
+use crate::db::AppState;
+use crate::audit;
+use crate::error::db_error;
+use crate::api::devices::{send_device_wake, MAINTENANCE_MODE};
+use crate::api::validation::{check_batch_size, reject_control_chars};
+use crate::auth::{AuthUser, AdminUser};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{sse::{Event, KeepAlive, Sse}, IntoResponse},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::UnboundedReceiverStream, StreamExt};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+// ==========================================
+// 1. DTOs
+// ==========================================
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateGroupRequest {
+    pub name: String,
+    /// Wake members one after another instead of all at once
+    pub sequential_wake: Option<bool>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateGroupRequest {
+    pub name: Option<String>,
+    pub sequential_wake: Option<bool>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct GroupMemberRequest {
+    pub device_id: i64,
+    /// Pause before waking this device in a sequential wake
+    pub delay_secs: Option<i64>,
+}
+
+/// Replaces the group's members; list order is the wake order
+#[derive(Deserialize, ToSchema)]
+pub struct SetGroupMembersRequest {
+    pub members: Vec<GroupMemberRequest>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct GroupMember {
+    pub device_id: i64,
+    pub name: String,
+    pub wake_order: i64,
+    pub wake_delay_secs: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct GroupResponse {
+    pub id: i64,
+    pub name: String,
+    pub sequential_wake: bool,
+    /// In wake order
+    pub members: Vec<GroupMember>,
+}
+
+/// Member counts by status, e.g. for a "4/6 online" header
+#[derive(Serialize, ToSchema)]
+pub struct GroupSummaryResponse {
+    pub total: i64,
+    pub online: i64,
+    pub offline: i64,
+    /// Never probed, or without an IP address to probe
+    pub unknown: i64,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WakeGroupQuery {
+    /// Stream each device's result as a server-sent event as soon as it is known
+    pub stream: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct GroupWakeResult {
+    pub device_id: i64,
+    pub sent: bool,
+    pub error: Option<String>,
+}
+
+// ==========================================
+// 2. HELPER FUNCTIONS
+// ==========================================
+
+/// Upper bound for `delay_secs`, since a sequential wake holds the request open
+const MAX_WAKE_DELAY_SECS: i64 = 300;
+
+/// Members of every group, leaving out devices `viewer` may not see
+async fn fetch_members(state: &AppState, viewer: &AuthUser) -> Result<HashMap<i64, Vec<GroupMember>>, sqlx::Error> {
+    let is_admin = viewer.is_admin();
+    let rows = sqlx::query!(
+        r#"SELECT group_id as "group_id!", id as "id!", name, wake_order, wake_delay_secs
+           FROM devices WHERE group_id IS NOT NULL
+             AND (? OR owner_id IS NULL OR owner_id = ?)
+           ORDER BY wake_order, id"#,
+        is_admin,
+        viewer.id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut members: HashMap<i64, Vec<GroupMember>> = HashMap::new();
+    for row in rows {
+        members.entry(row.group_id).or_default().push(GroupMember {
+            device_id: row.id,
+            name: row.name,
+            wake_order: row.wake_order,
+            wake_delay_secs: row.wake_delay_secs,
+        });
+    }
+    Ok(members)
+}
+
+async fn fetch_group(state: &AppState, id: i64) -> Result<Option<GroupResponse>, sqlx::Error> {
+    let Some(group) = sqlx::query!(
+        r#"SELECT id as "id!", name, sequential_wake FROM groups WHERE id = ?"#,
+        id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let members = sqlx::query_as!(
+        GroupMember,
+        r#"SELECT id as "device_id!", name, wake_order, wake_delay_secs
+           FROM devices WHERE group_id = ?
+           ORDER BY wake_order, id"#,
+        id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Some(GroupResponse {
+        id: group.id,
+        name: group.name,
+        sequential_wake: group.sequential_wake,
+        members,
+    }))
+}
+
+/// A group or scene member as needed for waking it
+pub struct WakeMember {
+    pub id: i64,
+    pub mac_address: String,
+    pub broadcast_addr: Option<String>,
+    pub wake_delay_secs: i64,
+    pub enabled: bool,
+    pub wol_ports: Option<String>,
+}
+
+/// Wakes `members` in order, reporting each result on `results` as soon as it is known.
+/// Sequential groups wait each member's `wake_delay_secs` first. Returns how many packets went out.
+pub async fn wake_members(
+    state: AppState,
+    sequential: bool,
+    members: Vec<WakeMember>,
+    results: mpsc::UnboundedSender<GroupWakeResult>,
+) -> usize {
+    let mut sent = 0;
+    for (position, member) in members.into_iter().enumerate() {
+        if !member.enabled {
+            let _ = results.send(GroupWakeResult {
+                device_id: member.id,
+                sent: false,
+                error: Some("Device disabled".to_string()),
+            });
+            continue;
+        }
+        if sequential && position > 0 && member.wake_delay_secs > 0 {
+            state.wake_sender.pause(Duration::from_secs(member.wake_delay_secs as u64)).await;
+        }
+        let result = send_device_wake(
+            &state,
+            member.id,
+            &member.mac_address,
+            member.broadcast_addr.as_deref(),
+            member.wol_ports.as_deref(),
+        )
+        .await;
+        sent += usize::from(result.is_ok());
+        // A streaming client that went away doesn't stop the wake
+        let _ = results.send(GroupWakeResult {
+            device_id: member.id,
+            sent: result.is_ok(),
+            error: result.err(),
+        });
+    }
+    sent
+}
+
+/// Audits a group wake once all of its members have been handled
+async fn record_group_wake(state: &AppState, user_id: i64, group_id: i64, sent: usize, total: usize) {
+    let details = format!("group={} sent={}/{}", group_id, sent, total);
+    audit::record(&state.db, Some(user_id), audit::ACTION_GROUP_WAKE, None, None, Some(&details)).await;
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.to_string().contains("UNIQUE")
+}
+
+// ==========================================
+// 3. HANDLERS
+// ==========================================
+
+/// GET /api/groups
+#[utoipa::path(
+    get,
+    path = "/api/groups",
+    tag = "groups",
+    responses(
+        (status = 200, description = "All groups with their members", body = [GroupResponse])
+    )
+)]
+pub async fn list_groups(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let groups = match sqlx::query!(r#"SELECT id as "id!", name, sequential_wake FROM groups ORDER BY name"#)
+        .fetch_all(&state.db)
+        .await
+    {
+        Ok(g) => g,
+        Err(e) => return db_error(&e, "Failed to fetch groups"),
+    };
+
+    let mut members = match fetch_members(&state, &auth).await {
+        Ok(m) => m,
+        Err(e) => return db_error(&e, "Failed to fetch groups"),
+    };
+
+    let res: Vec<GroupResponse> = groups
+        .into_iter()
+        .map(|g| GroupResponse {
+            members: members.remove(&g.id).unwrap_or_default(),
+            id: g.id,
+            name: g.name,
+            sequential_wake: g.sequential_wake,
+        })
+        .collect();
+    Json(res).into_response()
+}
+
+/// POST /api/groups
+#[utoipa::path(
+    post,
+    path = "/api/groups",
+    request_body = CreateGroupRequest,
+    tag = "groups",
+    responses(
+        (status = 201, description = "Group created", body = GroupResponse,
+            headers(("Location" = String, description = "URL of the created group"))),
+        (status = 400, description = "Invalid name"),
+        (status = 409, description = "Name taken")
+    )
+)]
+pub async fn create_group(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateGroupRequest>,
+) -> impl IntoResponse {
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Group name must not be empty").into_response();
+    }
+    if let Err(e) = reject_control_chars("name", name) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+    let sequential_wake = payload.sequential_wake.unwrap_or(false);
+
+    let result = sqlx::query!(
+        r#"INSERT INTO groups (name, sequential_wake) VALUES (?, ?) RETURNING id as "id!", name, sequential_wake"#,
+        name,
+        sequential_wake
+    )
+    .fetch_one(&state.db)
+    .await;
+
+    match result {
+        Ok(g) => {
+            let resp = GroupResponse {
+                id: g.id,
+                name: g.name,
+                sequential_wake: g.sequential_wake,
+                members: Vec::new(),
+            };
+            let location = format!("/api/groups/{}", resp.id);
+            (StatusCode::CREATED, [(header::LOCATION, location)], Json(resp)).into_response()
+        }
+        Err(e) if is_unique_violation(&e) => (StatusCode::CONFLICT, "Group name already exists").into_response(),
+        Err(e) => db_error(&e, "Failed to create group"),
+    }
+}
+
+/// PUT /api/groups/:id
+#[utoipa::path(
+    put,
+    path = "/api/groups/{id}",
+    params(
+        ("id" = i64, Path, description = "Group ID")
+    ),
+    request_body = UpdateGroupRequest,
+    tag = "groups",
+    responses(
+        (status = 200, description = "Group updated", body = GroupResponse),
+        (status = 404, description = "Group not found"),
+        (status = 409, description = "Name taken")
+    )
+)]
+pub async fn update_group(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<UpdateGroupRequest>,
+) -> impl IntoResponse {
+    let name = payload.name.as_deref().map(str::trim);
+    if name == Some("") {
+        return (StatusCode::BAD_REQUEST, "Group name must not be empty").into_response();
+    }
+    if let Some(name) = name
+        && let Err(e) = reject_control_chars("name", name)
+    {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+
+    let result = sqlx::query!(
+        "UPDATE groups SET name = COALESCE(?, name), sequential_wake = COALESCE(?, sequential_wake) WHERE id = ?",
+        name,
+        payload.sequential_wake,
+        id
+    )
+    .execute(&state.db)
+    .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => return (StatusCode::NOT_FOUND, "Group not found").into_response(),
+        Ok(_) => {}
+        Err(e) if is_unique_violation(&e) => return (StatusCode::CONFLICT, "Group name already exists").into_response(),
+        Err(e) => return db_error(&e, "Failed to update group"),
+    }
+
+    match fetch_group(&state, id).await {
+        Ok(Some(g)) => Json(g).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Group not found").into_response(),
+        Err(e) => db_error(&e, "Database error"),
+    }
+}
+
+/// DELETE /api/groups/:id
+/// Members stay as ungrouped devices
+#[utoipa::path(
+    delete,
+    path = "/api/groups/{id}",
+    params(
+        ("id" = i64, Path, description = "Group ID")
+    ),
+    tag = "groups",
+    responses(
+        (status = 200, description = "Group deleted"),
+        (status = 404, description = "Group not found")
+    )
+)]
+pub async fn delete_group(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    let result = sqlx::query!("DELETE FROM groups WHERE id = ?", id)
+        .execute(&state.db)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Group not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Group deleted").into_response(),
+        Err(e) => db_error(&e, "Failed to delete group"),
+    }
+}
+
+/// PUT /api/groups/:id/members
+/// Replaces the member list. Devices listed here leave their previous group.
+#[utoipa::path(
+    put,
+    path = "/api/groups/{id}/members",
+    params(
+        ("id" = i64, Path, description = "Group ID")
+    ),
+    request_body = SetGroupMembersRequest,
+    tag = "groups",
+    responses(
+        (status = 200, description = "Members updated", body = GroupResponse),
+        (status = 400, description = "Duplicate device or invalid delay"),
+        (status = 404, description = "Group or device not found"),
+        (status = 422, description = "Too many members")
+    )
+)]
+pub async fn set_group_members(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<SetGroupMembersRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = check_batch_size(&state.config, "members", payload.members.len()) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+    let mut seen = HashSet::new();
+    for member in &payload.members {
+        if !seen.insert(member.device_id) {
+            return (StatusCode::BAD_REQUEST, format!("Device {} listed twice", member.device_id)).into_response();
+        }
+        if let Some(delay) = member.delay_secs
+            && !(0..=MAX_WAKE_DELAY_SECS).contains(&delay)
+        {
+            return (StatusCode::BAD_REQUEST, format!("delay_secs must be between 0 and {}", MAX_WAKE_DELAY_SECS)).into_response();
+        }
+    }
+
+    let mut tx = match state.db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(&e, "Failed to update members"),
+    };
+
+    match sqlx::query!("SELECT id FROM groups WHERE id = ?", id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return (StatusCode::NOT_FOUND, "Group not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
+    }
+
+    let cleared = sqlx::query!(
+        "UPDATE devices SET group_id = NULL, wake_order = 0, wake_delay_secs = 0 WHERE group_id = ?",
+        id
+    )
+    .execute(&mut *tx)
+    .await;
+
+    if let Err(e) = cleared {
+        return db_error(&e, "Failed to update members");
+    }
+
+    for (position, member) in payload.members.iter().enumerate() {
+        let position = position as i64;
+        let delay = member.delay_secs.unwrap_or(0);
+        let result = sqlx::query!(
+            "UPDATE devices SET group_id = ?, wake_order = ?, wake_delay_secs = ? WHERE id = ?",
+            id,
+            position,
+            delay,
+            member.device_id
+        )
+        .execute(&mut *tx)
+        .await;
+
+        match result {
+            Ok(r) if r.rows_affected() == 0 => {
+                return (StatusCode::NOT_FOUND, format!("Device {} not found", member.device_id)).into_response();
+            }
+            Ok(_) => {}
+            Err(e) => return db_error(&e, "Failed to update members"),
+        }
+    }
+
+    if let Err(e) = tx.commit().await {
+        return db_error(&e, "Failed to update members");
+    }
+
+    match fetch_group(&state, id).await {
+        Ok(Some(g)) => Json(g).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Group not found").into_response(),
+        Err(e) => db_error(&e, "Database error"),
+    }
+}
+
+/// GET /api/groups/:id/summary
+#[utoipa::path(
+    get,
+    path = "/api/groups/{id}/summary",
+    params(
+        ("id" = i64, Path, description = "Group ID")
+    ),
+    tag = "groups",
+    responses(
+        (status = 200, description = "Member counts by status", body = GroupSummaryResponse),
+        (status = 404, description = "Group not found")
+    )
+)]
+pub async fn group_summary(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    // Same rules as `DeviceStatus::new`; the LEFT JOIN keeps empty groups.
+    // Devices owned by other users don't count for non-admins.
+    let is_admin = auth.is_admin();
+    let summary = sqlx::query_as!(
+        GroupSummaryResponse,
+        r#"SELECT
+            COUNT(d.id) as "total!: i64",
+            COALESCE(SUM(d.is_online = 1 AND d.ip_address IS NOT NULL), 0) as "online!: i64",
+            COALESCE(SUM(d.is_online = 0 AND d.ip_address IS NOT NULL), 0) as "offline!: i64",
+            COALESCE(SUM(d.id IS NOT NULL AND (d.is_online IS NULL OR d.ip_address IS NULL)), 0) as "unknown!: i64"
+           FROM groups g
+           LEFT JOIN devices d ON d.group_id = g.id
+             AND (? OR d.owner_id IS NULL OR d.owner_id = ?)
+           WHERE g.id = ?
+           GROUP BY g.id"#,
+        is_admin,
+        auth.id,
+        id
+    )
+    .fetch_optional(&state.db)
+    .await;
+
+    match summary {
+        Ok(Some(s)) => Json(s).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Group not found").into_response(),
+        Err(e) => db_error(&e, "Failed to fetch group summary"),
+    }
+}
+
+/// POST /api/groups/:id/wake
+/// Wakes every member. Sequential groups go in wake order and wait each
+/// member's `wake_delay_secs` first; otherwise all packets go out at once.
+/// With `?stream=true` each result is sent as a `result` event as it completes,
+/// followed by a final `done` event.
+#[utoipa::path(
+    post,
+    path = "/api/groups/{id}/wake",
+    params(
+        ("id" = i64, Path, description = "Group ID"),
+        WakeGroupQuery
+    ),
+    tag = "groups",
+    responses(
+        (status = 200, description = "Per-device results, in wake order", body = [GroupWakeResult]),
+        (status = 200, description = "With `stream=true`: one `result` event per device, then `done`",
+            content_type = "text/event-stream", body = GroupWakeResult),
+        (status = 404, description = "Group not found"),
+        (status = 503, description = "Maintenance mode")
+    )
+)]
+pub async fn wake_group(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(query): Query<WakeGroupQuery>,
+) -> impl IntoResponse {
+    if state.in_maintenance() {
+        return (StatusCode::SERVICE_UNAVAILABLE, MAINTENANCE_MODE).into_response();
+    }
+
+    let sequential = match sqlx::query_scalar!("SELECT sequential_wake FROM groups WHERE id = ?", id)
+        .fetch_optional(&state.db)
+        .await
+    {
+        Ok(Some(s)) => s,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Group not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
+    };
+
+    // Members owned by other users are left out for non-admins
+    let is_admin = auth.is_admin();
+    let members = match sqlx::query_as!(
+        WakeMember,
+        r#"SELECT id as "id!", mac_address, broadcast_addr, wake_delay_secs, enabled, wol_ports
+           FROM devices WHERE group_id = ?
+             AND (? OR owner_id IS NULL OR owner_id = ?)
+           ORDER BY wake_order, id"#,
+        id,
+        is_admin,
+        auth.id
+    )
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(m) => m,
+        Err(e) => return db_error(&e, "Failed to fetch group members"),
+    };
+
+    let total = members.len();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    if query.stream.unwrap_or(false) {
+        tokio::spawn(async move {
+            let sent = wake_members(state.clone(), sequential, members, tx).await;
+            record_group_wake(&state, auth.id, id, sent, total).await;
+        });
+        let results = UnboundedReceiverStream::new(rx)
+            .filter_map(|result| Event::default().event("result").json_data(result).ok());
+        let done = tokio_stream::once(Event::default().event("done").data(""));
+        let stream = results.chain(done).map(Ok::<_, Infallible>);
+        return Sse::new(stream).keep_alive(KeepAlive::default()).into_response();
+    }
+
+    let sent = wake_members(state.clone(), sequential, members, tx).await;
+    record_group_wake(&state, auth.id, id, sent, total).await;
+    let mut results = Vec::new();
+    while let Ok(result) = rx.try_recv() {
+        results.push(result);
+    }
+    Json(results).into_response()
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        list_groups,
+        create_group,
+        update_group,
+        delete_group,
+        set_group_members,
+        group_summary,
+        wake_group
+    ),
+    components(
+        schemas(
+            CreateGroupRequest,
+            UpdateGroupRequest,
+            GroupMemberRequest,
+            SetGroupMembersRequest,
+            GroupMember,
+            GroupResponse,
+            GroupSummaryResponse,
+            GroupWakeResult
+        )
+    ),
+    tags(
+        (name = "groups", description = "Device group endpoints")
+    )
+)]
+pub struct GroupApi;
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{call, device, recording_state, state, user};
+    use axum::http::{Method, StatusCode};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn sequential_wake_sends_in_order_with_delays() {
+        let (state, sender) = recording_state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let group_id = sqlx::query_scalar!(r#"INSERT INTO groups (name, sequential_wake) VALUES ('rack', 1) RETURNING id as "id!""#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        // Listed out of wake order; the first member's delay doesn't apply
+        for (mac, wake_order, delay) in [("00:00:00:00:00:03", 3, 10), ("00:00:00:00:00:01", 1, 7), ("00:00:00:00:00:02", 2, 5)] {
+            sqlx::query!(
+                "INSERT INTO devices (name, mac_address, group_id, wake_order, wake_delay_secs) VALUES (?, ?, ?, ?, ?)",
+                mac,
+                mac,
+                group_id,
+                wake_order,
+                delay
+            )
+            .execute(&state.db)
+            .await
+            .unwrap();
+        }
+
+        let (status, _) = call(&state, Method::POST, &format!("/api/groups/{group_id}/wake"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let sent = sender.sent.lock().unwrap().clone();
+        let macs: Vec<u8> = sent.iter().map(|p| p.mac[5]).collect();
+        assert_eq!(macs, [1, 2, 3]);
+        assert_eq!(sent[1].at - sent[0].at, Duration::from_secs(5));
+        assert_eq!(sent[2].at - sent[1].at, Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn parallel_wake_sends_without_delays() {
+        let (state, sender) = recording_state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let group_id = sqlx::query_scalar!(r#"INSERT INTO groups (name) VALUES ('office') RETURNING id as "id!""#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        for (mac, wake_order) in [("00:00:00:00:00:01", 1), ("00:00:00:00:00:02", 2)] {
+            sqlx::query!(
+                "INSERT INTO devices (name, mac_address, group_id, wake_order, wake_delay_secs) VALUES (?, ?, ?, ?, 30)",
+                mac,
+                mac,
+                group_id,
+                wake_order
+            )
+            .execute(&state.db)
+            .await
+            .unwrap();
+        }
+
+        let (status, _) = call(&state, Method::POST, &format!("/api/groups/{group_id}/wake"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let sent = sender.sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].at, sent[1].at);
+    }
+
+    #[tokio::test]
+    async fn groups_hide_other_users_devices() {
+        let state = state().await;
+        let (alice_id, alice) = user(&state, "alice", "user").await;
+        let (_, bob) = user(&state, "bob", "user").await;
+        let group_id = sqlx::query_scalar!(r#"INSERT INTO groups (name) VALUES ('desk') RETURNING id as "id!""#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        let private = device(&state, "alice-laptop", Some(alice_id)).await;
+        let shared = device(&state, "printer", None).await;
+        sqlx::query!("UPDATE devices SET group_id = ?", group_id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        for (token, expected) in [(&alice, vec![private, shared]), (&bob, vec![shared])] {
+            let (_, body) = call(&state, Method::GET, "/api/groups", Some(token), None).await;
+            let groups: serde_json::Value = serde_json::from_str(&body).unwrap();
+            let members: Vec<i64> = groups[0]["members"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|m| m["device_id"].as_i64().unwrap())
+                .collect();
+            assert_eq!(members, expected);
+
+            let (_, body) = call(&state, Method::GET, &format!("/api/groups/{group_id}/summary"), Some(token), None).await;
+            let summary: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(summary["total"], expected.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn summary_counts_members_by_status() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let group_id = sqlx::query_scalar!(r#"INSERT INTO groups (name) VALUES ('lab') RETURNING id as "id!""#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        // Without an IP address a device is unknown even if it was marked online
+        for (ip, is_online) in [
+            (Some("10.0.0.1"), Some(true)),
+            (Some("10.0.0.2"), Some(true)),
+            (Some("10.0.0.3"), Some(false)),
+            (Some("10.0.0.4"), None),
+            (None, Some(true)),
+        ] {
+            sqlx::query!(
+                "INSERT INTO devices (name, mac_address, ip_address, is_online, group_id) VALUES ('pc', 'AA:BB:CC:DD:EE:FF', ?, ?, ?)",
+                ip,
+                is_online,
+                group_id
+            )
+            .execute(&state.db)
+            .await
+            .unwrap();
+        }
+        // Devices outside the group don't count
+        device(&state, "elsewhere", None).await;
+
+        let (status, body) = call(&state, Method::GET, &format!("/api/groups/{group_id}/summary"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let summary: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(summary, serde_json::json!({"total": 5, "online": 2, "offline": 1, "unknown": 2}));
+
+        let (status, _) = call(&state, Method::GET, "/api/groups/999/summary", Some(&admin), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn streamed_wake_reports_each_result_in_order() {
+        let (state, _) = recording_state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let group_id = sqlx::query_scalar!(r#"INSERT INTO groups (name, sequential_wake) VALUES ('rack', 1) RETURNING id as "id!""#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        let mut ids = Vec::new();
+        for (wake_order, enabled) in [(1, true), (2, false), (3, true)] {
+            let id = sqlx::query_scalar!(
+                r#"INSERT INTO devices (name, mac_address, group_id, wake_order, wake_delay_secs, enabled)
+                   VALUES ('pc', 'AA:BB:CC:DD:EE:FF', ?, ?, 5, ?) RETURNING id as "id!""#,
+                group_id,
+                wake_order,
+                enabled
+            )
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+            ids.push(id);
+        }
+
+        let (status, body) = call(&state, Method::POST, &format!("/api/groups/{group_id}/wake?stream=true"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let events: Vec<&str> = body.lines().filter_map(|line| line.strip_prefix("event: ")).collect();
+        assert_eq!(events, ["result", "result", "result", "done"]);
+        let results: Vec<serde_json::Value> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter(|data| !data.is_empty())
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+        let reported: Vec<(i64, bool)> = results.iter().map(|r| (r["device_id"].as_i64().unwrap(), r["sent"].as_bool().unwrap())).collect();
+        assert_eq!(reported, [(ids[0], true), (ids[1], false), (ids[2], true)]);
+    }
+}
@@ -1,2 +1,10 @@
 pub mod users;
 pub mod devices;
+pub mod groups;
+pub mod scenes;
+pub mod activity;
+pub mod audit_log;
+pub mod config;
+pub mod backup;
+pub mod pagination;
+pub mod validation;
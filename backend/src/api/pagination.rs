@@ -0,0 +1,59 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+pub const TOTAL_COUNT_HEADER: HeaderName = HeaderName::from_static("x-total-count");
+pub const PAGE_LIMIT_HEADER: HeaderName = HeaderName::from_static("x-page-limit");
+pub const PAGE_OFFSET_HEADER: HeaderName = HeaderName::from_static("x-page-offset");
+
+/// Optional `limit`/`offset` query parameters shared by list endpoints.
+/// Without `limit` the whole list is returned.
+#[derive(Deserialize, IntoParams, Default)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    /// Maximum number of items to return
+    pub limit: Option<i64>,
+    /// Number of items to skip
+    pub offset: Option<i64>,
+}
+
+/// A validated page request
+pub struct Page {
+    limit: Option<i64>,
+    offset: i64,
+}
+
+impl Page {
+    pub fn new(limit: Option<i64>, offset: Option<i64>) -> Result<Self, &'static str> {
+        if limit.is_some_and(|l| l < 1) {
+            return Err("limit must be at least 1");
+        }
+        if offset.is_some_and(|o| o < 0) {
+            return Err("offset must not be negative");
+        }
+        if offset.is_some() && limit.is_none() {
+            return Err("offset requires limit");
+        }
+        Ok(Page { limit, offset: offset.unwrap_or(0) })
+    }
+
+    /// Value for a SQLite `LIMIT ?` bind; -1 means no limit
+    pub fn sql_limit(&self) -> i64 {
+        self.limit.unwrap_or(-1)
+    }
+
+    pub fn sql_offset(&self) -> i64 {
+        self.offset
+    }
+
+    /// `X-Total-Count` always, `X-Page-Limit`/`X-Page-Offset` when a page was requested
+    pub fn headers(&self, total: i64) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(TOTAL_COUNT_HEADER, HeaderValue::from(total));
+        if let Some(limit) = self.limit {
+            headers.insert(PAGE_LIMIT_HEADER, HeaderValue::from(limit));
+            headers.insert(PAGE_OFFSET_HEADER, HeaderValue::from(self.offset));
+        }
+        headers
+    }
+}
@@ -0,0 +1,498 @@
+use crate::audit;
+use crate::db::AppState;
+use crate::error::db_error;
+use crate::api::devices::MAINTENANCE_MODE;
+use crate::api::groups::{wake_members, GroupWakeResult, WakeMember};
+use crate::api::validation::{check_batch_size, reject_control_chars};
+use crate::auth::{AuthUser, AdminUser};
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Sqlite, Transaction};
+use std::collections::{HashMap, HashSet};
+use tokio::sync::mpsc;
+use utoipa::{OpenApi, ToSchema};
+
+// ==========================================
+// 1. DTOs
+// ==========================================
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateSceneRequest {
+    pub name: String,
+    /// Devices to wake, in wake order
+    #[serde(default)]
+    pub device_ids: Vec<i64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateSceneRequest {
+    pub name: Option<String>,
+    /// Replaces the members when present
+    pub device_ids: Option<Vec<i64>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SceneMember {
+    pub device_id: i64,
+    pub name: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SceneResponse {
+    pub id: i64,
+    pub name: String,
+    /// In wake order
+    pub members: Vec<SceneMember>,
+}
+
+// ==========================================
+// 2. HELPER FUNCTIONS
+// ==========================================
+
+fn validate_device_ids(state: &AppState, device_ids: &[i64]) -> Result<(), (StatusCode, String)> {
+    if let Err(e) = check_batch_size(&state.config, "device_ids", device_ids.len()) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, e));
+    }
+    let mut seen = HashSet::new();
+    for id in device_ids {
+        if !seen.insert(*id) {
+            return Err((StatusCode::BAD_REQUEST, format!("Device {} listed twice", id)));
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the scene's members. Returns the first listed device that doesn't exist, if any.
+async fn set_members(
+    tx: &mut Transaction<'_, Sqlite>,
+    scene_id: i64,
+    device_ids: &[i64],
+) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query!("DELETE FROM scene_devices WHERE scene_id = ?", scene_id)
+        .execute(&mut **tx)
+        .await?;
+
+    for (position, device_id) in device_ids.iter().enumerate() {
+        let position = position as i64;
+        let inserted = sqlx::query!(
+            "INSERT INTO scene_devices (scene_id, device_id, position) SELECT ?, id, ? FROM devices WHERE id = ?",
+            scene_id,
+            position,
+            device_id
+        )
+        .execute(&mut **tx)
+        .await?;
+        if inserted.rows_affected() == 0 {
+            return Ok(Some(*device_id));
+        }
+    }
+    Ok(None)
+}
+
+async fn fetch_scene(state: &AppState, id: i64) -> Result<Option<SceneResponse>, sqlx::Error> {
+    let Some(scene) = sqlx::query!(r#"SELECT id as "id!", name FROM scenes WHERE id = ?"#, id)
+        .fetch_optional(&state.db)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let members = sqlx::query_as!(
+        SceneMember,
+        r#"SELECT d.id as "device_id!", d.name
+           FROM scene_devices sd JOIN devices d ON d.id = sd.device_id
+           WHERE sd.scene_id = ?
+           ORDER BY sd.position, d.id"#,
+        id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Some(SceneResponse {
+        id: scene.id,
+        name: scene.name,
+        members,
+    }))
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.to_string().contains("UNIQUE")
+}
+
+// ==========================================
+// 3. HANDLERS
+// ==========================================
+
+/// GET /api/scenes
+#[utoipa::path(
+    get,
+    path = "/api/scenes",
+    tag = "scenes",
+    responses(
+        (status = 200, description = "All scenes with their members", body = [SceneResponse])
+    )
+)]
+pub async fn list_scenes(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let scenes = match sqlx::query!(r#"SELECT id as "id!", name FROM scenes ORDER BY name"#)
+        .fetch_all(&state.db)
+        .await
+    {
+        Ok(s) => s,
+        Err(e) => return db_error(&e, "Failed to fetch scenes"),
+    };
+
+    // Members owned by other users are left out for non-admins
+    let is_admin = auth.is_admin();
+    let rows = match sqlx::query!(
+        r#"SELECT sd.scene_id, d.id as "device_id!", d.name
+           FROM scene_devices sd JOIN devices d ON d.id = sd.device_id
+           WHERE ? OR d.owner_id IS NULL OR d.owner_id = ?
+           ORDER BY sd.position, d.id"#,
+        is_admin,
+        auth.id
+    )
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(r) => r,
+        Err(e) => return db_error(&e, "Failed to fetch scenes"),
+    };
+
+    let mut members: HashMap<i64, Vec<SceneMember>> = HashMap::new();
+    for row in rows {
+        members.entry(row.scene_id).or_default().push(SceneMember {
+            device_id: row.device_id,
+            name: row.name,
+        });
+    }
+
+    let res: Vec<SceneResponse> = scenes
+        .into_iter()
+        .map(|s| SceneResponse {
+            members: members.remove(&s.id).unwrap_or_default(),
+            id: s.id,
+            name: s.name,
+        })
+        .collect();
+    Json(res).into_response()
+}
+
+/// POST /api/scenes
+#[utoipa::path(
+    post,
+    path = "/api/scenes",
+    request_body = CreateSceneRequest,
+    tag = "scenes",
+    responses(
+        (status = 201, description = "Scene created", body = SceneResponse,
+            headers(("Location" = String, description = "URL of the created scene"))),
+        (status = 400, description = "Invalid name or duplicate device"),
+        (status = 404, description = "Device not found"),
+        (status = 409, description = "Name taken"),
+        (status = 422, description = "Too many devices")
+    )
+)]
+pub async fn create_scene(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateSceneRequest>,
+) -> impl IntoResponse {
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Scene name must not be empty").into_response();
+    }
+    if let Err(e) = reject_control_chars("name", name) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+    if let Err(e) = validate_device_ids(&state, &payload.device_ids) {
+        return e.into_response();
+    }
+
+    let mut tx = match state.db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(&e, "Failed to create scene"),
+    };
+
+    let id = match sqlx::query_scalar!(r#"INSERT INTO scenes (name) VALUES (?) RETURNING id as "id!""#, name)
+        .fetch_one(&mut *tx)
+        .await
+    {
+        Ok(id) => id,
+        Err(e) if is_unique_violation(&e) => return (StatusCode::CONFLICT, "Scene name already exists").into_response(),
+        Err(e) => return db_error(&e, "Failed to create scene"),
+    };
+
+    match set_members(&mut tx, id, &payload.device_ids).await {
+        Ok(None) => {}
+        Ok(Some(device_id)) => return (StatusCode::NOT_FOUND, format!("Device {} not found", device_id)).into_response(),
+        Err(e) => return db_error(&e, "Failed to create scene"),
+    }
+
+    if let Err(e) = tx.commit().await {
+        return db_error(&e, "Failed to create scene");
+    }
+
+    match fetch_scene(&state, id).await {
+        Ok(Some(s)) => {
+            let location = format!("/api/scenes/{}", s.id);
+            (StatusCode::CREATED, [(header::LOCATION, location)], Json(s)).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Scene not found").into_response(),
+        Err(e) => db_error(&e, "Database error"),
+    }
+}
+
+/// PUT /api/scenes/:id
+#[utoipa::path(
+    put,
+    path = "/api/scenes/{id}",
+    params(
+        ("id" = i64, Path, description = "Scene ID")
+    ),
+    request_body = UpdateSceneRequest,
+    tag = "scenes",
+    responses(
+        (status = 200, description = "Scene updated", body = SceneResponse),
+        (status = 400, description = "Invalid name or duplicate device"),
+        (status = 404, description = "Scene or device not found"),
+        (status = 409, description = "Name taken"),
+        (status = 422, description = "Too many devices")
+    )
+)]
+pub async fn update_scene(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<UpdateSceneRequest>,
+) -> impl IntoResponse {
+    let name = payload.name.as_deref().map(str::trim);
+    if name == Some("") {
+        return (StatusCode::BAD_REQUEST, "Scene name must not be empty").into_response();
+    }
+    if let Some(name) = name
+        && let Err(e) = reject_control_chars("name", name)
+    {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+    if let Some(device_ids) = &payload.device_ids
+        && let Err(e) = validate_device_ids(&state, device_ids)
+    {
+        return e.into_response();
+    }
+
+    let mut tx = match state.db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(&e, "Failed to update scene"),
+    };
+
+    let result = sqlx::query!("UPDATE scenes SET name = COALESCE(?, name) WHERE id = ?", name, id)
+        .execute(&mut *tx)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => return (StatusCode::NOT_FOUND, "Scene not found").into_response(),
+        Ok(_) => {}
+        Err(e) if is_unique_violation(&e) => return (StatusCode::CONFLICT, "Scene name already exists").into_response(),
+        Err(e) => return db_error(&e, "Failed to update scene"),
+    }
+
+    if let Some(device_ids) = &payload.device_ids {
+        match set_members(&mut tx, id, device_ids).await {
+            Ok(None) => {}
+            Ok(Some(device_id)) => return (StatusCode::NOT_FOUND, format!("Device {} not found", device_id)).into_response(),
+            Err(e) => return db_error(&e, "Failed to update scene"),
+        }
+    }
+
+    if let Err(e) = tx.commit().await {
+        return db_error(&e, "Failed to update scene");
+    }
+
+    match fetch_scene(&state, id).await {
+        Ok(Some(s)) => Json(s).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Scene not found").into_response(),
+        Err(e) => db_error(&e, "Database error"),
+    }
+}
+
+/// DELETE /api/scenes/:id
+/// The member devices are not affected
+#[utoipa::path(
+    delete,
+    path = "/api/scenes/{id}",
+    params(
+        ("id" = i64, Path, description = "Scene ID")
+    ),
+    tag = "scenes",
+    responses(
+        (status = 200, description = "Scene deleted"),
+        (status = 404, description = "Scene not found")
+    )
+)]
+pub async fn delete_scene(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    let result = sqlx::query!("DELETE FROM scenes WHERE id = ?", id)
+        .execute(&state.db)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Scene not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Scene deleted").into_response(),
+        Err(e) => db_error(&e, "Failed to delete scene"),
+    }
+}
+
+/// POST /api/scenes/:id/activate
+/// Wakes every member at once, like a non-sequential group
+#[utoipa::path(
+    post,
+    path = "/api/scenes/{id}/activate",
+    params(
+        ("id" = i64, Path, description = "Scene ID")
+    ),
+    tag = "scenes",
+    responses(
+        (status = 200, description = "Per-device results, in wake order", body = [GroupWakeResult]),
+        (status = 404, description = "Scene not found"),
+        (status = 503, description = "Maintenance mode")
+    )
+)]
+pub async fn activate_scene(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if state.in_maintenance() {
+        return (StatusCode::SERVICE_UNAVAILABLE, MAINTENANCE_MODE).into_response();
+    }
+
+    let name = match sqlx::query_scalar!("SELECT name FROM scenes WHERE id = ?", id)
+        .fetch_optional(&state.db)
+        .await
+    {
+        Ok(Some(n)) => n,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Scene not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
+    };
+
+    // Members owned by other users are left out for non-admins
+    let is_admin = auth.is_admin();
+    let members = match sqlx::query_as!(
+        WakeMember,
+        r#"SELECT d.id as "id!", d.mac_address, d.broadcast_addr, 0 as "wake_delay_secs!: i64", d.enabled, d.wol_ports
+           FROM scene_devices sd JOIN devices d ON d.id = sd.device_id
+           WHERE sd.scene_id = ?
+             AND (? OR d.owner_id IS NULL OR d.owner_id = ?)
+           ORDER BY sd.position, d.id"#,
+        id,
+        is_admin,
+        auth.id
+    )
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(m) => m,
+        Err(e) => return db_error(&e, "Failed to fetch scene members"),
+    };
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    wake_members(state.clone(), false, members, tx).await;
+    let mut results = Vec::new();
+    while let Ok(result) = rx.try_recv() {
+        results.push(result);
+    }
+
+    let sent = results.iter().filter(|r| r.sent).count();
+    let details = format!("scene={} name={} sent={}/{}", id, name, sent, results.len());
+    audit::record(&state.db, Some(auth.id), audit::ACTION_SCENE_ACTIVATED, None, None, Some(&details)).await;
+
+    Json(results).into_response()
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        list_scenes,
+        create_scene,
+        update_scene,
+        delete_scene,
+        activate_scene
+    ),
+    components(
+        schemas(
+            CreateSceneRequest,
+            UpdateSceneRequest,
+            SceneMember,
+            SceneResponse
+        )
+    ),
+    tags(
+        (name = "scenes", description = "Scene endpoints")
+    )
+)]
+pub struct SceneApi;
+
+#[cfg(test)]
+mod tests {
+    use crate::audit;
+    use crate::test_support::{call, device, recording_state, state, user};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn scenes_hide_other_users_devices() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let (alice_id, _) = user(&state, "alice", "user").await;
+        let (_, bob) = user(&state, "bob", "user").await;
+        let private = device(&state, "alice-laptop", Some(alice_id)).await;
+        let shared = device(&state, "printer", None).await;
+        let scene = json!({ "name": "evening", "device_ids": [private, shared] });
+        let (status, _) = call(&state, Method::POST, "/api/scenes", Some(&admin), Some(scene)).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (_, body) = call(&state, Method::GET, "/api/scenes", Some(&bob), None).await;
+        let scenes: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(scenes[0]["members"].as_array().unwrap().len(), 1);
+        assert_eq!(scenes[0]["members"][0]["device_id"], shared);
+    }
+
+    #[tokio::test]
+    async fn activating_wakes_exactly_the_members() {
+        let (state, sender) = recording_state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let mut ids = Vec::new();
+        for mac in ["00:00:00:00:00:01", "00:00:00:00:00:02", "00:00:00:00:00:03"] {
+            let id = sqlx::query_scalar!(r#"INSERT INTO devices (name, mac_address) VALUES (?, ?) RETURNING id as "id!""#, mac, mac)
+                .fetch_one(&state.db)
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        let scene = json!({ "name": "Movie Night", "device_ids": [ids[2], ids[0]] });
+        let (_, body) = call(&state, Method::POST, "/api/scenes", Some(&admin), Some(scene)).await;
+        let scene_id = serde_json::from_str::<serde_json::Value>(&body).unwrap()["id"].as_i64().unwrap();
+
+        let (status, _) = call(&state, Method::POST, &format!("/api/scenes/{scene_id}/activate"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let woken: Vec<u8> = sender.sent.lock().unwrap().iter().map(|p| p.mac[5]).collect();
+        assert_eq!(woken, [3, 1]);
+        let details = sqlx::query_scalar!("SELECT details FROM audit_log WHERE action = ?", audit::ACTION_SCENE_ACTIVATED)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(details.unwrap(), format!("scene={scene_id} name=Movie Night sent=2/2"));
+    }
+}
@@ -1,25 +1,38 @@
+use crate::config::Config;
 use crate::db::AppState;
-use crate::auth::{AuthUser, AdminUser, create_jwt, generate_refresh_token};
+use crate::error::db_error;
+use crate::api::pagination::{Page, PageQuery};
+use crate::api::validation::reject_control_chars;
+use crate::audit;
+use crate::client_ip;
+use crate::auth::{AuthUser, AdminUser, create_impersonation_jwt, create_jwt, generate_refresh_token, role_capabilities};
 use argon2::{
-    Argon2,
+    Algorithm, Argon2, Params, Version,
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
 };
 use axum::{
     Json,
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
     response::IntoResponse,
 };
 use chrono::{NaiveDateTime, TimeZone};
 use rand_core::OsRng;
 use rand::distr::{Alphanumeric, SampleString};
 use serde::{Deserialize, Serialize};
-use utoipa::{OpenApi, ToSchema};
+use std::sync::OnceLock;
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
 // ==========================================
 // 1. DTOs
 // ==========================================
 
+#[derive(Deserialize, ToSchema)]
+pub struct SetupRequest {
+    pub username: String,
+    pub password: String,
+}
+
 #[derive(Deserialize, ToSchema)]
 pub struct CreateUserRequest {
     pub username: String,
@@ -43,6 +56,11 @@ pub struct RefreshTokenResponse {
     pub refresh_token: String,
 }
 
+#[derive(Serialize, ToSchema)]
+pub struct LogoutAllResponse {
+    pub revoked: u64,
+}
+
 #[derive(Deserialize, ToSchema)]
 pub struct UpdateRoleRequest {
     pub role: String, // 'admin' or 'user'
@@ -80,6 +98,29 @@ pub struct UserResponse {
     pub is_disabled: bool,
 }
 
+#[derive(Serialize, ToSchema)]
+pub struct MeResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    /// What the user's role allows, e.g. `wake`, `manage_users`
+    pub permissions: Vec<String>,
+}
+
+/// Admin view of a user, including login failure and lockout state
+#[derive(Serialize, ToSchema)]
+pub struct UserDetailResponse {
+    pub id: i64,
+    pub username: String,
+    pub role: String,
+    pub last_login_at: Option<NaiveDateTime>,
+    pub force_password_change: bool,
+    pub is_disabled: bool,
+    pub failed_login_attempts: i64,
+    pub last_failed_login_at: Option<NaiveDateTime>,
+    /// Logins are refused until this time (UTC)
+    pub locked_until: Option<NaiveDateTime>,
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct CreateUserResponse {
     pub message: String,
@@ -95,35 +136,221 @@ pub struct LoginResponse {
     pub refresh_token: String,
 }
 
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UsernameAvailabilityQuery {
+    /// Compared case-insensitively, like usernames at creation
+    pub username: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UsernameAvailabilityResponse {
+    pub available: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImpersonateResponse {
+    pub user: UserResponse,
+    /// Short-lived access token; no refresh token is issued
+    pub access_token: String,
+    pub expires_in: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserStatsResponse {
+    pub devices_owned: i64,
+    /// Device, ad-hoc, scheduled and scene wakes attributed to the user
+    pub wakes_total: i64,
+    pub wakes_last_7d: i64,
+    /// UTC; `null` if the user never woke anything
+    pub last_wake_at: Option<NaiveDateTime>,
+}
+
 // ==========================================
 // 2. HELPER FUNCTIONS (Service Logic)
 // ==========================================
 
-pub fn hash_password(password: &str) -> Result<String, String> {
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+/// Deletes the user's oldest refresh tokens beyond `max_sessions_per_user`.
+/// Refreshing re-inserts a token, so the oldest one is the least recently used session.
+async fn prune_sessions(state: &AppState, user_id: i64) {
+    let Some(max) = state.config.max_sessions_per_user else {
+        return;
+    };
+
+    let _ = sqlx::query!(
+        "DELETE FROM refresh_tokens WHERE user_id = ? AND rowid NOT IN (
+            SELECT rowid FROM refresh_tokens WHERE user_id = ?
+            ORDER BY created_at DESC, rowid DESC
+            LIMIT ?
+        )",
+        user_id,
+        user_id,
+        max
+    )
+    .execute(&state.db)
+    .await;
+}
+
+/// Argon2 instance for new hashes, with the configured `ARGON2_*` parameters.
+/// Existing hashes embed their own parameters, so they keep verifying after a change.
+fn argon2(config: &Config) -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, config.argon2_params())
+}
+
+pub fn hash_password(config: &Config, password: &str) -> Result<String, String> {
     let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
 
-    argon2
+    argon2(config)
         .hash_password(password.as_bytes(), &salt)
         .map(|hash| hash.to_string())
         .map_err(|e| e.to_string())
 }
 
-fn verify_password(password: &str, password_hash: &str) -> bool {
+fn verify_password(config: &Config, password: &str, password_hash: &str) -> bool {
     let parsed_hash = match PasswordHash::new(password_hash) {
         Ok(h) => h,
         Err(_) => return false,
     };
 
-    Argon2::default()
+    argon2(config)
         .verify_password(password.as_bytes(), &parsed_hash)
         .is_ok()
 }
 
+/// Verifies `password` against a throwaway hash made with the current argon2 settings,
+/// so a login for an unknown username costs as much as one for a real user
+/// and response timing does not reveal which usernames exist.
+fn dummy_verify_password(config: &Config, password: &str) {
+    let _ = verify_password(config, password, init_dummy_hash(config));
+}
+
+/// Creates the hash [`dummy_verify_password`] checks against, if it doesn't exist yet.
+/// Called at startup so the first login for an unknown username isn't the one paying for it.
+pub fn init_dummy_hash(config: &Config) -> &'static str {
+    DUMMY_HASH.get_or_init(|| {
+        hash_password(config, "timing-equalizer").expect("Failed to hash dummy password")
+    })
+}
+
+/// Minimum bar for passwords users choose themselves:
+/// at least 8 characters with at least one letter and one digit.
+pub fn validate_password_strength(password: &str) -> Result<(), &'static str> {
+    if password.chars().count() < 8 {
+        return Err("Password must be at least 8 characters long");
+    }
+    if !password.chars().any(char::is_alphabetic) || !password.chars().any(|c| c.is_ascii_digit()) {
+        return Err("Password must contain at least one letter and one digit");
+    }
+    Ok(())
+}
+
+/// The bootstrap admin password is held to a higher bar than user-chosen ones:
+/// the regular strength rules plus at least 12 characters.
+pub fn validate_admin_password(password: &str) -> Result<(), &'static str> {
+    validate_password_strength(password)?;
+    if password.chars().count() < 12 {
+        return Err("Admin password must be at least 12 characters long");
+    }
+    Ok(())
+}
+
+/// True when `password_hash` was produced with different argon2 settings
+/// than the ones currently configured for new hashes.
+fn needs_rehash(config: &Config, password_hash: &str) -> bool {
+    let parsed_hash = match PasswordHash::new(password_hash) {
+        Ok(h) => h,
+        Err(_) => return false,
+    };
+
+    let current = config.argon2_params();
+    let same_algorithm = Algorithm::try_from(parsed_hash.algorithm) == Ok(Algorithm::Argon2id);
+    let same_version = parsed_hash.version == Some(Version::V0x13.into());
+    let same_params = Params::try_from(&parsed_hash)
+        .map(|p| {
+            p.m_cost() == current.m_cost()
+                && p.t_cost() == current.t_cost()
+                && p.p_cost() == current.p_cost()
+        })
+        .unwrap_or(false);
+
+    !(same_algorithm && same_version && same_params)
+}
+
+/// `WELCOME_MESSAGE_TEMPLATE` filled in for a user and their generated password, if configured
+fn welcome_message(config: &Config, username: &str, password: &str) -> Option<String> {
+    config
+        .welcome_message_template
+        .as_ref()
+        .map(|template| template.replace("{username}", username).replace("{password}", password))
+}
+
 // ==========================================
 // 3. HANDLERS (Controllers)
 // ==========================================
 
+/// POST /api/setup
+/// Creates the initial admin on a fresh install; refused once any user exists
+#[utoipa::path(
+    post,
+    path = "/api/setup",
+    request_body = SetupRequest,
+    tag = "users",
+    security(),
+    responses(
+        (status = 201, description = "Initial admin created", body = UserResponse),
+        (status = 400, description = "Invalid username or weak password"),
+        (status = 409, description = "Setup already completed")
+    )
+)]
+pub async fn setup(
+    State(state): State<AppState>,
+    Json(payload): Json<SetupRequest>,
+) -> impl IntoResponse {
+    let username = payload.username.trim().to_lowercase();
+    if username.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Username must not be empty").into_response();
+    }
+    if let Err(e) = reject_control_chars("username", &username) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+    if let Err(e) = validate_admin_password(&payload.password) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    let password_hash = match hash_password(&state.config, &payload.password) {
+        Ok(h) => h,
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password").into_response(),
+    };
+
+    // Single statement so two concurrent setups can't both succeed
+    let user = sqlx::query_as!(
+        UserResponse,
+        r#"
+            INSERT INTO users (username, password_hash, role, force_password_change)
+            SELECT ?, ?, 'admin', 0
+            WHERE NOT EXISTS (SELECT 1 FROM users)
+            RETURNING id as "id!", username as "username!", role as "role!", last_login_at,
+                force_password_change as "force_password_change!", is_disabled as "is_disabled!"
+        "#,
+        username,
+        password_hash
+    )
+    .fetch_optional(&state.db)
+    .await;
+
+    match user {
+        Ok(Some(user)) => {
+            println!("Initial admin '{}' created via setup", user.username);
+            let location = format!("/api/users/{}", user.id);
+            (StatusCode::CREATED, [(header::LOCATION, location)], Json(user)).into_response()
+        }
+        Ok(None) => (StatusCode::CONFLICT, "Setup already completed").into_response(),
+        Err(e) => db_error(&e, "Failed to create admin"),
+    }
+}
+
 /// POST /api/users
 #[utoipa::path(
     post,
@@ -131,13 +358,14 @@ fn verify_password(password: &str, password_hash: &str) -> bool {
     request_body = CreateUserRequest,
     tag = "users",
     responses(
-        (status = 201, description = "User created", body = CreateUserResponse),
+        (status = 201, description = "User created", body = CreateUserResponse,
+            headers(("Location" = String, description = "URL of the created user"))),
         (status = 409, description = "Username taken"),
         (status = 500, description = "Server error")
     )
 )]
 pub async fn create_user(
-    _admin: AdminUser,
+    admin: AdminUser,
     State(state): State<AppState>,
     Json(payload): Json<CreateUserRequest>,
 ) -> impl IntoResponse {
@@ -146,9 +374,12 @@ pub async fn create_user(
 
     // Ensure username is lowercase
     let username = payload.username.to_lowercase();
+    if let Err(e) = reject_control_chars("username", &username) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
 
     // 1. Hash the password
-    let password_hash = match hash_password(&password.to_string()) {
+    let password_hash = match hash_password(&state.config, &password.to_string()) {
         Ok(h) => h,
         Err(_) => {
             return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password").into_response();
@@ -170,8 +401,11 @@ pub async fn create_user(
 
     match user_result {
         Ok(user) => {
+            audit::record(&state.db, Some(admin.0.id), audit::ACTION_USER_CREATED, Some(user.id), None, None).await;
+            let message = welcome_message(&state.config, &user.username, &password)
+                .unwrap_or_else(|| "User created successfully".to_string());
             let resp = CreateUserResponse {
-                message: "User created successfully".to_string(),
+                message,
                 user: UserResponse {
                     id: user.id,
                     username: user.username,
@@ -182,13 +416,14 @@ pub async fn create_user(
                 },
                 password: password.clone(),
             };
-            (StatusCode::CREATED, Json(resp)).into_response()
+            let location = format!("/api/users/{}", resp.user.id);
+            (StatusCode::CREATED, [(header::LOCATION, location)], Json(resp)).into_response()
         }
         Err(e) => {
             if e.to_string().contains("UNIQUE") {
                 (StatusCode::CONFLICT, "Username already exists").into_response()
             } else {
-                (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+                db_error(&e, "Database error")
             }
         }
     }
@@ -203,7 +438,8 @@ pub async fn create_user(
     responses(
         (status = 200, description = "Login successful", body = LoginResponse),
         (status = 401, description = "Invalid credentials"),
-        (status = 403, description = "Password change required")
+        (status = 403, description = "Password change required"),
+        (status = 423, description = "Account locked after too many failed logins")
     )
 )]
 pub async fn login(
@@ -214,17 +450,20 @@ pub async fn login(
 
     // 1. Fetch user by username
     let user = sqlx::query!(
-        r#"SELECT id as "id!", username, password_hash, role, last_login_at, force_password_change, is_disabled
+        r#"SELECT id as "id!", username, password_hash, role, last_login_at, force_password_change, is_disabled, locked_until
          FROM users WHERE username = ?"#,
         username
     )
     .fetch_optional(&state.db)
-    .await
-    .unwrap_or(None);
+    .await;
 
     let user = match user {
-        Some(u) => u,
-        None => return (StatusCode::UNAUTHORIZED, "Invalid credentials").into_response(),
+        Ok(Some(u)) => u,
+        Ok(None) => {
+            dummy_verify_password(&state.config, &payload.password);
+            return (StatusCode::UNAUTHORIZED, "Invalid credentials").into_response();
+        }
+        Err(e) => return db_error(&e, "Database error"),
     };
 
     if user.is_disabled {
@@ -235,58 +474,103 @@ pub async fn login(
             .into_response();
     }
 
+    if let Some(locked_until) = user.locked_until
+        && locked_until > chrono::Utc::now().naive_utc()
+    {
+        return (
+            StatusCode::LOCKED,
+            Json(serde_json::json!({ "error": "Account locked", "locked_until": locked_until })),
+        )
+            .into_response();
+    }
+
     // 2. Check if password change is required (before password verification)
     // Actually, user MUST be able to login to change password.
     // So we should ALLOW login but user will have `force_password_change: true`.
     // The frontend should redirect them to change password page.
     
     // 3. Verify Password
-    if !verify_password(&payload.password, &user.password_hash) {
-        // Increment failed attempts (optional logic here)
-        let _ = sqlx::query!(
-            "UPDATE users SET failed_login_attempts = failed_login_attempts + 1 WHERE id = ?",
+    if !verify_password(&state.config, &payload.password, &user.password_hash) {
+        // Count recent failures only: restart from 1 when the previous one is outside the window
+        let window = format!("-{} minutes", state.config.failed_login_window_minutes);
+        let attempts = sqlx::query_scalar!(
+            "UPDATE users SET
+                failed_login_attempts = CASE
+                    WHEN last_failed_login_at IS NULL OR last_failed_login_at < datetime('now', ?) THEN 1
+                    ELSE failed_login_attempts + 1
+                END,
+                last_failed_login_at = CURRENT_TIMESTAMP
+             WHERE id = ?
+             RETURNING failed_login_attempts",
+            window,
             user.id
         )
-        .execute(&state.db)
+        .fetch_one(&state.db)
         .await;
 
+        let threshold = state.config.lockout_threshold;
+        if threshold > 0 && attempts.is_ok_and(|a| a >= i64::from(threshold)) {
+            let lock_for = format!("+{} minutes", state.config.lockout_minutes);
+            let _ = sqlx::query!(
+                "UPDATE users SET locked_until = datetime('now', ?) WHERE id = ?",
+                lock_for,
+                user.id
+            )
+            .execute(&state.db)
+            .await;
+            let from = client_ip::current().map(|ip| format!(" (last from {})", ip)).unwrap_or_default();
+            println!("User '{}' locked out after {} failed logins{}", user.username, threshold, from);
+        }
+
         return (StatusCode::UNAUTHORIZED, "Invalid credentials").into_response();
     }
 
+    // Upgrade hashes created with older argon2 settings while we have the plaintext
+    if needs_rehash(&state.config, &user.password_hash)
+        && let Ok(new_hash) = hash_password(&state.config, &payload.password)
+    {
+        let _ = sqlx::query!(
+            "UPDATE users SET password_hash = ? WHERE id = ?",
+            new_hash,
+            user.id
+        )
+        .execute(&state.db)
+        .await;
+    }
+
     // 4. Success: Reset failed attempts & Update last login
     let _ = sqlx::query!(
-        "UPDATE users SET failed_login_attempts = 0, last_login_at = CURRENT_TIMESTAMP WHERE id = ?",
+        "UPDATE users SET failed_login_attempts = 0, locked_until = NULL, last_login_at = CURRENT_TIMESTAMP WHERE id = ?",
         user.id
     )
     .execute(&state.db)
     .await;
+    audit::record(&state.db, Some(user.id), audit::ACTION_LOGIN, None, None, None).await;
 
     // 5. Generate Tokens
     // Access Token: 15 minutes
-    let access_token = match create_jwt(user.id, &user.username, &user.role, chrono::Duration::minutes(15)) {
+    let access_token = match create_jwt(&state.config, user.id, &user.username, &user.role, chrono::Duration::minutes(15)) {
         Ok(t) => t,
         Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate token").into_response(),
     };
 
     // Refresh Token
     let refresh_token = generate_refresh_token();
-    let duration = if payload.remember_me.unwrap_or(false) {
-        chrono::Duration::days(30)
-    } else {
-        chrono::Duration::days(1)
-    };
-    let refresh_expires_at = chrono::Utc::now() + duration;
+    let remember_me = payload.remember_me.unwrap_or(false);
+    let refresh_expires_at = chrono::Utc::now() + state.config.session_duration(remember_me);
 
     // Store Refresh Token in DB
     // Ideally we hash it, but for simplicity we store as is (it's high entropy)
     let _ = sqlx::query!(
-        "INSERT INTO refresh_tokens (token_hash, user_id, expires_at) VALUES (?, ?, ?)",
+        "INSERT INTO refresh_tokens (token_hash, user_id, expires_at, remember_me, last_used_at) VALUES (?, ?, ?, ?, CURRENT_TIMESTAMP)",
         refresh_token,
         user.id,
-        refresh_expires_at
+        refresh_expires_at,
+        remember_me
     )
     .execute(&state.db)
     .await;
+    prune_sessions(&state, user.id).await;
 
     // 6. Return User Info
     let response = LoginResponse {
@@ -310,25 +594,245 @@ pub async fn login(
 #[utoipa::path(
     get,
     path = "/api/users",
+    params(PageQuery),
     tag = "users",
     responses(
-        (status = 200, description = "List all users", body = [UserResponse])
+        (status = 200, description = "List all users", body = [UserResponse],
+            headers(
+                ("X-Total-Count" = i64, description = "Number of users"),
+                ("X-Page-Limit" = i64, description = "Requested limit, when paginating"),
+                ("X-Page-Offset" = i64, description = "Requested offset, when paginating")
+            )),
+        (status = 400, description = "Invalid pagination")
     )
 )]
 pub async fn list_users(
     _admin: AdminUser,
-    State(state): State<AppState>
+    State(state): State<AppState>,
+    Query(query): Query<PageQuery>,
 ) -> impl IntoResponse {
+    let page = match Page::new(query.limit, query.offset) {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let total = match sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!: i64" FROM users"#)
+        .fetch_one(&state.db)
+        .await
+    {
+        Ok(t) => t,
+        Err(e) => return db_error(&e, "Failed to fetch users"),
+    };
+
+    let limit = page.sql_limit();
+    let offset = page.sql_offset();
     let users = sqlx::query_as!(
         UserResponse,
-        "SELECT id, username, role, last_login_at, force_password_change, is_disabled FROM users"
+        "SELECT id, username, role, last_login_at, force_password_change, is_disabled FROM users ORDER BY id LIMIT ? OFFSET ?",
+        limit,
+        offset
     )
     .fetch_all(&state.db)
     .await;
 
     match users {
-        Ok(u) => Json(u).into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch users").into_response(),
+        Ok(u) => (page.headers(total), Json(u)).into_response(),
+        Err(e) => db_error(&e, "Failed to fetch users"),
+    }
+}
+
+/// GET /api/users/available
+/// Lets the admin user form check a username before submitting it
+#[utoipa::path(
+    get,
+    path = "/api/users/available",
+    params(UsernameAvailabilityQuery),
+    tag = "users",
+    responses(
+        (status = 200, description = "Whether the username is free", body = UsernameAvailabilityResponse)
+    )
+)]
+pub async fn username_available(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Query(query): Query<UsernameAvailabilityQuery>,
+) -> impl IntoResponse {
+    let username = query.username.to_lowercase();
+    let taken = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM users WHERE username = ?) as "taken!: bool""#,
+        username
+    )
+    .fetch_one(&state.db)
+    .await;
+
+    match taken {
+        Ok(taken) => Json(UsernameAvailabilityResponse { available: !taken }).into_response(),
+        Err(e) => db_error(&e, "Database error"),
+    }
+}
+
+/// GET /api/users/:id
+/// Admin view of a single user, including lockout state
+#[utoipa::path(
+    get,
+    path = "/api/users/{id}",
+    params(
+        ("id" = i64, Path, description = "User ID")
+    ),
+    tag = "users",
+    responses(
+        (status = 200, description = "User details", body = UserDetailResponse),
+        (status = 404, description = "User not found")
+    )
+)]
+pub async fn get_user(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+) -> impl IntoResponse {
+    let user = sqlx::query_as!(
+        UserDetailResponse,
+        "SELECT id, username, role, last_login_at, force_password_change, is_disabled,
+                failed_login_attempts, last_failed_login_at, locked_until
+         FROM users WHERE id = ?",
+        user_id
+    )
+    .fetch_optional(&state.db)
+    .await;
+
+    match user {
+        Ok(Some(u)) => Json(u).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => db_error(&e, "Database error"),
+    }
+}
+
+/// GET /api/users/:id/stats
+/// Devices owned and wakes sent by a user. Admins can look up anyone, other users
+/// only themselves.
+#[utoipa::path(
+    get,
+    path = "/api/users/{id}/stats",
+    params(
+        ("id" = i64, Path, description = "User ID")
+    ),
+    tag = "users",
+    responses(
+        (status = 200, description = "User statistics", body = UserStatsResponse),
+        (status = 403, description = "Not your account"),
+        (status = 404, description = "User not found")
+    )
+)]
+pub async fn user_stats(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+) -> impl IntoResponse {
+    if !auth.is_admin() && auth.id != user_id {
+        return (StatusCode::FORBIDDEN, "Not your account").into_response();
+    }
+
+    let stats = sqlx::query_as!(
+        UserStatsResponse,
+        r#"SELECT
+               (SELECT COUNT(*) FROM devices WHERE owner_id = u.id) as "devices_owned!: i64",
+               COUNT(a.id) as "wakes_total!: i64",
+               COALESCE(SUM(a.created_at >= datetime('now', '-7 days')), 0) as "wakes_last_7d!: i64",
+               MAX(a.created_at) as "last_wake_at?: NaiveDateTime"
+           FROM users u
+           LEFT JOIN audit_log a ON a.user_id = u.id AND a.action IN (?, ?, ?, ?, ?)
+           WHERE u.id = ?
+           GROUP BY u.id"#,
+        audit::ACTION_DEVICE_WAKE,
+        audit::ACTION_ADHOC_WAKE,
+        audit::ACTION_SCHEDULED_WAKE,
+        audit::ACTION_SCENE_ACTIVATED,
+        audit::ACTION_GROUP_WAKE,
+        user_id
+    )
+    .fetch_optional(&state.db)
+    .await;
+
+    match stats {
+        Ok(Some(stats)) => Json(stats).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => db_error(&e, "Failed to fetch user statistics"),
+    }
+}
+
+/// POST /api/users/:id/unlock
+/// Lifts a lockout and resets the failed login counter
+#[utoipa::path(
+    post,
+    path = "/api/users/{id}/unlock",
+    params(
+        ("id" = i64, Path, description = "User ID")
+    ),
+    tag = "users",
+    responses(
+        (status = 200, description = "User unlocked"),
+        (status = 404, description = "User not found")
+    )
+)]
+pub async fn unlock_user(
+    _admin: AdminUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+) -> impl IntoResponse {
+    let result = sqlx::query!(
+        "UPDATE users SET locked_until = NULL, failed_login_attempts = 0, last_failed_login_at = NULL WHERE id = ?",
+        user_id
+    )
+    .execute(&state.db)
+    .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Ok(_) => (StatusCode::OK, "User unlocked").into_response(),
+        Err(e) => db_error(&e, "Failed to unlock user"),
+    }
+}
+
+/// POST /api/users/:id/force-logout
+/// Revokes every session (refresh token) of a user, e.g. when offboarding them.
+/// Access tokens already issued stay valid until they expire (15 minutes).
+#[utoipa::path(
+    post,
+    path = "/api/users/{id}/force-logout",
+    params(
+        ("id" = i64, Path, description = "User ID")
+    ),
+    tag = "users",
+    responses(
+        (status = 200, description = "Sessions revoked", body = LogoutAllResponse),
+        (status = 404, description = "User not found")
+    )
+)]
+pub async fn force_logout(
+    admin: AdminUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+) -> impl IntoResponse {
+    match sqlx::query_scalar!("SELECT id FROM users WHERE id = ?", user_id)
+        .fetch_optional(&state.db)
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
+    }
+
+    let result = sqlx::query!("DELETE FROM refresh_tokens WHERE user_id = ?", user_id)
+        .execute(&state.db)
+        .await;
+
+    match result {
+        Ok(r) => {
+            let details = format!("sessions={}", r.rows_affected());
+            audit::record(&state.db, Some(admin.0.id), audit::ACTION_FORCE_LOGOUT, Some(user_id), None, Some(&details)).await;
+            (StatusCode::OK, Json(LogoutAllResponse { revoked: r.rows_affected() })).into_response()
+        }
+        Err(e) => db_error(&e, "Failed to revoke sessions"),
     }
 }
 
@@ -368,8 +872,12 @@ pub async fn update_role(
         Ok(r) if r.rows_affected() == 0 => {
             (StatusCode::NOT_FOUND, "User not found").into_response()
         }
-        Ok(_) => (StatusCode::OK, "Role updated").into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update role").into_response(),
+        Ok(_) => {
+            let details = format!("role={}", payload.role);
+            audit::record(&state.db, Some(admin.0.id), audit::ACTION_ROLE_CHANGED, Some(user_id), None, Some(&details)).await;
+            (StatusCode::OK, "Role updated").into_response()
+        }
+        Err(e) => db_error(&e, "Failed to update role"),
     }
 }
 
@@ -409,8 +917,12 @@ pub async fn update_status(
         Ok(r) if r.rows_affected() == 0 => {
             (StatusCode::NOT_FOUND, "User not found").into_response()
         }
-        Ok(_) => (StatusCode::OK, "Status updated").into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update status").into_response(),
+        Ok(_) => {
+            let details = format!("disabled={}", payload.is_disabled);
+            audit::record(&state.db, Some(admin.0.id), audit::ACTION_USER_STATUS_CHANGED, Some(user_id), None, Some(&details)).await;
+            (StatusCode::OK, "Status updated").into_response()
+        }
+        Err(e) => db_error(&e, "Failed to update status"),
     }
 }
 
@@ -430,20 +942,20 @@ pub async fn update_status(
     )
 )]
 pub async fn admin_reset_password(
-    _admin: AdminUser,
+    admin: AdminUser,
     State(state): State<AppState>,
     Path(user_id): Path<i64>,
     Json(payload): Json<AdminResetPasswordRequest>,
 ) -> impl IntoResponse {
 
     let (password_hash, generated_password) = if let Some(p) = &payload.new_password {
-        match hash_password(p) {
+        match hash_password(&state.config, p) {
             Ok(h) => (h, None),
             Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password").into_response(),
         }
     } else {
         let p = Alphanumeric.sample_string(&mut rand::rng(), 12);
-        match hash_password(&p) {
+        match hash_password(&state.config, &p) {
             Ok(h) => (h, Some(p)),
             Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password").into_response(),
         }
@@ -453,31 +965,36 @@ pub async fn admin_reset_password(
     // Spec says: "User accounts should be created by the admins and these get assigned a temp password... On first log in they'd have to type in a new password."
     // If admin resets it, it's effectively a temp password again. So set force_password_change = 1.
     
-    let result = sqlx::query!(
-        "UPDATE users SET password_hash = ?, failed_login_attempts = 0, last_login_at = NULL, force_password_change = 1 WHERE id = ?",
+    let result = sqlx::query_scalar!(
+        "UPDATE users SET password_hash = ?, failed_login_attempts = 0, locked_until = NULL, last_login_at = NULL, force_password_change = 1 WHERE id = ?
+         RETURNING username",
         password_hash,
         user_id
     )
-    .execute(&state.db)
+    .fetch_optional(&state.db)
     .await;
 
     match result {
-        Ok(r) if r.rows_affected() == 0 => {
+        Ok(None) => {
             (StatusCode::NOT_FOUND, "User not found").into_response()
         }
-        Ok(_) => (
-            StatusCode::OK,
-            Json(AdminResetPasswordResponse {
-                message: "Password reset successfully. User must change it on next login.".to_string(),
-                password: generated_password,
-            }),
-        )
-            .into_response(),
-        Err(_) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Failed to reset password",
-        )
-            .into_response(),
+        Ok(Some(username)) => {
+            // Only how the password was chosen, never the password itself
+            let details = if generated_password.is_some() { "generated" } else { "set by admin" };
+            audit::record(&state.db, Some(admin.0.id), audit::ACTION_PASSWORD_RESET, Some(user_id), None, Some(details)).await;
+            (
+                StatusCode::OK,
+                Json(AdminResetPasswordResponse {
+                    message: generated_password
+                        .as_deref()
+                        .and_then(|password| welcome_message(&state.config, &username, password))
+                        .unwrap_or_else(|| "Password reset successfully. User must change it on next login.".to_string()),
+                    password: generated_password,
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => db_error(&e, "Failed to reset password"),
     }
 }
 
@@ -490,7 +1007,8 @@ pub async fn admin_reset_password(
     tag = "users",
     responses(
         (status = 200, description = "Password changed"),
-        (status = 401, description = "Invalid credentials")
+        (status = 401, description = "Invalid credentials"),
+        (status = 403, description = "Not allowed while impersonating")
     )
 )]
 pub async fn change_password(
@@ -498,23 +1016,27 @@ pub async fn change_password(
     State(state): State<AppState>,
     Json(payload): Json<ChangePasswordRequest>,
 ) -> impl IntoResponse {
+    if auth_user.impersonated_by.is_some() {
+        return (StatusCode::FORBIDDEN, "Not allowed while impersonating").into_response();
+    }
+
     // 1. Verify old password
     let user = sqlx::query!("SELECT password_hash FROM users WHERE id = ?", auth_user.id)
         .fetch_optional(&state.db)
-        .await
-        .unwrap_or(None);
+        .await;
         
     let user = match user {
-        Some(u) => u,
-        None => return (StatusCode::UNAUTHORIZED, "User not found").into_response(),
+        Ok(Some(u)) => u,
+        Ok(None) => return (StatusCode::UNAUTHORIZED, "User not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
     };
 
-    if !verify_password(&payload.old_password, &user.password_hash) {
+    if !verify_password(&state.config, &payload.old_password, &user.password_hash) {
         return (StatusCode::UNAUTHORIZED, "Invalid current password").into_response();
     }
 
     // 2. Hash new password
-    let password_hash = match hash_password(&payload.new_password) {
+    let password_hash = match hash_password(&state.config, &payload.new_password) {
         Ok(h) => h,
         Err(_) => {
             return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password").into_response();
@@ -538,11 +1060,7 @@ pub async fn change_password(
             })),
         )
             .into_response(),
-        Err(_) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Failed to change password",
-        )
-            .into_response(),
+        Err(e) => db_error(&e, "Failed to change password"),
     }
 }
 
@@ -569,27 +1087,102 @@ pub async fn delete_user(
         return (StatusCode::FORBIDDEN, "Cannot delete your own account").into_response();
     }
 
-    let result = sqlx::query!("DELETE FROM users WHERE id = ?", user_id)
-        .execute(&state.db)
+    let result = sqlx::query_scalar!("DELETE FROM users WHERE id = ? RETURNING username", user_id)
+        .fetch_optional(&state.db)
         .await;
 
     match result {
-        Ok(r) if r.rows_affected() == 0 => {
+        Ok(None) => {
             (StatusCode::NOT_FOUND, "User not found").into_response()
         }
-        Ok(_) => (
-            StatusCode::OK,
-            Json(serde_json::json!({
-                "message": "User deleted successfully"
-            })),
-        )
-            .into_response(),
-        Err(_) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Failed to delete user",
-        )
-            .into_response(),
+        Ok(Some(username)) => {
+            // The row is gone, so the target is kept in the details rather than target_user_id
+            let details = format!("user_id={} username={}", user_id, username);
+            audit::record(&state.db, Some(admin.0.id), audit::ACTION_USER_DELETED, None, None, Some(&details)).await;
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "message": "User deleted successfully"
+                })),
+            )
+                .into_response()
+        }
+        Err(e) => db_error(&e, "Failed to delete user"),
+    }
+}
+
+/// Lifetime of an impersonation token; it cannot be refreshed
+const IMPERSONATION_TOKEN_MINUTES: i64 = 15;
+
+/// POST /api/users/:id/impersonate
+/// Issues an access token that acts as the given user, for support
+#[utoipa::path(
+    post,
+    path = "/api/users/{id}/impersonate",
+    params(
+        ("id" = i64, Path, description = "User ID")
+    ),
+    tag = "users",
+    responses(
+        (status = 200, description = "Impersonation token issued", body = ImpersonateResponse),
+        (status = 400, description = "Cannot impersonate yourself"),
+        (status = 403, description = "Target is an admin or disabled"),
+        (status = 404, description = "User not found")
+    )
+)]
+pub async fn impersonate_user(
+    admin: AdminUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+) -> impl IntoResponse {
+    if user_id == admin.0.id {
+        return (StatusCode::BAD_REQUEST, "Cannot impersonate yourself").into_response();
+    }
+
+    let user = sqlx::query_as!(
+        UserResponse,
+        "SELECT id, username, role, last_login_at, force_password_change, is_disabled FROM users WHERE id = ?",
+        user_id
+    )
+    .fetch_optional(&state.db)
+    .await;
+
+    let user = match user {
+        Ok(Some(u)) => u,
+        Ok(None) => return (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
+    };
+
+    // Acting as another admin would not help support and only widens what the token can do
+    if user.role == "admin" {
+        return (StatusCode::FORBIDDEN, "Cannot impersonate an admin").into_response();
     }
+    if user.is_disabled {
+        return (StatusCode::FORBIDDEN, "Cannot impersonate a disabled user").into_response();
+    }
+
+    let access_token = match create_impersonation_jwt(
+        &state.config,
+        user.id,
+        &user.username,
+        &user.role,
+        admin.0.id,
+        chrono::Duration::minutes(IMPERSONATION_TOKEN_MINUTES),
+    ) {
+        Ok(t) => t,
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Token generation failed").into_response(),
+    };
+
+    audit::record(&state.db, Some(admin.0.id), audit::ACTION_IMPERSONATE, Some(user.id), None, None).await;
+    println!("Admin '{}' is impersonating user '{}'", admin.0.username, user.username);
+
+    let response = ImpersonateResponse {
+        user,
+        access_token,
+        expires_in: IMPERSONATION_TOKEN_MINUTES * 60,
+    };
+
+    (StatusCode::OK, Json(response)).into_response()
 }
 
 /// POST /api/refresh
@@ -609,16 +1202,18 @@ pub async fn refresh_token(
 ) -> impl IntoResponse {
     // 1. Verify Refresh Token in DB
     let token_record = sqlx::query!(
-        "SELECT token_hash, user_id, expires_at FROM refresh_tokens WHERE token_hash = ?",
+        r#"SELECT token_hash, user_id, expires_at, remember_me,
+                COALESCE(last_used_at, created_at) as "last_used_at!: chrono::NaiveDateTime"
+           FROM refresh_tokens WHERE token_hash = ?"#,
         payload.refresh_token
     )
     .fetch_optional(&state.db)
-    .await
-    .unwrap_or(None);
+    .await;
 
     let token_record = match token_record {
-        Some(t) => t,
-        None => return (StatusCode::UNAUTHORIZED, "Invalid refresh token").into_response(),
+        Ok(Some(t)) => t,
+        Ok(None) => return (StatusCode::UNAUTHORIZED, "Invalid refresh token").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
     };
 
     // 2. Check Expiration
@@ -633,18 +1228,29 @@ pub async fn refresh_token(
         return (StatusCode::UNAUTHORIZED, "Refresh token expired").into_response();
     }
 
+    // Idle sessions end even before their absolute expiry
+    if let Some(idle_minutes) = state.config.session_idle_minutes {
+        let last_used_at = chrono::Utc.from_utc_datetime(&token_record.last_used_at);
+        if now - last_used_at > chrono::Duration::minutes(idle_minutes) {
+            let _ = sqlx::query!("DELETE FROM refresh_tokens WHERE token_hash = ?", payload.refresh_token)
+                .execute(&state.db)
+                .await;
+            return (StatusCode::UNAUTHORIZED, "Session expired due to inactivity").into_response();
+        }
+    }
+
     // 3. Fetch User
     let user = sqlx::query!(
         "SELECT username, role FROM users WHERE id = ?",
         token_record.user_id
     )
     .fetch_optional(&state.db)
-    .await
-    .unwrap_or(None);
+    .await;
 
     let user = match user {
-        Some(u) => u,
-        None => return (StatusCode::UNAUTHORIZED, "User not found").into_response(),
+        Ok(Some(u)) => u,
+        Ok(None) => return (StatusCode::UNAUTHORIZED, "User not found").into_response(),
+        Err(e) => return db_error(&e, "Database error"),
     };
 
     // 4. Rotate Tokens
@@ -654,25 +1260,21 @@ pub async fn refresh_token(
         .await;
 
     // Generate New
-    let access_token = match create_jwt(token_record.user_id, &user.username, &user.role, chrono::Duration::minutes(15)) {
+    let access_token = match create_jwt(&state.config, token_record.user_id, &user.username, &user.role, chrono::Duration::minutes(15)) {
         Ok(t) => t,
         Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate token").into_response(),
     };
 
     let new_refresh_token = generate_refresh_token();
-    // Keep same expiration duration logic? Or slide it? Let's slide it.
-    // Actually, calculate duration from old token? Or just give fresh duration?
-    // Let's give fresh 30 days / 1 day based on...? We lost "remember_me" context.
-    // We can infer it: if old token was > 24h, it was remember_me.
-    // Or just simplify: Refreshing keeps the session alive, so slide window.
-    // Default to 30 days sliding window for simplicity in this iteration.
-    let new_expires_at = now + chrono::Duration::days(30);
+    // Sliding window: refreshing keeps the session alive for another full lifetime of its kind
+    let new_expires_at = now + state.config.session_duration(token_record.remember_me);
 
     let _ = sqlx::query!(
-        "INSERT INTO refresh_tokens (token_hash, user_id, expires_at) VALUES (?, ?, ?)",
+        "INSERT INTO refresh_tokens (token_hash, user_id, expires_at, remember_me, last_used_at) VALUES (?, ?, ?, ?, CURRENT_TIMESTAMP)",
         new_refresh_token,
         token_record.user_id,
-        new_expires_at
+        new_expires_at,
+        token_record.remember_me
     )
     .execute(&state.db)
     .await;
@@ -704,13 +1306,43 @@ pub async fn logout_user(
     (StatusCode::OK, Json(serde_json::json!({"message": "Logged out"}))).into_response()
 }
 
+/// POST /api/logout-all
+/// Revokes every session (refresh token) of the calling user
+#[utoipa::path(
+    post,
+    path = "/api/logout-all",
+    tag = "users",
+    responses(
+        (status = 200, description = "All sessions revoked", body = LogoutAllResponse),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not allowed while impersonating")
+    )
+)]
+pub async fn logout_all(
+    auth_user: AuthUser,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    if auth_user.impersonated_by.is_some() {
+        return (StatusCode::FORBIDDEN, "Not allowed while impersonating").into_response();
+    }
+
+    let result = sqlx::query!("DELETE FROM refresh_tokens WHERE user_id = ?", auth_user.id)
+        .execute(&state.db)
+        .await;
+
+    match result {
+        Ok(r) => (StatusCode::OK, Json(LogoutAllResponse { revoked: r.rows_affected() })).into_response(),
+        Err(e) => db_error(&e, "Failed to revoke sessions"),
+    }
+}
+
 /// GET /api/me
 #[utoipa::path(
     get,
     path = "/api/me",
     tag = "users",
     responses(
-        (status = 200, description = "Current user info", body = UserResponse),
+        (status = 200, description = "Current user info and permissions", body = MeResponse),
         (status = 401, description = "Unauthorized")
     )
 )]
@@ -727,9 +1359,12 @@ pub async fn get_me(
     .await;
 
     match user {
-        Ok(Some(u)) => Json(u).into_response(),
+        Ok(Some(u)) => {
+            let permissions = role_capabilities(&u.role).iter().map(|c| c.to_string()).collect();
+            Json(MeResponse { user: u, permissions }).into_response()
+        }
         Ok(None) => (StatusCode::UNAUTHORIZED, "User not found").into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response(),
+        Err(e) => db_error(&e, "Database error"),
     }
 }
 
@@ -737,31 +1372,46 @@ pub async fn get_me(
 #[derive(OpenApi)]
 #[openapi(
     paths(
+        setup,
         create_user,
         login,
         refresh_token,
         logout_user,
+        logout_all,
         get_me,
         list_users,
+        username_available,
+        get_user,
+        user_stats,
+        unlock_user,
+        force_logout,
         update_role,
         update_status,
         admin_reset_password,
         change_password,
-        delete_user
+        delete_user,
+        impersonate_user
     ),
     components(
         schemas(
+            SetupRequest,
             CreateUserRequest,
             LoginRequest,
             RefreshTokenRequest,
             RefreshTokenResponse,
+            LogoutAllResponse,
             LoginResponse,
             UserResponse,
+            UserDetailResponse,
+            MeResponse,
             UpdateRoleRequest,
             UpdateStatusRequest,
             AdminResetPasswordRequest,
             AdminResetPasswordResponse,
-            ChangePasswordRequest
+            ChangePasswordRequest,
+            UsernameAvailabilityResponse,
+            ImpersonateResponse,
+            UserStatsResponse
         )
     ),
     tags(
@@ -769,3 +1419,537 @@ pub async fn get_me(
     )
 )]
 pub struct UserApi;
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_password, init_dummy_hash, validate_admin_password, verify_password};
+    use crate::audit;
+    use crate::db::AppState;
+    use crate::test_support::{app, call, device, state, state_with, user};
+    use axum::body::{to_bytes, Body};
+    use axum::http::{header, Method, Request, StatusCode};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    #[test]
+    fn admin_password_needs_twelve_characters() {
+        assert!(validate_admin_password("admin1234").is_err());
+        assert!(validate_admin_password("abcdefgh123").is_err());
+        assert!(validate_admin_password("abcdefghijkl").is_err());
+        assert!(validate_admin_password("abcdefgh1234").is_ok());
+    }
+
+    #[tokio::test]
+    async fn unknown_username_still_verifies_a_password() {
+        let state = state_with(|config| {
+            config.argon2_memory_kib = 64;
+            config.argon2_iterations = 1;
+        })
+        .await;
+        let hash = init_dummy_hash(&state.config);
+        assert!(hash.starts_with("$argon2id$"));
+
+        let login = json!({ "username": "nobody", "password": "whatever" });
+        let (status, _) = call(&state, Method::POST, "/api/login", None, Some(login)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        // Created once, not again per login
+        assert!(std::ptr::eq(init_dummy_hash(&state.config), hash));
+    }
+
+    #[tokio::test]
+    async fn setup_rejects_a_short_admin_password() {
+        let state = state().await;
+        let short = json!({ "username": "admin", "password": "abcdefgh12" });
+        let (status, _) = call(&state, Method::POST, "/api/setup", None, Some(short)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let strong = json!({ "username": "admin", "password": "abcdefgh1234" });
+        let (status, _) = call(&state, Method::POST, "/api/setup", None, Some(strong)).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn stats_count_each_wake_once() {
+        let state = state().await;
+        let (id, token) = user(&state, "alice", "user").await;
+        let desktop = device(&state, "desktop", Some(id)).await;
+        audit::record(&state.db, Some(id), audit::ACTION_DEVICE_WAKE, None, Some(desktop), None).await;
+        // A scheduled wake that only went out on its third attempt, and one that was skipped
+        for action in [audit::ACTION_SCHEDULED_WAKE_FAILED, audit::ACTION_SCHEDULED_WAKE_FAILED, audit::ACTION_SCHEDULED_WAKE] {
+            audit::record(&state.db, Some(id), action, None, Some(desktop), None).await;
+        }
+        audit::record(&state.db, Some(id), audit::ACTION_SCHEDULED_WAKE_SKIPPED, None, Some(desktop), None).await;
+
+        let group_id = sqlx::query_scalar!(r#"INSERT INTO groups (name) VALUES ('office') RETURNING id as "id!""#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        sqlx::query!("UPDATE devices SET group_id = ?, enabled = 0 WHERE id = ?", group_id, desktop)
+            .execute(&state.db)
+            .await
+            .unwrap();
+        let (status, _) = call(&state, Method::POST, &format!("/api/groups/{group_id}/wake"), Some(&token), None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = call(&state, Method::GET, &format!("/api/users/{id}/stats"), Some(&token), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let stats: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(stats["wakes_total"], 3, "{body}");
+        assert_eq!(stats["wakes_last_7d"], 3, "{body}");
+    }
+
+    /// Stores a refresh token for `user_id` last used at `last_used_at` (an SQLite datetime expression)
+    async fn session(state: &AppState, user_id: i64, token: &str, last_used_at: &str) {
+        let query = format!(
+            "INSERT INTO refresh_tokens (token_hash, user_id, expires_at, last_used_at) VALUES (?, ?, datetime('now', '+7 days'), {})",
+            last_used_at
+        );
+        sqlx::query(&query).bind(token).bind(user_id).execute(&state.db).await.unwrap();
+    }
+
+    async fn refresh(state: &AppState, token: &str) -> (StatusCode, String) {
+        call(state, Method::POST, "/api/refresh", None, Some(json!({ "refresh_token": token }))).await
+    }
+
+    #[tokio::test]
+    async fn idle_sessions_cannot_be_refreshed() {
+        let state = state_with(|config| config.session_idle_minutes = Some(30)).await;
+        let (id, _) = user(&state, "alice", "user").await;
+        session(&state, id, "idle", "datetime('now', '-2 hours')").await;
+        session(&state, id, "active", "datetime('now', '-5 minutes')").await;
+
+        let (status, body) = refresh(&state, "idle").await;
+        assert_eq!((status, body.as_str()), (StatusCode::UNAUTHORIZED, "Session expired due to inactivity"));
+        // And it is gone for good
+        let (status, _) = refresh(&state, "idle").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = refresh(&state, "active").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn force_logout_ends_every_session() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let (id, _) = user(&state, "alice", "user").await;
+        session(&state, id, "laptop", "CURRENT_TIMESTAMP").await;
+        session(&state, id, "phone", "CURRENT_TIMESTAMP").await;
+
+        let (status, body) = call(&state, Method::POST, &format!("/api/users/{id}/force-logout"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap()["revoked"], 2);
+
+        for token in ["laptop", "phone"] {
+            let (status, _) = refresh(&state, token).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn admins_can_act_as_a_user_with_limits() {
+        let state = state().await;
+        let (admin_id, admin) = user(&state, "admin", "admin").await;
+        let (other_admin_id, _) = user(&state, "root", "admin").await;
+        let (alice_id, alice) = user(&state, "alice", "user").await;
+        let (bob_id, _) = user(&state, "bob", "user").await;
+
+        let (status, _) = call(&state, Method::POST, &format!("/api/users/{bob_id}/impersonate"), Some(&alice), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = call(&state, Method::POST, &format!("/api/users/{other_admin_id}/impersonate"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = call(&state, Method::POST, &format!("/api/users/{alice_id}/impersonate"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let token = body["access_token"].as_str().unwrap();
+
+        let (status, me) = call(&state, Method::GET, "/api/me", Some(token), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let me: serde_json::Value = serde_json::from_str(&me).unwrap();
+        assert_eq!(me["username"], "alice");
+
+        let password = json!({ "old_password": "irrelevant", "new_password": "Another-password-1" });
+        let (status, _) = call(&state, Method::POST, "/api/change-password", Some(token), Some(password)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = call(&state, Method::POST, "/api/logout-all", Some(token), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let audited = sqlx::query_scalar!(
+            "SELECT target_user_id FROM audit_log WHERE user_id = ? AND action = ?",
+            admin_id,
+            audit::ACTION_IMPERSONATE
+        )
+        .fetch_all(&state.db)
+        .await
+        .unwrap();
+        assert_eq!(audited, [Some(alice_id)]);
+    }
+
+    #[tokio::test]
+    async fn create_points_location_at_the_new_user() {
+        let state = state_with(|config| {
+            config.argon2_memory_kib = 8;
+            config.argon2_iterations = 1;
+            config.argon2_parallelism = 1;
+        })
+        .await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let request = Request::post("/api/users")
+            .header(header::AUTHORIZATION, format!("Bearer {admin}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "username": "alice" }).to_string()))
+            .unwrap();
+        let response = app(&state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers()[header::LOCATION].to_str().unwrap().to_string();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(location, format!("/api/users/{}", created["user"]["id"]));
+    }
+
+    #[tokio::test]
+    async fn logout_all_revokes_only_the_callers_sessions() {
+        let state = state().await;
+        let (alice_id, alice) = user(&state, "alice", "user").await;
+        let (bob_id, _) = user(&state, "bob", "user").await;
+        for token in ["laptop", "phone", "tablet"] {
+            session(&state, alice_id, token, "CURRENT_TIMESTAMP").await;
+        }
+        session(&state, bob_id, "bobs-laptop", "CURRENT_TIMESTAMP").await;
+
+        let (status, body) = call(&state, Method::POST, "/api/logout-all", Some(&alice), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap()["revoked"], 3);
+
+        for token in ["laptop", "phone", "tablet"] {
+            let (status, _) = refresh(&state, token).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+        let (status, _) = refresh(&state, "bobs-laptop").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn hashes_with_configured_argon2_params() {
+        let light = state_with(|config| {
+            config.argon2_memory_kib = 8;
+            config.argon2_iterations = 1;
+            config.argon2_parallelism = 1;
+        })
+        .await;
+        let hash = hash_password(&light.config, "correct horse 1").unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$m=8,t=1,p=1$"), "{hash}");
+        assert!(verify_password(&light.config, "correct horse 1", &hash));
+        assert!(!verify_password(&light.config, "wrong horse 1", &hash));
+
+        // The params are read from the hash, so changing them keeps old hashes working
+        let heavier = state_with(|config| {
+            config.argon2_memory_kib = 16;
+            config.argon2_iterations = 2;
+            config.argon2_parallelism = 1;
+        })
+        .await;
+        assert!(verify_password(&heavier.config, "correct horse 1", &hash));
+    }
+
+    #[tokio::test]
+    async fn login_upgrades_hashes_made_with_old_params() {
+        let state = state_with(|config| {
+            config.argon2_memory_kib = 16;
+            config.argon2_iterations = 2;
+            config.argon2_parallelism = 1;
+        })
+        .await;
+        let mut old_config = state.config.as_ref().clone();
+        old_config.argon2_memory_kib = 8;
+        old_config.argon2_iterations = 1;
+        let (id, _) = user(&state, "alice", "user").await;
+        let old_hash = hash_password(&old_config, "correct horse 1").unwrap();
+        sqlx::query!("UPDATE users SET password_hash = ? WHERE id = ?", old_hash, id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let login = json!({ "username": "alice", "password": "correct horse 1" });
+        let (status, body) = call(&state, Method::POST, "/api/login", None, Some(login.clone())).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let new_hash = sqlx::query_scalar!("SELECT password_hash FROM users WHERE id = ?", id)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert!(new_hash.starts_with("$argon2id$v=19$m=16,t=2,p=1$"), "{new_hash}");
+        let (status, _) = call(&state, Method::POST, "/api/login", None, Some(login)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    /// Cheap argon2 settings, so tests that log in stay fast
+    fn fast_hashing(config: &mut crate::config::Config) {
+        config.argon2_memory_kib = 8;
+        config.argon2_iterations = 1;
+        config.argon2_parallelism = 1;
+    }
+
+    /// Gives the user `password`, hashed with the state's settings
+    async fn set_password(state: &AppState, user_id: i64, password: &str) {
+        let hash = hash_password(&state.config, password).unwrap();
+        sqlx::query!("UPDATE users SET password_hash = ? WHERE id = ?", hash, user_id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn logins_beyond_the_session_limit_evict_the_oldest() {
+        let state = state_with(|config| {
+            fast_hashing(config);
+            config.max_sessions_per_user = Some(5);
+        })
+        .await;
+        let (id, _) = user(&state, "alice", "user").await;
+        set_password(&state, id, "correct horse 1").await;
+
+        let mut refresh_tokens = Vec::new();
+        for _ in 0..6 {
+            let login = json!({ "username": "alice", "password": "correct horse 1" });
+            let (status, body) = call(&state, Method::POST, "/api/login", None, Some(login)).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            refresh_tokens.push(body["refresh_token"].as_str().unwrap().to_string());
+        }
+
+        let sessions = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!: i64" FROM refresh_tokens WHERE user_id = ?"#, id)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(sessions, 5);
+        let (status, _) = refresh(&state, &refresh_tokens[0]).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = refresh(&state, &refresh_tokens[5]).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn failures_outside_the_window_do_not_count_toward_lockout() {
+        let state = state_with(|config| {
+            fast_hashing(config);
+            config.lockout_threshold = 3;
+            config.failed_login_window_minutes = 60;
+        })
+        .await;
+        for (username, last_failure) in [("alice", "datetime('now', '-2 hours')"), ("bob", "datetime('now', '-5 minutes')")] {
+            let (id, _) = user(&state, username, "user").await;
+            set_password(&state, id, "correct horse 1").await;
+            let query = format!("UPDATE users SET failed_login_attempts = 2, last_failed_login_at = {} WHERE id = ?", last_failure);
+            sqlx::query(&query).bind(id).execute(&state.db).await.unwrap();
+
+            let typo = json!({ "username": username, "password": "correct horse 2" });
+            let (status, _) = call(&state, Method::POST, "/api/login", None, Some(typo)).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+
+        let login = |username: &str| json!({ "username": username, "password": "correct horse 1" });
+        // Alice's old failures were forgotten, so this was her first
+        let (status, _) = call(&state, Method::POST, "/api/login", None, Some(login("alice"))).await;
+        assert_eq!(status, StatusCode::OK);
+        // Bob's were recent, so this was his third
+        let (status, _) = call(&state, Method::POST, "/api/login", None, Some(login("bob"))).await;
+        assert_eq!(status, StatusCode::LOCKED);
+    }
+
+    #[tokio::test]
+    async fn sessions_last_the_configured_number_of_days() {
+        let state = state_with(|config| {
+            fast_hashing(config);
+            config.session_days = 1;
+            config.remember_me_days = 7;
+        })
+        .await;
+        let (id, _) = user(&state, "alice", "user").await;
+        set_password(&state, id, "correct horse 1").await;
+        let days_left = |token: String| {
+            let state = state.clone();
+            async move {
+                let expires_at = sqlx::query_scalar!(
+                    r#"SELECT expires_at as "expires_at: chrono::DateTime<chrono::Utc>" FROM refresh_tokens WHERE token_hash = ?"#,
+                    token
+                )
+                .fetch_one(&state.db)
+                .await
+                .unwrap();
+                (expires_at - chrono::Utc::now()).num_hours() as f64 / 24.0
+            }
+        };
+
+        for (remember_me, days) in [(false, 1.0), (true, 7.0)] {
+            let login = json!({ "username": "alice", "password": "correct horse 1", "remember_me": remember_me });
+            let (status, body) = call(&state, Method::POST, "/api/login", None, Some(login)).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let token = serde_json::from_str::<serde_json::Value>(&body).unwrap()["refresh_token"].as_str().unwrap().to_string();
+            assert!((days - days_left(token.clone()).await).abs() < 0.1);
+
+            // Rotation keeps the lifetime the session started with
+            let (status, body) = refresh(&state, &token).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let rotated = serde_json::from_str::<serde_json::Value>(&body).unwrap()["refresh_token"].as_str().unwrap().to_string();
+            assert!((days - days_left(rotated).await).abs() < 0.1);
+        }
+    }
+
+    #[tokio::test]
+    async fn admins_see_and_clear_lockouts() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let (id, alice) = user(&state, "alice", "user").await;
+        sqlx::query!(
+            "UPDATE users SET failed_login_attempts = 5, last_failed_login_at = CURRENT_TIMESTAMP,
+                locked_until = datetime('now', '+15 minutes') WHERE id = ?",
+            id
+        )
+        .execute(&state.db)
+        .await
+        .unwrap();
+        let detail = |token: String| {
+            let state = state.clone();
+            async move {
+                let (status, body) = call(&state, Method::GET, &format!("/api/users/{id}"), Some(&token), None).await;
+                (status, serde_json::from_str::<serde_json::Value>(&body).unwrap_or_default())
+            }
+        };
+
+        let (status, user) = detail(admin.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(user["failed_login_attempts"], 5);
+        assert!(!user["locked_until"].is_null());
+        assert!(!user["last_failed_login_at"].is_null());
+
+        let unlock = format!("/api/users/{id}/unlock");
+        let (status, _) = detail(alice.clone()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = call(&state, Method::POST, &unlock, Some(&alice), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = call(&state, Method::POST, &unlock, Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, user) = detail(admin).await;
+        assert_eq!(user["failed_login_attempts"], 0);
+        assert!(user["locked_until"].is_null());
+        assert!(user["last_failed_login_at"].is_null());
+    }
+
+    #[tokio::test]
+    async fn me_lists_the_roles_permissions() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let (_, alice) = user(&state, "alice", "user").await;
+        let permissions = |token: String| {
+            let state = state.clone();
+            async move {
+                let (status, body) = call(&state, Method::GET, "/api/me", Some(&token), None).await;
+                assert_eq!(status, StatusCode::OK);
+                serde_json::from_str::<serde_json::Value>(&body).unwrap()["permissions"].clone()
+            }
+        };
+
+        assert_eq!(permissions(admin).await, json!(["wake", "shutdown", "manage_devices", "manage_users"]));
+        assert_eq!(permissions(alice).await, json!(["wake", "shutdown"]));
+    }
+
+    #[tokio::test]
+    async fn setup_creates_the_first_admin_only_once() {
+        let state = state_with(fast_hashing).await;
+        let setup = json!({ "username": "root", "password": "abcdefgh1234" });
+        let (status, body) = call(&state, Method::POST, "/api/setup", None, Some(setup)).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+
+        let login = json!({ "username": "root", "password": "abcdefgh1234" });
+        let (status, body) = call(&state, Method::POST, "/api/login", None, Some(login)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap()["user"]["role"], "admin");
+
+        let again = json!({ "username": "intruder", "password": "abcdefgh1234" });
+        let (status, _) = call(&state, Method::POST, "/api/setup", None, Some(again)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let users = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!: i64" FROM users"#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(users, 1);
+    }
+
+    #[tokio::test]
+    async fn username_availability_ignores_case() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let (_, alice) = user(&state, "alice", "user").await;
+        let available = |username: &str| {
+            let (state, admin, uri) = (state.clone(), admin.clone(), format!("/api/users/available?username={username}"));
+            async move {
+                let (status, body) = call(&state, Method::GET, &uri, Some(&admin), None).await;
+                assert_eq!(status, StatusCode::OK);
+                serde_json::from_str::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        assert_eq!(available("ALICE").await, json!({ "available": false }));
+        assert_eq!(available("bob").await, json!({ "available": true }));
+        let (status, _) = call(&state, Method::GET, "/api/users/available?username=bob", Some(&alice), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn role_changes_and_password_resets_are_audited() {
+        let state = state().await;
+        let (admin_id, admin) = user(&state, "admin", "admin").await;
+        let (target_id, _) = user(&state, "alice", "user").await;
+
+        let uri = format!("/api/users/{target_id}/role");
+        let (status, _) = call(&state, Method::PUT, &uri, Some(&admin), Some(json!({ "role": "admin" }))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let entry = sqlx::query!(
+            "SELECT user_id, target_user_id, details FROM audit_log WHERE action = ?",
+            audit::ACTION_ROLE_CHANGED
+        )
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+        assert_eq!(entry.user_id, Some(admin_id));
+        assert_eq!(entry.target_user_id, Some(target_id));
+        assert_eq!(entry.details.as_deref(), Some("role=admin"));
+
+        let reset = json!({ "new_password": "Correct-Horse-9" });
+        let (status, _) = call(&state, Method::POST, &format!("/api/users/{target_id}/reset-password"), Some(&admin), Some(reset)).await;
+        assert_eq!(status, StatusCode::OK);
+        let details = sqlx::query_scalar!("SELECT details FROM audit_log WHERE action = ?", audit::ACTION_PASSWORD_RESET)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(details.as_deref(), Some("set by admin"));
+    }
+
+    #[tokio::test]
+    async fn welcome_message_fills_in_username_and_password() {
+        let state = state_with(|config| {
+            fast_hashing(config);
+            config.welcome_message_template = Some("Log in at https://wol.example.com as {username} with {password}".to_string());
+        })
+        .await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+
+        let (status, body) = call(&state, Method::POST, "/api/users", Some(&admin), Some(json!({ "username": "alice" }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let created: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let password = created["password"].as_str().unwrap();
+        assert_eq!(created["message"], format!("Log in at https://wol.example.com as alice with {password}"));
+        let alice_id = created["user"]["id"].as_i64().unwrap();
+
+        let uri = format!("/api/users/{alice_id}/reset-password");
+        let (status, body) = call(&state, Method::POST, &uri, Some(&admin), Some(json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        let reset: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let password = reset["password"].as_str().unwrap();
+        assert_eq!(reset["message"], format!("Log in at https://wol.example.com as alice with {password}"));
+    }
+}
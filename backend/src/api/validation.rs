@@ -0,0 +1,45 @@
+use crate::config::Config;
+
+/// Rejects control characters (newlines, NUL, escape sequences, ...) in
+/// single-line fields that end up in logs, exports and other clients.
+/// Tabs are ordinary whitespace and allowed.
+pub fn reject_control_chars(field: &str, value: &str) -> Result<(), String> {
+    if value.chars().any(|c| c.is_control() && c != '\t') {
+        return Err(format!("{} must not contain control characters", field));
+    }
+    Ok(())
+}
+
+/// Same as [`reject_control_chars`] for fields that may be omitted.
+pub fn reject_control_chars_opt(field: &str, value: Option<&str>) -> Result<(), String> {
+    value.map_or(Ok(()), |v| reject_control_chars(field, v))
+}
+
+/// Caps the number of items in one batch request (`MAX_BATCH_ITEMS`), so a
+/// single call can't hold the database or flood the network for long.
+/// Callers answer the error with 422.
+pub fn check_batch_size(config: &Config, field: &str, len: usize) -> Result<(), String> {
+    if len > config.max_batch_items {
+        return Err(format!("Too many items in {}: at most {} per request", field, config.max_batch_items));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::reject_control_chars;
+
+    #[test]
+    fn allows_plain_text_and_tabs() {
+        assert!(reject_control_chars("name", "Living room PC").is_ok());
+        assert!(reject_control_chars("name", "Rack\t2").is_ok());
+        assert!(reject_control_chars("name", "Büro ☕").is_ok());
+    }
+
+    #[test]
+    fn rejects_other_control_characters() {
+        for value in ["two\nlines", "carriage\rreturn", "nul\0byte", "\u{1b}[31mred", "del\u{7f}", "next\u{85}line"] {
+            assert!(reject_control_chars("name", value).is_err(), "{value:?}");
+        }
+    }
+}
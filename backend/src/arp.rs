@@ -0,0 +1,100 @@
+use pnet_datalink::{Channel, Config, MacAddr, NetworkInterface};
+use pnet_packet::arp::{ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket};
+use pnet_packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet_packet::{MutablePacket, Packet};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const ARP_PACKET_LEN: usize = 28;
+
+/// Finds the interface (and our address on it) whose subnet contains `target`.
+fn interface_for(target: Ipv4Addr) -> Option<(NetworkInterface, Ipv4Addr)> {
+    pnet_datalink::interfaces()
+        .into_iter()
+        .filter(|iface| iface.is_up() && !iface.is_loopback() && iface.mac.is_some())
+        .find_map(|iface| {
+            let source_ip = iface.ips.iter().find_map(|net| match net.ip() {
+                IpAddr::V4(ip) if net.contains(IpAddr::V4(target)) => Some(ip),
+                _ => None,
+            })?;
+            Some((iface, source_ip))
+        })
+}
+
+/// Sends an ARP request for `target` and waits up to `timeout` for a reply.
+///
+/// Blocking and needs CAP_NET_RAW, so call it from `spawn_blocking`.
+/// Only works for hosts on a directly attached subnet.
+pub fn probe(target: Ipv4Addr, timeout: Duration) -> std::io::Result<bool> {
+    let Some((iface, source_ip)) = interface_for(target) else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No local interface on the subnet of {}", target),
+        ));
+    };
+    let source_mac = iface.mac.unwrap_or(MacAddr::zero());
+
+    let config = Config {
+        read_timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let (mut tx, mut rx) = match pnet_datalink::channel(&iface, config)? {
+        Channel::Ethernet(tx, rx) => (tx, rx),
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Unsupported datalink channel",
+            ))
+        }
+    };
+
+    let mut arp_buffer = [0u8; ARP_PACKET_LEN];
+    let mut arp_request = MutableArpPacket::new(&mut arp_buffer).expect("buffer fits an ARP packet");
+    arp_request.set_hardware_type(ArpHardwareTypes::Ethernet);
+    arp_request.set_protocol_type(EtherTypes::Ipv4);
+    arp_request.set_hw_addr_len(6);
+    arp_request.set_proto_addr_len(4);
+    arp_request.set_operation(ArpOperations::Request);
+    arp_request.set_sender_hw_addr(source_mac);
+    arp_request.set_sender_proto_addr(source_ip);
+    arp_request.set_target_hw_addr(MacAddr::zero());
+    arp_request.set_target_proto_addr(target);
+
+    let mut ethernet_buffer = [0u8; ETHERNET_HEADER_LEN + ARP_PACKET_LEN];
+    let mut ethernet_packet =
+        MutableEthernetPacket::new(&mut ethernet_buffer).expect("buffer fits an Ethernet frame");
+    ethernet_packet.set_destination(MacAddr::broadcast());
+    ethernet_packet.set_source(source_mac);
+    ethernet_packet.set_ethertype(EtherTypes::Arp);
+    ethernet_packet.set_payload(arp_request.packet_mut());
+
+    if let Some(result) = tx.send_to(ethernet_packet.packet(), None) {
+        result?;
+    }
+
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        let frame = match rx.next() {
+            Ok(frame) => frame,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        };
+
+        let Some(ethernet) = EthernetPacket::new(frame) else {
+            continue;
+        };
+        if ethernet.get_ethertype() != EtherTypes::Arp {
+            continue;
+        }
+
+        if let Some(arp) = ArpPacket::new(ethernet.payload())
+            && arp.get_operation() == ArpOperations::Reply
+            && arp.get_sender_proto_addr() == target
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
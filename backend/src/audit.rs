@@ -0,0 +1,53 @@
+use sqlx::{Pool, Sqlite};
+
+use crate::client_ip;
+
+pub const ACTION_IMPERSONATE: &str = "impersonate";
+pub const ACTION_LOGIN: &str = "login";
+pub const ACTION_DEVICE_WAKE: &str = "device_wake";
+pub const ACTION_ADHOC_WAKE: &str = "adhoc_wake";
+/// A scheduled wake that went out; failed attempts and skipped schedules have actions of their own
+pub const ACTION_SCHEDULED_WAKE: &str = "scheduled_wake";
+pub const ACTION_SCHEDULED_WAKE_FAILED: &str = "scheduled_wake_failed";
+pub const ACTION_SCHEDULED_WAKE_SKIPPED: &str = "scheduled_wake_skipped";
+pub const ACTION_GROUP_WAKE: &str = "group_wake";
+pub const ACTION_RESTORE: &str = "restore";
+pub const ACTION_USER_CREATED: &str = "user_created";
+pub const ACTION_USER_DELETED: &str = "user_deleted";
+pub const ACTION_ROLE_CHANGED: &str = "role_changed";
+pub const ACTION_USER_STATUS_CHANGED: &str = "user_status_changed";
+pub const ACTION_PASSWORD_RESET: &str = "password_reset";
+pub const ACTION_FORCE_LOGOUT: &str = "force_logout";
+pub const ACTION_SCENE_ACTIVATED: &str = "scene_activated";
+pub const ACTION_OWNER_CHANGED: &str = "owner_changed";
+pub const ACTION_MAINTENANCE_CHANGED: &str = "maintenance_changed";
+
+/// Appends an entry to `audit_log`.
+/// Best effort: a failed write is logged but never fails the audited request.
+/// `user_id` is `None` for actions the server takes on its own; the client address
+/// is recorded for entries written while handling a request.
+pub async fn record(
+    db: &Pool<Sqlite>,
+    user_id: Option<i64>,
+    action: &str,
+    target_user_id: Option<i64>,
+    device_id: Option<i64>,
+    details: Option<&str>,
+) {
+    let ip_address = client_ip::current().map(|ip| ip.to_string());
+    let result = sqlx::query!(
+        "INSERT INTO audit_log (user_id, action, target_user_id, device_id, details, ip_address) VALUES (?, ?, ?, ?, ?, ?)",
+        user_id,
+        action,
+        target_user_id,
+        device_id,
+        details,
+        ip_address
+    )
+    .execute(db)
+    .await;
+
+    if let Err(e) = result {
+        println!("Failed to write audit log entry '{}': {}", action, e);
+    }
+}
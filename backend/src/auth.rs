@@ -10,20 +10,33 @@ use axum_extra::{
 };
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
-use std::env;
-use std::sync::OnceLock;
+use subtle::ConstantTimeEq;
+use crate::config::Config;
 use crate::db::AppState;
+use crate::error;
 
-static JWT_SECRET: OnceLock<String> = OnceLock::new();
-
-pub fn get_jwt_secret() -> &'static str {
-    JWT_SECRET.get_or_init(|| {
-        env::var("JWT_SECRET").unwrap_or_else(|_| {
-            println!("WARNING: JWT_SECRET not set, using random secret. Tokens will be invalid after restart.");
-            use rand::distr::{Alphanumeric, SampleString};
-            Alphanumeric.sample_string(&mut rand::rng(), 32)
-        })
-    })
+/// Decodes a token signed with the current secret or any previous one
+/// (`JWT_SECRET_PREVIOUS`), so the secret can be rotated without logging everyone out.
+/// `iss`/`aud` must match `JWT_ISSUER`/`JWT_AUDIENCE` when those are set.
+pub fn decode_jwt(config: &Config, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let mut validation = Validation::default();
+    if let Some(issuer) = &config.jwt_issuer {
+        validation.set_issuer(&[issuer]);
+        // Required too, or a token without the claim at all would pass
+        validation.required_spec_claims.insert("iss".to_string());
+    }
+    if let Some(audience) = &config.jwt_audience {
+        validation.set_audience(&[audience]);
+        validation.required_spec_claims.insert("aud".to_string());
+    }
+    let mut result = decode::<Claims>(token, &DecodingKey::from_secret(config.jwt_secret.as_bytes()), &validation);
+    for secret in &config.jwt_previous_secrets {
+        if result.is_ok() {
+            break;
+        }
+        result = decode::<Claims>(token, &DecodingKey::from_secret(secret.as_bytes()), &validation);
+    }
+    result.map(|data| data.claims)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -32,9 +45,27 @@ pub struct Claims {
     pub uid: i64,    // user id
     pub role: String, // 'admin' or 'user'
     pub exp: usize,
+    /// Id of the admin acting as this user, for impersonation tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonated_by: Option<i64>,
+    /// `JWT_ISSUER`, when configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// `JWT_AUDIENCE`, when configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
 }
 
-pub fn create_jwt(uid: i64, username: &str, role: &str, duration: chrono::Duration) -> Result<String, jsonwebtoken::errors::Error> {
+pub fn create_jwt(config: &Config, uid: i64, username: &str, role: &str, duration: chrono::Duration) -> Result<String, jsonwebtoken::errors::Error> {
+    encode_jwt(config, uid, username, role, None, duration)
+}
+
+/// Access token carrying `uid`'s claims, issued to the admin `admin_id`
+pub fn create_impersonation_jwt(config: &Config, uid: i64, username: &str, role: &str, admin_id: i64, duration: chrono::Duration) -> Result<String, jsonwebtoken::errors::Error> {
+    encode_jwt(config, uid, username, role, Some(admin_id), duration)
+}
+
+fn encode_jwt(config: &Config, uid: i64, username: &str, role: &str, impersonated_by: Option<i64>, duration: chrono::Duration) -> Result<String, jsonwebtoken::errors::Error> {
     let expiration = chrono::Utc::now()
         .checked_add_signed(duration)
         .expect("valid timestamp")
@@ -45,12 +76,15 @@ pub fn create_jwt(uid: i64, username: &str, role: &str, duration: chrono::Durati
         uid,
         role: role.to_owned(),
         exp: expiration as usize,
+        impersonated_by,
+        iss: config.jwt_issuer.clone(),
+        aud: config.jwt_audience.clone(),
     };
 
     encode(
         &Header::default(),
         &claims,
-        &EncodingKey::from_secret(get_jwt_secret().as_bytes()),
+        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
     )
 }
 
@@ -63,6 +97,14 @@ pub struct AuthUser {
     pub id: i64,
     pub username: String,
     pub role: String,
+    /// Set when an admin is acting as this user
+    pub impersonated_by: Option<i64>,
+}
+
+impl AuthUser {
+    pub fn is_admin(&self) -> bool {
+        self.role == "admin"
+    }
 }
 
 // #[async_trait]
@@ -77,31 +119,42 @@ impl FromRequestParts<AppState> for AuthUser {
             .map_err(|_| AuthError::MissingCredentials)?;
 
         // Decode the user data
-        let token_data = decode::<Claims>(
-            bearer.token(),
-            &DecodingKey::from_secret(get_jwt_secret().as_bytes()),
-            &Validation::default(),
-        )
-        .map_err(|_| AuthError::InvalidToken)?;
+        let claims = decode_jwt(&state.config, bearer.token()).map_err(|_| AuthError::InvalidToken)?;
 
         // Check if user is disabled
-        let user = sqlx::query!("SELECT is_disabled FROM users WHERE id = ?", token_data.claims.uid)
+        let user = sqlx::query!("SELECT is_disabled FROM users WHERE id = ?", claims.uid)
             .fetch_optional(&state.db)
             .await
-            .map_err(|_| AuthError::DatabaseError)?;
+            .map_err(|e| {
+                if error::is_transient(&e) {
+                    AuthError::DatabaseUnavailable
+                } else {
+                    AuthError::DatabaseError
+                }
+            })?;
 
         match user {
             Some(u) if u.is_disabled => Err(AuthError::AccountDisabled),
             Some(_) => Ok(AuthUser {
-                id: token_data.claims.uid,
-                username: token_data.claims.sub,
-                role: token_data.claims.role,
+                id: claims.uid,
+                username: claims.sub,
+                role: claims.role,
+                impersonated_by: claims.impersonated_by,
             }),
             None => Err(AuthError::InvalidToken), // User deleted
         }
     }
 }
 
+/// Capabilities granted by a role, so clients don't duplicate the mapping.
+/// Must stay in line with which handlers take `AuthUser` vs `AdminUser`.
+pub fn role_capabilities(role: &str) -> &'static [&'static str] {
+    match role {
+        "admin" => &["wake", "shutdown", "manage_devices", "manage_users"],
+        _ => &["wake", "shutdown"],
+    }
+}
+
 // Ensure Admin Middleware (Extractor)
 pub struct AdminUser(pub AuthUser);
 
@@ -112,7 +165,7 @@ impl FromRequestParts<AppState> for AdminUser {
     async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
         let user = AuthUser::from_request_parts(parts, state).await?;
         
-        if user.role == "admin" {
+        if user.is_admin() {
             Ok(AdminUser(user))
         } else {
             Err(AuthError::Forbidden)
@@ -120,6 +173,32 @@ impl FromRequestParts<AppState> for AdminUser {
     }
 }
 
+/// Access to operational endpoints (readiness details, metrics).
+/// Open to anyone unless `METRICS_TOKEN` is set; then the request must carry it as a bearer token.
+pub struct MetricsAccess;
+
+impl FromRequestParts<AppState> for MetricsAccess {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let Some(expected) = &state.config.metrics_token else {
+            return Ok(MetricsAccess);
+        };
+
+        let TypedHeader(Authorization(bearer)) = parts
+            .extract::<TypedHeader<Authorization<Bearer>>>()
+            .await
+            .map_err(|_| AuthError::MissingCredentials)?;
+
+        // Constant time, so response timing doesn't reveal how much of a guess was right
+        if bool::from(bearer.token().as_bytes().ct_eq(expected.as_bytes())) {
+            Ok(MetricsAccess)
+        } else {
+            Err(AuthError::InvalidToken)
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum AuthError {
     MissingCredentials,
@@ -127,6 +206,7 @@ pub enum AuthError {
     Forbidden,
     AccountDisabled,
     DatabaseError,
+    DatabaseUnavailable,
 }
 
 impl IntoResponse for AuthError {
@@ -137,6 +217,7 @@ impl IntoResponse for AuthError {
             AuthError::Forbidden => (StatusCode::FORBIDDEN, "Access denied"),
             AuthError::AccountDisabled => (StatusCode::FORBIDDEN, "Account disabled"),
             AuthError::DatabaseError => (StatusCode::INTERNAL_SERVER_ERROR, "Database error"),
+            AuthError::DatabaseUnavailable => return error::db_unavailable(),
         };
         let body = Json(serde_json::json!({
             "error": error_message,
@@ -144,3 +225,76 @@ impl IntoResponse for AuthError {
         (status, body).into_response()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{create_jwt, MetricsAccess};
+    use crate::test_support::{call, state_with, user};
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn metrics_token_must_match_exactly() {
+        let state = state_with(|config| config.metrics_token = Some("s3cret-token".to_string())).await;
+        let app = Router::new().route("/ready", get(|_: MetricsAccess| async { "ok" })).with_state(state);
+
+        for (token, expected) in [
+            (Some("s3cret-token"), StatusCode::OK),
+            (Some("s3cret-toke"), StatusCode::UNAUTHORIZED),
+            (Some("s3cret-token2"), StatusCode::UNAUTHORIZED),
+            (Some("wrong"), StatusCode::UNAUTHORIZED),
+            (None, StatusCode::UNAUTHORIZED),
+        ] {
+            let mut request = Request::get("/ready");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+            }
+            let response = app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), expected, "{token:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn tokens_signed_with_a_previous_secret_still_verify() {
+        for previous in [vec!["old-secret".to_string()], vec![]] {
+            let state = state_with(|config| {
+                config.jwt_secret = "new-secret".to_string();
+                config.jwt_previous_secrets = previous.clone();
+            })
+            .await;
+            let (id, _) = user(&state, "alice", "user").await;
+            let mut old_config = state.config.as_ref().clone();
+            old_config.jwt_secret = "old-secret".to_string();
+            let old_token = create_jwt(&old_config, id, "alice", "user", chrono::Duration::minutes(15)).unwrap();
+
+            let (status, _) = call(&state, Method::GET, "/api/me", Some(&old_token), None).await;
+            let expected = if previous.is_empty() { StatusCode::UNAUTHORIZED } else { StatusCode::OK };
+            assert_eq!(status, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn tokens_must_match_the_configured_audience() {
+        let state = state_with(|config| {
+            config.jwt_issuer = Some("sso".to_string());
+            config.jwt_audience = Some("wol".to_string());
+        })
+        .await;
+        let (id, token) = user(&state, "alice", "user").await;
+        let (status, _) = call(&state, Method::GET, "/api/me", Some(&token), None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        for audience in [Some("other"), None] {
+            let mut other_config = state.config.as_ref().clone();
+            other_config.jwt_audience = audience.map(str::to_string);
+            let other_token = create_jwt(&other_config, id, "alice", "user", chrono::Duration::minutes(15)).unwrap();
+            let (status, _) = call(&state, Method::GET, "/api/me", Some(&other_token), None).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{audience:?}");
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use crate::db::AppState;
+
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+const REAL_IP_HEADER: &str = "x-real-ip";
+
+tokio::task_local! {
+    static CLIENT_IP: IpAddr;
+}
+
+/// Effective client address of the request currently being handled, if called from within one.
+pub fn current() -> Option<IpAddr> {
+    CLIENT_IP.try_with(|ip| *ip).ok()
+}
+
+/// One `TRUSTED_PROXIES` entry: a CIDR block, or a single address
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl TrustedProxy {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len)).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len)).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for TrustedProxy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network = canonical(addr.parse().map_err(|_| ())?);
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix {
+            Some(p) => p.parse().map_err(|_| ())?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(());
+        }
+        Ok(TrustedProxy { network, prefix_len })
+    }
+}
+
+impl fmt::Display for TrustedProxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+impl Serialize for TrustedProxy {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// IPv4-mapped IPv6 addresses (dual-stack listeners) compare as plain IPv4
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        v4 => v4,
+    }
+}
+
+/// Whether `peer` is one of the `TRUSTED_PROXIES`, whose forwarded headers can be believed
+pub fn is_trusted(peer: IpAddr, trusted: &[TrustedProxy]) -> bool {
+    let peer = canonical(peer);
+    trusted.iter().any(|proxy| proxy.contains(peer))
+}
+
+/// The address to attribute a request to.
+///
+/// Forwarded headers are only believed when `peer` is a trusted proxy. `X-Forwarded-For`
+/// is read right to left, skipping further trusted hops, so a client can't spoof an
+/// address by prepending its own entries; `X-Real-IP` is the fallback.
+pub fn resolve(peer: IpAddr, headers: &HeaderMap, trusted: &[TrustedProxy]) -> IpAddr {
+    let peer = canonical(peer);
+    let is_trusted = |ip: IpAddr| is_trusted(ip, trusted);
+    if !is_trusted(peer) {
+        return peer;
+    }
+
+    let forwarded: Vec<IpAddr> = headers
+        .get_all(FORWARDED_FOR_HEADER)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|hop| hop.trim().parse().map(canonical))
+        .collect::<Result<_, _>>()
+        .unwrap_or_default();
+    if let Some(last) = forwarded.last() {
+        return forwarded.iter().rev().copied().find(|ip| !is_trusted(*ip)).unwrap_or(*last);
+    }
+
+    headers
+        .get(REAL_IP_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .map(canonical)
+        .unwrap_or(peer)
+}
+
+/// Resolves the client address (see [`resolve`]), makes it available via [`current`]
+/// and records it on the request's tracing span.
+pub async fn middleware(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let ip = resolve(peer.ip(), req.headers(), &state.config.trusted_proxies);
+    tracing::Span::current().record("client_ip", tracing::field::display(ip));
+    CLIENT_IP.scope(ip, next.run(req)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve, TrustedProxy};
+    use axum::http::HeaderMap;
+    use std::net::IpAddr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for &(name, value) in pairs {
+            headers.append(name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn proxies() -> Vec<TrustedProxy> {
+        vec!["10.0.0.0/8".parse().unwrap(), "192.0.2.1".parse().unwrap()]
+    }
+
+    #[test]
+    fn untrusted_peers_are_taken_at_their_word() {
+        let forwarded = headers(&[("x-forwarded-for", "203.0.113.7"), ("x-real-ip", "203.0.113.8")]);
+        assert_eq!(resolve(ip("198.51.100.1"), &forwarded, &proxies()), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn trusted_proxies_pass_the_client_on() {
+        let forwarded = headers(&[("x-forwarded-for", "203.0.113.7")]);
+        assert_eq!(resolve(ip("10.1.2.3"), &forwarded, &proxies()), ip("203.0.113.7"));
+        let real_ip = headers(&[("x-real-ip", "203.0.113.8")]);
+        assert_eq!(resolve(ip("192.0.2.1"), &real_ip, &proxies()), ip("203.0.113.8"));
+        // Dual-stack listeners see IPv4 peers as mapped addresses
+        assert_eq!(resolve(ip("::ffff:10.1.2.3"), &forwarded, &proxies()), ip("203.0.113.7"));
+        assert_eq!(resolve(ip("10.1.2.3"), &HeaderMap::new(), &proxies()), ip("10.1.2.3"));
+    }
+
+    #[test]
+    fn prepended_hops_cannot_spoof_the_client() {
+        // The client sent "1.2.3.4" itself; the proxies appended the rest
+        let forwarded = headers(&[("x-forwarded-for", "1.2.3.4, 203.0.113.7, 10.0.0.5")]);
+        assert_eq!(resolve(ip("10.1.2.3"), &forwarded, &proxies()), ip("203.0.113.7"));
+        let split = headers(&[("x-forwarded-for", "1.2.3.4"), ("x-forwarded-for", "203.0.113.7")]);
+        assert_eq!(resolve(ip("10.1.2.3"), &split, &proxies()), ip("203.0.113.7"));
+    }
+}
@@ -0,0 +1,398 @@
+use crate::client_ip::TrustedProxy;
+use crate::pinger::{self, PingDecision, PingMode};
+use crate::scheduler::MissedFirePolicy;
+use crate::wol;
+use argon2::Params;
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:3000";
+const DEFAULT_DATABASE_URL: &str = "sqlite:wol.db";
+const DEFAULT_DB_MAX_CONNECTIONS: u32 = 5;
+const DEFAULT_SWEEP_INTERVAL_SECS: u64 = 60;
+const DEFAULT_SWEEP_JITTER_SECS: u64 = 5;
+/// Probe timeout for devices without a `ping_timeout_ms` override
+const DEFAULT_PING_TIMEOUT_MS: u64 = 1000;
+const DEFAULT_WAKE_VERIFY_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_WAKE_VERIFY_INTERVAL_SECS: u64 = 2;
+const DEFAULT_SESSION_DAYS: u32 = 1;
+const DEFAULT_REMEMBER_ME_DAYS: u32 = 30;
+/// Upper bound for `SESSION_DAYS` / `REMEMBER_ME_DAYS`
+const MAX_SESSION_DAYS: u32 = 365;
+const DEFAULT_FAILED_LOGIN_WINDOW_MINUTES: u32 = 60;
+const DEFAULT_LOCKOUT_MINUTES: u32 = 15;
+/// Port the device agent listens on (see Agents.md)
+const DEFAULT_AGENT_PORT: u16 = 3001;
+const DEFAULT_SCHEDULE_WAKE_RETRIES: u32 = 3;
+const DEFAULT_MAX_BATCH_ITEMS: usize = 100;
+const DEFAULT_AUDIT_RETENTION_DAYS: u32 = 365;
+const DEFAULT_STATUS_HISTORY_RETENTION_DAYS: u32 = 90;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 30;
+
+/// Effective runtime configuration, loaded once at startup from the
+/// environment and CLI and shared through `AppState`.
+///
+/// Secrets are `#[serde(skip)]`, so the serialized form is safe to show to admins.
+#[derive(Clone, Serialize, ToSchema)]
+pub struct Config {
+    /// `LISTEN_ADDR`
+    pub listen_addr: String,
+    /// `DATABASE_URL`
+    #[serde(skip)]
+    pub database_url: String,
+    /// `DB_MAX_CONNECTIONS`
+    pub db_max_connections: u32,
+    /// `MAX_DEVICES`, unlimited when unset
+    pub max_devices: Option<i64>,
+    /// `--static-dir` / `STATIC_DIR`
+    pub static_dir: String,
+    /// `--response-compression` / `RESPONSE_COMPRESSION`
+    pub response_compression: bool,
+    /// `REQUEST_TIMEOUT_SECS`, 0 disables the timeout
+    pub request_timeout_secs: u64,
+    /// `TRUSTED_PROXIES`, comma-separated CIDR blocks allowed to set `X-Forwarded-For`/`X-Real-IP`
+    #[schema(value_type = Vec<String>)]
+    pub trusted_proxies: Vec<TrustedProxy>,
+    /// `FORCE_HTTPS`, redirect plain HTTP requests and send HSTS
+    pub force_https: bool,
+
+    /// `JWT_SECRET`; random per process when unset
+    #[serde(skip)]
+    pub jwt_secret: String,
+    /// `JWT_SECRET_PREVIOUS`, comma-separated
+    #[serde(skip)]
+    pub jwt_previous_secrets: Vec<String>,
+    /// `JWT_ISSUER`, set as and required in the `iss` claim
+    pub jwt_issuer: Option<String>,
+    /// `JWT_AUDIENCE`, set as and required in the `aud` claim
+    pub jwt_audience: Option<String>,
+    /// `SESSION_DAYS`
+    pub session_days: u32,
+    /// `REMEMBER_ME_DAYS`
+    pub remember_me_days: u32,
+    /// `MAX_SESSIONS_PER_USER`, unlimited when unset
+    pub max_sessions_per_user: Option<i64>,
+    /// `SESSION_IDLE_MINUTES`; sessions never expire from inactivity when unset
+    pub session_idle_minutes: Option<i64>,
+    /// `WELCOME_MESSAGE_TEMPLATE`, with `{username}` and `{password}` placeholders
+    pub welcome_message_template: Option<String>,
+    /// `FAILED_LOGIN_WINDOW_MINUTES`
+    pub failed_login_window_minutes: u32,
+    /// `LOCKOUT_THRESHOLD`, 0 disables lockout
+    pub lockout_threshold: u32,
+    /// `LOCKOUT_MINUTES`
+    pub lockout_minutes: u32,
+    /// `ARGON2_MEMORY_KIB`
+    pub argon2_memory_kib: u32,
+    /// `ARGON2_ITERATIONS`
+    pub argon2_iterations: u32,
+    /// `ARGON2_PARALLELISM`
+    pub argon2_parallelism: u32,
+
+    /// `PINGER_INTERVAL_SECS`
+    pub sweep_interval_secs: u64,
+    /// `PINGER_JITTER_SECS`, at most half the interval
+    pub sweep_jitter_secs: u64,
+    pub default_ping_timeout_ms: u64,
+    /// `PING_MODE`
+    pub ping_mode: PingMode,
+    /// `PING_COUNT`, probes per device and sweep
+    pub ping_count: u32,
+    /// `PING_DECISION`
+    pub ping_decision: PingDecision,
+    /// `OFFLINE_AFTER_MISSES`, failed sweeps in a row before an online device goes offline
+    pub offline_after_misses: u32,
+    /// `WOL_DEFAULT_PORT`, for devices and ad-hoc wakes without a port of their own
+    pub wol_default_port: u16,
+    /// `WOL_SOURCE_PORT`, the local port magic packets are sent from; ephemeral when unset
+    pub wol_source_port: Option<u16>,
+    /// `LENIENT_MAC_PARSING`, accept MACs with other or no separators
+    pub lenient_mac_parsing: bool,
+    /// `DEFAULT_BROADCAST_ADDR`, for new devices created without a broadcast address
+    #[schema(value_type = String)]
+    pub default_broadcast_addr: IpAddr,
+    /// `DEFAULT_MONITORING_ENABLED`, for new devices created without `monitoring_enabled`
+    pub default_monitoring_enabled: bool,
+    /// `WAKE_VERIFY_TIMEOUT_SECS`
+    pub wake_verify_timeout_secs: u64,
+    /// `WAKE_VERIFY_INTERVAL_SECS`
+    pub wake_verify_interval_secs: u64,
+
+    /// `ENABLE_AGENT_CONTROL`; when off, agent endpoints answer 501 without contacting agents
+    pub agent_control_enabled: bool,
+    /// `AGENT_PORT`
+    pub agent_port: u16,
+    /// `AGENT_SECRET`
+    #[serde(skip)]
+    pub agent_secret: Option<String>,
+    /// Whether an `AGENT_SECRET` is configured
+    pub agent_auth_enabled: bool,
+
+    /// `SCHEDULE_MISSED_POLICY`
+    pub schedule_missed_policy: MissedFirePolicy,
+    /// `SCHEDULE_WAKE_RETRIES`, 0 disables retries
+    pub schedule_wake_retries: u32,
+
+    /// `MAX_BATCH_ITEMS`, per list in batch requests
+    pub max_batch_items: usize,
+
+    /// `AUDIT_RETENTION_DAYS`, 0 keeps audit entries forever
+    pub audit_retention_days: u32,
+    /// `STATUS_HISTORY_RETENTION_DAYS`, 0 keeps status transitions forever
+    pub status_history_retention_days: u32,
+
+    /// `METRICS_TOKEN`; operational endpoints are open when unset
+    #[serde(skip)]
+    pub metrics_token: Option<String>,
+    /// Whether a `METRICS_TOKEN` is configured
+    pub metrics_auth_enabled: bool,
+}
+
+impl Config {
+    /// Reads every setting from the environment, warning about and replacing invalid values.
+    pub fn load(static_dir: &Path, response_compression: bool) -> Self {
+        let jwt_secret = std::env::var("JWT_SECRET").unwrap_or_else(|_| {
+            println!("WARNING: JWT_SECRET not set, using random secret. Tokens will be invalid after restart.");
+            use rand::distr::{Alphanumeric, SampleString};
+            Alphanumeric.sample_string(&mut rand::rng(), 32)
+        });
+        let jwt_previous_secrets = std::env::var("JWT_SECRET_PREVIOUS")
+            .map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let (argon2_memory_kib, argon2_iterations, argon2_parallelism) = {
+            let m = env_or("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST);
+            let t = env_or("ARGON2_ITERATIONS", Params::DEFAULT_T_COST);
+            let p = env_or("ARGON2_PARALLELISM", Params::DEFAULT_P_COST);
+            match Params::new(m, t, p, None) {
+                Ok(_) => (m, t, p),
+                Err(e) => {
+                    println!("WARNING: Invalid argon2 parameters ({}), using defaults.", e);
+                    (Params::DEFAULT_M_COST, Params::DEFAULT_T_COST, Params::DEFAULT_P_COST)
+                }
+            }
+        };
+
+        let sweep_interval_secs = positive("PINGER_INTERVAL_SECS", DEFAULT_SWEEP_INTERVAL_SECS);
+        // Never let the jitter swallow the whole interval
+        let sweep_jitter_secs = env_or("PINGER_JITTER_SECS", DEFAULT_SWEEP_JITTER_SECS).min(sweep_interval_secs / 2);
+
+        let agent_secret = std::env::var("AGENT_SECRET").ok().filter(|s| !s.is_empty());
+        let metrics_token = std::env::var("METRICS_TOKEN").ok().filter(|s| !s.is_empty());
+
+        let trusted_proxies = trusted_proxies("TRUSTED_PROXIES");
+        let force_https = env_or("FORCE_HTTPS", false);
+        if force_https && trusted_proxies.is_empty() {
+            println!("WARNING: FORCE_HTTPS is set without TRUSTED_PROXIES; X-Forwarded-Proto is ignored and every request is redirected");
+        }
+
+        Config {
+            listen_addr: std::env::var("LISTEN_ADDR").unwrap_or_else(|_| DEFAULT_LISTEN_ADDR.to_string()),
+            database_url: std::env::var("DATABASE_URL").unwrap_or_else(|_| DEFAULT_DATABASE_URL.to_string()),
+            db_max_connections: positive("DB_MAX_CONNECTIONS", DEFAULT_DB_MAX_CONNECTIONS),
+            max_devices: limit("MAX_DEVICES", "devices"),
+            static_dir: static_dir.display().to_string(),
+            response_compression,
+            request_timeout_secs: env_or("REQUEST_TIMEOUT_SECS", DEFAULT_REQUEST_TIMEOUT_SECS),
+            trusted_proxies,
+            force_https,
+            jwt_secret,
+            jwt_previous_secrets,
+            jwt_issuer: std::env::var("JWT_ISSUER").ok().filter(|s| !s.is_empty()),
+            jwt_audience: std::env::var("JWT_AUDIENCE").ok().filter(|s| !s.is_empty()),
+            session_days: session_days("SESSION_DAYS", DEFAULT_SESSION_DAYS),
+            remember_me_days: session_days("REMEMBER_ME_DAYS", DEFAULT_REMEMBER_ME_DAYS),
+            max_sessions_per_user: limit("MAX_SESSIONS_PER_USER", "sessions"),
+            session_idle_minutes: limit("SESSION_IDLE_MINUTES", "idle sessions"),
+            welcome_message_template: std::env::var("WELCOME_MESSAGE_TEMPLATE").ok().filter(|s| !s.trim().is_empty()),
+            failed_login_window_minutes: env_or("FAILED_LOGIN_WINDOW_MINUTES", DEFAULT_FAILED_LOGIN_WINDOW_MINUTES),
+            lockout_threshold: env_or("LOCKOUT_THRESHOLD", 0),
+            lockout_minutes: env_or("LOCKOUT_MINUTES", DEFAULT_LOCKOUT_MINUTES),
+            argon2_memory_kib,
+            argon2_iterations,
+            argon2_parallelism,
+            sweep_interval_secs,
+            sweep_jitter_secs,
+            default_ping_timeout_ms: DEFAULT_PING_TIMEOUT_MS,
+            ping_mode: env_or("PING_MODE", PingMode::default()),
+            ping_count: positive("PING_COUNT", 1).min(pinger::MAX_PING_COUNT),
+            ping_decision: env_or("PING_DECISION", PingDecision::default()),
+            offline_after_misses: positive("OFFLINE_AFTER_MISSES", 1),
+            wol_default_port: positive("WOL_DEFAULT_PORT", wol::WOL_PORT),
+            wol_source_port: Some(env_or("WOL_SOURCE_PORT", 0)).filter(|&port| port != 0),
+            lenient_mac_parsing: env_or("LENIENT_MAC_PARSING", false),
+            default_broadcast_addr: env_or("DEFAULT_BROADCAST_ADDR", IpAddr::V4(Ipv4Addr::BROADCAST)),
+            wake_verify_timeout_secs: positive("WAKE_VERIFY_TIMEOUT_SECS", DEFAULT_WAKE_VERIFY_TIMEOUT_SECS),
+            wake_verify_interval_secs: positive("WAKE_VERIFY_INTERVAL_SECS", DEFAULT_WAKE_VERIFY_INTERVAL_SECS),
+            agent_control_enabled: env_or("ENABLE_AGENT_CONTROL", true),
+            default_monitoring_enabled: env_or("DEFAULT_MONITORING_ENABLED", true),
+            agent_port: env_or("AGENT_PORT", DEFAULT_AGENT_PORT),
+            agent_auth_enabled: agent_secret.is_some(),
+            agent_secret,
+            schedule_missed_policy: env_or("SCHEDULE_MISSED_POLICY", MissedFirePolicy::default()),
+            schedule_wake_retries: env_or("SCHEDULE_WAKE_RETRIES", DEFAULT_SCHEDULE_WAKE_RETRIES),
+            max_batch_items: positive("MAX_BATCH_ITEMS", DEFAULT_MAX_BATCH_ITEMS),
+            audit_retention_days: env_or("AUDIT_RETENTION_DAYS", DEFAULT_AUDIT_RETENTION_DAYS),
+            status_history_retention_days: env_or("STATUS_HISTORY_RETENTION_DAYS", DEFAULT_STATUS_HISTORY_RETENTION_DAYS),
+            metrics_auth_enabled: metrics_token.is_some(),
+            metrics_token,
+        }
+    }
+
+    /// Refresh token lifetime; remember-me sessions use `remember_me_days`.
+    pub fn session_duration(&self, remember_me: bool) -> chrono::Duration {
+        let days = if remember_me { self.remember_me_days } else { self.session_days };
+        chrono::Duration::days(days.into())
+    }
+
+    /// Parameters new password hashes are created with (validated in [`Config::load`])
+    pub fn argon2_params(&self) -> Params {
+        Params::new(self.argon2_memory_kib, self.argon2_iterations, self.argon2_parallelism, None)
+            .unwrap_or_default()
+    }
+
+    /// Deadline for API handlers; `None` when disabled
+    pub fn request_timeout(&self) -> Option<Duration> {
+        (self.request_timeout_secs > 0).then(|| Duration::from_secs(self.request_timeout_secs))
+    }
+
+    pub fn sweep_interval(&self) -> Duration {
+        Duration::from_secs(self.sweep_interval_secs)
+    }
+
+    pub fn sweep_jitter(&self) -> Duration {
+        Duration::from_secs(self.sweep_jitter_secs)
+    }
+
+    pub fn default_ping_timeout(&self) -> Duration {
+        Duration::from_millis(self.default_ping_timeout_ms)
+    }
+
+    pub fn wake_verify_timeout(&self) -> Duration {
+        Duration::from_secs(self.wake_verify_timeout_secs)
+    }
+
+    pub fn wake_verify_interval(&self) -> Duration {
+        Duration::from_secs(self.wake_verify_interval_secs)
+    }
+}
+
+/// `name` parsed as `T`, or `default` (with a warning) when set but invalid
+fn env_or<T: FromStr + std::fmt::Display>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(v) => v.trim().parse().unwrap_or_else(|_| {
+            println!("WARNING: {} has an invalid value, using default {}", name, default);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+/// Like [`env_or`], but zero is rejected as well
+fn positive<T: FromStr + std::fmt::Display + PartialEq + Default + Copy>(name: &str, default: T) -> T {
+    let value = env_or(name, default);
+    if value == T::default() {
+        println!("WARNING: {} must be positive, using default {}", name, default);
+        return default;
+    }
+    value
+}
+
+/// Optional positive limit; unset or invalid (with a warning) means unlimited
+fn limit(name: &str, what: &str) -> Option<i64> {
+    match std::env::var(name) {
+        Ok(v) => match v.trim().parse::<i64>() {
+            Ok(n) if n > 0 => Some(n),
+            _ => {
+                println!("WARNING: {} must be a positive number, {} are unlimited", name, what);
+                None
+            }
+        },
+        Err(_) => None,
+    }
+}
+
+/// Comma-separated list of CIDR blocks; invalid entries are skipped with a warning
+fn trusted_proxies(name: &str) -> Vec<TrustedProxy> {
+    let Ok(v) = std::env::var(name) else {
+        return Vec::new();
+    };
+    v.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|entry| {
+            entry
+                .parse()
+                .inspect_err(|_| println!("WARNING: {} entry '{}' is not a valid CIDR block, ignoring it", name, entry))
+                .ok()
+        })
+        .collect()
+}
+
+fn session_days(name: &str, default: u32) -> u32 {
+    match env_or(name, default) {
+        0 => {
+            println!("WARNING: {} must be at least 1, using default {}", name, default);
+            default
+        }
+        days if days > MAX_SESSION_DAYS => {
+            println!("WARNING: {} is capped at {} days", name, MAX_SESSION_DAYS);
+            MAX_SESSION_DAYS
+        }
+        days => days,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, DEFAULT_DB_MAX_CONNECTIONS, DEFAULT_LISTEN_ADDR, DEFAULT_SWEEP_INTERVAL_SECS, DEFAULT_SWEEP_JITTER_SECS};
+    use std::path::Path;
+
+    /// Sets (or with `None` removes) variables no other test depends on
+    fn set_env(vars: &[(&str, Option<&str>)]) {
+        for (name, value) in vars {
+            // SAFETY: std serializes its own environment access, and nothing in the
+            // tests reads these variables through libc
+            unsafe {
+                match value {
+                    Some(value) => std::env::set_var(name, value),
+                    None => std::env::remove_var(name),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn loads_env_values_and_falls_back_to_defaults() {
+        set_env(&[
+            ("LISTEN_ADDR", Some("127.0.0.1:8080")),
+            ("PINGER_INTERVAL_SECS", Some("90")),
+            // Invalid, so the default applies
+            ("DB_MAX_CONNECTIONS", Some("0")),
+            // Capped at half the interval
+            ("PINGER_JITTER_SECS", Some("100")),
+        ]);
+        let config = Config::load(Path::new("static_files"), true);
+        assert_eq!(config.listen_addr, "127.0.0.1:8080");
+        assert_eq!(config.sweep_interval_secs, 90);
+        assert_eq!(config.db_max_connections, DEFAULT_DB_MAX_CONNECTIONS);
+        assert_eq!(config.sweep_jitter_secs, 45);
+        assert!(config.response_compression);
+
+        set_env(&[("LISTEN_ADDR", None), ("PINGER_INTERVAL_SECS", None), ("DB_MAX_CONNECTIONS", None), ("PINGER_JITTER_SECS", None)]);
+        let config = Config::load(Path::new("static_files"), false);
+        assert_eq!(config.listen_addr, DEFAULT_LISTEN_ADDR);
+        assert_eq!(config.sweep_interval_secs, DEFAULT_SWEEP_INTERVAL_SECS);
+        assert_eq!(config.sweep_jitter_secs, DEFAULT_SWEEP_JITTER_SECS);
+    }
+}
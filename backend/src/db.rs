@@ -1,6 +1,31 @@
+use crate::agent::CircuitBreaker;
+use crate::config::Config;
+use crate::events::EventSender;
+use crate::pinger::Prober;
+use crate::wol::WakeSender;
 use sqlx::{Pool, Sqlite};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Pool<Sqlite>,
+    /// Device status changes, fanned out to `/api/devices/events` subscribers
+    pub events: EventSender,
+    pub config: Arc<Config>,
+    /// Fast-fails agent calls to devices whose agent keeps failing
+    pub agent_breaker: Arc<CircuitBreaker>,
+    /// Toggled by admins via `PUT /api/maintenance`; not persisted, so a restart turns it off
+    pub maintenance: Arc<AtomicBool>,
+    /// Sends every magic packet; `wol::UdpWakeSender` outside of tests
+    pub wake_sender: Arc<dyn WakeSender>,
+    /// Runs every liveness probe; `pinger::NetworkProber` outside of tests
+    pub prober: Arc<dyn Prober>,
+}
+
+impl AppState {
+    /// While on, status sweeps and scheduled wakes are paused and wakes and shutdowns refused
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,141 @@
+use axum::{
+    body::{to_bytes, Body, Bytes, HttpBody},
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Seconds clients are asked to wait before retrying after a transient DB failure
+const DB_RETRY_AFTER_SECS: &str = "5";
+
+/// Plain-text error bodies are short messages; anything that may be bigger is left untouched
+const MAX_ERROR_BODY_BYTES: usize = 16 * 1024;
+
+// SQLite primary result codes (extended codes share the low byte)
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+
+/// True for database failures that are expected to clear up on their own,
+/// e.g. an exhausted pool or SQLite being busy/locked during a checkpoint.
+pub fn is_transient(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Database(db_err) => db_err
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .map(|code| matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// 503 response telling the client to retry shortly.
+pub fn db_unavailable() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, DB_RETRY_AFTER_SECS)],
+        "Database temporarily unavailable",
+    )
+        .into_response()
+}
+
+/// Maps a database error to a response: 503 with `Retry-After` for transient
+/// failures, otherwise 500 with the given message.
+pub fn db_error(err: &sqlx::Error, message: &'static str) -> Response {
+    if is_transient(err) {
+        db_unavailable()
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
+    }
+}
+
+/// Reads a plain-text error body for rewriting. Bodies that may exceed
+/// `MAX_ERROR_BODY_BYTES`, e.g. streamed ones of unknown size, are handed back unread.
+pub async fn read_error_body(body: Body) -> Result<Bytes, Body> {
+    let fits = body.size_hint().upper().is_some_and(|len| len <= MAX_ERROR_BODY_BYTES as u64);
+    if !fits {
+        return Err(body);
+    }
+    Ok(to_bytes(body, MAX_ERROR_BODY_BYTES).await.unwrap_or_default())
+}
+
+fn accepts_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|range| range.split(';').next().is_some_and(|media| media.trim().eq_ignore_ascii_case("application/json")))
+}
+
+/// Wraps plain-text error bodies as `{"error": "..."}` for clients that send
+/// `Accept: application/json`; everyone else keeps getting the text.
+pub async fn negotiate(req: Request, next: Next) -> Response {
+    let wants_json = accepts_json(req.headers());
+    let res = next.run(req).await;
+
+    let is_plain_text = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("text/plain"));
+    if !wants_json || !is_plain_text || !(res.status().is_client_error() || res.status().is_server_error()) {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let message = match read_error_body(body).await {
+        Ok(message) => message,
+        Err(body) => return Response::from_parts(parts, body),
+    };
+    let json = serde_json::json!({ "error": String::from_utf8_lossy(&message) }).to_string();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    Response::from_parts(parts, Body::from(json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{db_error, negotiate, DB_RETRY_AFTER_SECS, MAX_ERROR_BODY_BYTES};
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    /// Body of `GET /` answered with a 500 carrying `message`, asking for JSON
+    async fn error_body(message: String) -> String {
+        let app = Router::new()
+            .route("/", get(move || async move { (StatusCode::INTERNAL_SERVER_ERROR, message) }))
+            .layer(axum::middleware::from_fn(negotiate));
+        let request = Request::get("/").header(header::ACCEPT, "application/json").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn wraps_short_errors_as_json() {
+        assert_eq!(error_body("Boom".to_string()).await, r#"{"error":"Boom"}"#);
+    }
+
+    #[tokio::test]
+    async fn leaves_oversized_errors_untouched() {
+        let message = "x".repeat(MAX_ERROR_BODY_BYTES + 1);
+        assert_eq!(error_body(message.clone()).await, message);
+    }
+
+    #[test]
+    fn pool_timeouts_ask_clients_to_retry() {
+        let response = db_error(&sqlx::Error::PoolTimedOut, "Failed to list devices");
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], DB_RETRY_AFTER_SECS);
+
+        let response = db_error(&sqlx::Error::RowNotFound, "Failed to list devices");
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!response.headers().contains_key(header::RETRY_AFTER));
+    }
+}
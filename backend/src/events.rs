@@ -0,0 +1,28 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+
+/// Events a slow subscriber may fall behind by before it starts missing some
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Published whenever a device's online status changes
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeviceStatusEvent {
+    pub device_id: i64,
+    pub is_online: bool,
+    pub changed_at: chrono::NaiveDateTime,
+    /// Not sent; lets the stream leave out devices the subscriber can't see
+    #[serde(skip)]
+    pub owner_id: Option<i64>,
+}
+
+pub type EventSender = broadcast::Sender<DeviceStatusEvent>;
+
+pub fn channel() -> EventSender {
+    broadcast::channel(EVENT_CHANNEL_CAPACITY).0
+}
+
+/// Publishes an event; having no subscribers is not an error.
+pub fn publish(events: &EventSender, event: DeviceStatusEvent) {
+    let _ = events.send(event);
+}
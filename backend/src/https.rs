@@ -0,0 +1,127 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::net::SocketAddr;
+
+use crate::client_ip;
+use crate::db::AppState;
+
+const FORWARDED_PROTO_HEADER: &str = "x-forwarded-proto";
+/// One year; without `includeSubDomains`, other hosts on the same domain aren't affected
+const HSTS_VALUE: HeaderValue = HeaderValue::from_static("max-age=31536000");
+
+/// Liveness checks usually talk plain HTTP to the container directly
+fn is_exempt(path: &str) -> bool {
+    path == "/api/health" || path.starts_with("/api/health/")
+}
+
+/// Whether the client reached us over HTTPS. The server itself only speaks plain
+/// HTTP, so that is only known from `X-Forwarded-Proto` set by a trusted proxy.
+fn is_https(req: &Request, peer: SocketAddr, state: &AppState) -> bool {
+    if !client_ip::is_trusted(peer.ip(), &state.config.trusted_proxies) {
+        return false;
+    }
+    // The nearest proxy appends last
+    req.headers()
+        .get(FORWARDED_PROTO_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.rsplit(',').next())
+        .is_some_and(|proto| proto.trim().eq_ignore_ascii_case("https"))
+}
+
+/// With `FORCE_HTTPS`, redirects plain HTTP requests to the same URL on HTTPS with a
+/// `308` (which keeps the method and body) and adds HSTS to HTTPS responses.
+pub async fn middleware(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if !state.config.force_https || is_exempt(req.uri().path()) {
+        return next.run(req).await;
+    }
+
+    if is_https(&req, peer, &state) {
+        let mut response = next.run(req).await;
+        response.headers_mut().insert(header::STRICT_TRANSPORT_SECURITY, HSTS_VALUE);
+        return response;
+    }
+
+    let Some(host) = req.headers().get(header::HOST).and_then(|v| v.to_str().ok()) else {
+        return (StatusCode::BAD_REQUEST, "HTTPS required").into_response();
+    };
+    let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    match HeaderValue::try_from(format!("https://{}{}", host, path)) {
+        Ok(location) => (StatusCode::PERMANENT_REDIRECT, [(header::LOCATION, location)]).into_response(),
+        Err(_) => (StatusCode::BAD_REQUEST, "HTTPS required").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::state_with;
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{header, Request, StatusCode},
+        routing::post,
+        Router,
+    };
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    /// Sends a POST to `uri` from `peer` through the middleware, with `FORCE_HTTPS` on
+    /// and 10.0.0.1 as the trusted proxy
+    async fn send(peer: &str, uri: &str, forwarded_proto: Option<&str>) -> axum::response::Response {
+        let state = state_with(|config| {
+            config.force_https = true;
+            config.trusted_proxies = vec!["10.0.0.1".parse().unwrap()];
+        })
+        .await;
+        let app = Router::new()
+            .route("/api/devices", post(|| async { "created" }))
+            .route("/api/health", post(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), super::middleware))
+            .with_state(state);
+
+        let mut request = Request::post(uri).header(header::HOST, "wol.example.com");
+        if let Some(proto) = forwarded_proto {
+            request = request.header("x-forwarded-proto", proto);
+        }
+        let mut request = request.body(Body::empty()).unwrap();
+        let peer: SocketAddr = format!("{}:40000", peer).parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(peer));
+        app.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn plain_http_is_redirected_keeping_the_method() {
+        let response = send("10.0.0.1", "/api/devices?x=1", Some("http")).await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()[header::LOCATION], "https://wol.example.com/api/devices?x=1");
+        assert!(!response.headers().contains_key(header::STRICT_TRANSPORT_SECURITY));
+    }
+
+    #[tokio::test]
+    async fn only_https_responses_get_hsts() {
+        let response = send("10.0.0.1", "/api/devices", Some("https")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::STRICT_TRANSPORT_SECURITY], "max-age=31536000");
+    }
+
+    #[tokio::test]
+    async fn forwarded_proto_from_untrusted_peers_is_ignored() {
+        let response = send("198.51.100.1", "/api/devices", Some("https")).await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    }
+
+    #[tokio::test]
+    async fn health_checks_stay_on_plain_http() {
+        let response = send("198.51.100.1", "/api/health", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(header::STRICT_TRANSPORT_SECURITY));
+    }
+}
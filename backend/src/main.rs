@@ -1,22 +1,41 @@
+mod agent;
+#[cfg(unix)]
+mod arp;
+mod audit;
+mod client_ip;
+mod config;
 mod db;
+mod error;
+mod events;
+mod https;
 mod api;
 mod auth;
+mod pinger;
+mod request_id;
+mod retention;
+mod scheduler;
+mod selfcheck;
+#[cfg(test)]
+mod test_support;
+mod wol;
 
 use sqlx::sqlite::SqlitePoolOptions;
-use tower_http::services::ServeDir;
-use axum::{Router, routing::{get, post, put, delete}};
-use api::{users, devices};
+use tower_http::{compression::CompressionLayer, services::ServeDir, timeout::TimeoutLayer};
+use axum::{Router, routing::{get, post, put, delete, MethodRouter}};
+use api::{users, devices, groups, scenes, activity, audit_log, backup, config as config_api};
 use utoipa::{OpenApi, Modify};
 use utoipa::openapi::security::{SecurityScheme, HttpAuthScheme, Http};
-use utoipa_swagger_ui::SwaggerUi;
+use utoipa_swagger_ui::{Config, SwaggerUi};
 use clap::Parser;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
-use surge_ping::ping;
-use std::net::IpAddr;
 
-use crate::{api::users::UserApi, api::devices::DeviceApi, db::AppState};
+use crate::auth::MetricsAccess;
+use serde::Serialize;
+use crate::{api::users::UserApi, api::devices::DeviceApi, api::groups::GroupApi, api::scenes::SceneApi, api::activity::ActivityApi, api::audit_log::AuditLogApi, api::config::ConfigApi, api::backup::BackupApi, db::AppState};
 
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{body::Bytes, extract::State, http::{header, StatusCode}, response::IntoResponse, Json};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -24,6 +43,26 @@ struct Args {
     /// Sets the initial admin password
     #[arg(long)]
     admin_password: Option<String>,
+
+    /// Accept an --admin-password that fails the strength check
+    #[arg(long)]
+    allow_weak_admin_password: bool,
+
+    /// Directory the frontend is served from
+    #[arg(long, env = "STATIC_DIR", default_value = "./static_files")]
+    static_dir: PathBuf,
+
+    /// Check stored device MAC/IP/broadcast addresses at startup and log invalid ones
+    #[arg(long)]
+    validate_on_start: bool,
+
+    /// Like --validate-on-start, and also rewrite addresses that are only badly formatted
+    #[arg(long)]
+    fix_on_start: bool,
+
+    /// Compress responses (gzip/brotli) when the client accepts it
+    #[arg(long, env = "RESPONSE_COMPRESSION", default_value_t = true, action = clap::ArgAction::Set)]
+    response_compression: bool,
 }
 
 pub async fn health_check(
@@ -37,6 +76,56 @@ pub async fn health_check(
     }
 }
 
+#[derive(Serialize)]
+struct ReadinessResponse {
+    version: &'static str,
+    database: &'static str,
+    devices: i64,
+    devices_online: i64,
+    pending_schedules: i64,
+}
+
+/// Detailed readiness, behind `METRICS_TOKEN` when one is set.
+/// `/api/health` stays open for plain liveness checks.
+pub async fn health_ready(
+    _access: MetricsAccess,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let counts = sqlx::query!(
+        r#"SELECT
+            (SELECT COUNT(*) FROM devices) as "devices!: i64",
+            (SELECT COUNT(*) FROM devices WHERE is_online = 1) as "devices_online!: i64",
+            (SELECT COUNT(*) FROM schedules) as "pending_schedules!: i64""#
+    )
+    .fetch_one(&state.db)
+    .await;
+
+    match counts {
+        Ok(c) => Json(ReadinessResponse {
+            version: env!("CARGO_PKG_VERSION"),
+            database: "ok",
+            devices: c.devices,
+            devices_online: c.devices_online,
+            pending_schedules: c.pending_schedules,
+        })
+        .into_response(),
+        Err(_) => (StatusCode::SERVICE_UNAVAILABLE, "Database unavailable").into_response(),
+    }
+}
+
+/// Serves the merged OpenAPI document for tooling such as client generators.
+/// The spec only changes with a new build, so it is cacheable and open to any origin.
+async fn openapi_json(spec: Bytes) -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            (header::CACHE_CONTROL, "public, max-age=3600"),
+            (header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"),
+        ],
+        spec,
+    )
+}
+
 #[derive(OpenApi)]
 #[openapi(
     // We leave 'paths' empty here because we are merging modules below
@@ -66,23 +155,148 @@ impl Modify for SecurityAddon {
     }
 }
 
+/// The `/api` router, keeping track of every path registered on it so tests can
+/// check the OpenAPI document against them.
+struct ApiRoutes {
+    router: Router<AppState>,
+    #[cfg(test)]
+    paths: Vec<&'static str>,
+    request_timeout: Option<Duration>,
+}
+
+impl ApiRoutes {
+    /// Adds a route whose handlers answer `504` once `REQUEST_TIMEOUT_SECS` have passed.
+    /// Only the handler is timed; streamed bodies such as event streams can run on.
+    fn route(self, path: &'static str, method_router: MethodRouter<AppState>) -> Self {
+        let method_router = match self.request_timeout {
+            Some(timeout) => method_router.layer(TimeoutLayer::with_status_code(StatusCode::GATEWAY_TIMEOUT, timeout)),
+            None => method_router,
+        };
+        self.long_running_route(path, method_router)
+    }
+
+    /// Adds a route exempt from the request timeout, for handlers that wait on devices
+    /// by design and bound that wait themselves (e.g. `WAKE_VERIFY_TIMEOUT_SECS`).
+    fn long_running_route(mut self, path: &'static str, method_router: MethodRouter<AppState>) -> Self {
+        self.router = self.router.route(path, method_router);
+        #[cfg(test)]
+        self.paths.push(path);
+        self
+    }
+}
+
+/// Every `/api` route, relative to `/api`
+fn all_routes(request_timeout: Option<Duration>) -> ApiRoutes {
+    ApiRoutes {
+        router: Router::new(),
+        #[cfg(test)]
+        paths: Vec::new(),
+        request_timeout,
+    }
+        .route("/setup", post(users::setup))
+        .route("/login", post(users::login))
+        .route("/refresh", post(users::refresh_token))
+        .route("/logout", post(users::logout_user))
+        .route("/logout-all", post(users::logout_all))
+        .route("/users", get(users::list_users).post(users::create_user))
+        .route("/users/available", get(users::username_available))
+        .route("/users/{id}", get(users::get_user).delete(users::delete_user))
+        .route("/users/{id}/stats", get(users::user_stats))
+        .route("/users/{id}/unlock", post(users::unlock_user))
+        .route("/users/{id}/force-logout", post(users::force_logout))
+        .route("/users/{id}/role", put(users::update_role))
+        .route("/users/{id}/status", put(users::update_status))
+        .route("/users/{id}/reset-password", post(users::admin_reset_password))
+        .route("/users/{id}/impersonate", post(users::impersonate_user))
+        .route("/change-password", post(users::change_password))
+        .route("/me", get(users::get_me))
+        .route("/config", get(config_api::get_config))
+        .route("/features", get(config_api::get_features))
+        .route("/maintenance", put(config_api::set_maintenance))
+        .route("/backup", get(backup::get_backup))
+        .route("/restore", post(backup::restore_backup))
+        // Devices
+        .route("/devices", get(devices::list_devices).post(devices::create_device))
+        .route("/devices/events", get(devices::device_events))
+        .route("/devices/status", post(devices::device_status))
+        .route("/devices/{id}", delete(devices::delete_device).put(devices::update_device))
+        .route("/devices/{id}/clone", post(devices::clone_device))
+        .route("/devices/{id}/tags", post(devices::add_device_tag))
+        .route("/devices/{id}/tags/{tag}", delete(devices::remove_device_tag))
+        .route("/devices/{id}/favorite", post(devices::add_favorite).delete(devices::remove_favorite))
+        .route("/devices/{id}/status", put(devices::update_device_enabled))
+        .route("/devices/{id}/owner", put(devices::update_device_owner))
+        .route("/devices/{id}/group", put(devices::update_device_group))
+        .route("/devices/{id}/history", get(devices::device_history))
+        .route("/devices/{id}/schedules", get(devices::list_schedules).post(devices::create_schedule))
+        .route("/devices/{id}/schedules/{schedule_id}", delete(devices::delete_schedule))
+        .long_running_route("/devices/{id}/wake", post(devices::wake_device))
+        .long_running_route("/devices/{id}/test-wake", post(devices::test_wake))
+        .long_running_route("/devices/{id}/wake-and-ready", post(devices::wake_and_ready))
+        .route("/devices/{id}/wake-preflight", get(devices::wake_preflight))
+        .route("/wake", post(devices::wake_mac))
+        .route("/devices/{id}/shutdown", post(devices::shutdown_device))
+        .route("/devices/{id}/ping", post(devices::ping_device))
+        .route("/devices/{id}/agent/ping", post(devices::ping_agent))
+        // Groups
+        .route("/groups", get(groups::list_groups).post(groups::create_group))
+        .route("/groups/{id}", put(groups::update_group).delete(groups::delete_group))
+        .route("/groups/{id}/members", put(groups::set_group_members))
+        .route("/groups/{id}/summary", get(groups::group_summary))
+        .long_running_route("/groups/{id}/wake", post(groups::wake_group))
+        // Scenes
+        .route("/scenes", get(scenes::list_scenes).post(scenes::create_scene))
+        .route("/scenes/{id}", put(scenes::update_scene).delete(scenes::delete_scene))
+        .long_running_route("/scenes/{id}/activate", post(scenes::activate_scene))
+        // Activity
+        .route("/activity", get(activity::list_activity))
+        // Audit log
+        .route("/events", get(audit_log::list_audit_events))
+}
+
+/// The OpenAPI document served at `/api/openapi.json`
+fn api_doc() -> utoipa::openapi::OpenApi {
+    // MERGE the module docs here
+    let mut doc = ApiDoc::openapi();
+    doc.merge(UserApi::openapi()); // <--- This pulls in all User paths & components
+    doc.merge(DeviceApi::openapi());
+    doc.merge(GroupApi::openapi());
+    doc.merge(SceneApi::openapi());
+    doc.merge(ActivityApi::openapi());
+    doc.merge(AuditLogApi::openapi());
+    doc.merge(ConfigApi::openapi());
+    doc.merge(BackupApi::openapi());
+    doc
+}
+
 #[tokio::main]
 async fn main() {
+    tracing_subscriber::fmt::init();
     let args = Args::parse();
 
-    let db_connection_string = std::env::var("DATABASE_URL")
-        .unwrap_or_else(|_| "sqlite:wol.db".to_string());
+    if let Some(password) = &args.admin_password
+        && !args.allow_weak_admin_password
+        && let Err(reason) = users::validate_admin_password(password)
+    {
+        eprintln!("Refusing to start: weak --admin-password: {reason}");
+        eprintln!("Choose a stronger password or pass --allow-weak-admin-password.");
+        std::process::exit(1);
+    }
+
+    let static_dir = std::path::absolute(&args.static_dir).unwrap_or_else(|_| args.static_dir.clone());
+    let config = Arc::new(config::Config::load(&static_dir, args.response_compression));
+    users::init_dummy_hash(&config);
 
     let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(&db_connection_string)
+        .max_connections(config.db_max_connections)
+        .connect(&config.database_url)
         .await
         .expect("Failed to connect to database");
 
     // Initialize admin user if requested
     if let Some(password) = args.admin_password {
         println!("Initializing admin user...");
-        let password_hash = users::hash_password(&password).expect("Failed to hash password");
+        let password_hash = users::hash_password(&config, &password).expect("Failed to hash password");
         
         // Upsert admin user
         let result = sqlx::query!(
@@ -105,81 +319,200 @@ async fn main() {
         }
     }
 
-    let pinger_pool = pool.clone();
-    tokio::spawn(async move {
-        loop {
-            // Fetch all devices with IP addresses
-            if let Ok(devices) = sqlx::query!("SELECT id, ip_address FROM devices WHERE ip_address IS NOT NULL")
-                .fetch_all(&pinger_pool)
-                .await 
-            {
-                for device in devices {
-                    if let Some(ip_str) = device.ip_address {
-                        if let Ok(ip) = ip_str.parse::<IpAddr>() {
-                             // Ping with 1 second timeout
-                             let is_online = match ping(ip, &[0; 8]).await {
-                                 Ok((_, duration)) => {
-                                     println!("Ping success for {}: {:?}", ip, duration);
-                                     true
-                                 },
-                                 Err(_) => false,
-                             };
-
-                             let _ = sqlx::query!(
-                                 "UPDATE devices SET is_online = ?, last_seen_at = CASE WHEN ? THEN CURRENT_TIMESTAMP ELSE last_seen_at END WHERE id = ?",
-                                 is_online,
-                                 is_online,
-                                 device.id
-                             )
-                             .execute(&pinger_pool)
-                             .await;
-                        }
-                    }
-                }
-            }
-            
-            tokio::time::sleep(Duration::from_secs(60)).await;
-        }
-    });
+    if args.validate_on_start || args.fix_on_start {
+        selfcheck::validate_devices(&pool, args.fix_on_start).await;
+    }
 
-    let api_routes = Router::new()
-        .route("/login", post(users::login))
-        .route("/refresh", post(users::refresh_token))
-        .route("/logout", post(users::logout_user))
-        .route("/users", get(users::list_users).post(users::create_user))
-        .route("/users/{id}", delete(users::delete_user))
-        .route("/users/{id}/role", put(users::update_role))
-        .route("/users/{id}/status", put(users::update_status))
-        .route("/users/{id}/reset-password", post(users::admin_reset_password))
-        .route("/change-password", post(users::change_password))
-        .route("/me", get(users::get_me))
-        // Devices
-        .route("/devices", get(devices::list_devices).post(devices::create_device))
-        .route("/devices/{id}", delete(devices::delete_device).put(devices::update_device))
-        .route("/devices/{id}/wake", post(devices::wake_device))
-        .route("/devices/{id}/shutdown", post(devices::shutdown_device));
+    let state = AppState {
+        db: pool,
+        events: events::channel(),
+        config: config.clone(),
+        agent_breaker: Arc::default(),
+        maintenance: Arc::default(),
+        wake_sender: Arc::new(wol::UdpWakeSender),
+        prober: Arc::new(pinger::NetworkProber),
+    };
 
-    // MERGE the module docs here
-    let mut doc = ApiDoc::openapi();
-    doc.merge(UserApi::openapi()); // <--- This pulls in all User paths & components
-    doc.merge(DeviceApi::openapi());
+    pinger::spawn(state.clone());
+    scheduler::spawn(state.clone());
+    retention::spawn(state.clone());
 
+    if static_dir.is_dir() {
+        println!("Serving static files from {}", static_dir.display());
+    } else {
+        println!("WARNING: static files directory {} does not exist", static_dir.display());
+    }
+    let app = app(state, &static_dir);
 
-    let static_files = ServeDir::new("./static_files");
+    let listener = tokio::net::TcpListener::bind(&config.listen_addr).await.unwrap();
+    println!("Listening on {}", listener.local_addr().unwrap());
+    axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>()).await.unwrap();
+}
 
+/// The whole application: the API, its docs and the static frontend, with every middleware
+fn app(state: AppState, static_dir: &Path) -> Router {
+    let config = state.config.clone();
+    let api_routes = all_routes(config.request_timeout()).router;
+    // Unknown API paths must not fall through to the static files; wrong
+    // methods on known routes already get a 405 with an `Allow` header
+    let api_routes = api_routes
+        .fallback(|| async { (StatusCode::NOT_FOUND, "Not found") });
 
-    let state = AppState {
-        db: pool
-    };
+    let spec = Bytes::from(api_doc().to_json().expect("Failed to serialize OpenAPI document"));
+    let static_files = ServeDir::new(static_dir);
 
     let app = Router::new()
-        .merge(SwaggerUi::new("/swagger").url("/api/openapi.json", doc.into()))
+        .merge(SwaggerUi::new("/swagger").config(Config::from("/api/openapi.json")))
+        .route("/api/openapi.json", get(move || openapi_json(spec.clone())))
         .nest("/api", api_routes)
         .route("/api/health", get(health_check))
+        .route("/api/health/ready", get(health_ready))
         .fallback_service(static_files)
+        .layer(axum::middleware::from_fn_with_state(state.clone(), client_ip::middleware))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), https::middleware))
+        .layer(axum::middleware::from_fn(request_id::middleware))
+        // Outside `request_id` so the id it appends to 5xx bodies ends up in the JSON
+        .layer(axum::middleware::from_fn(error::negotiate))
         .with_state(state);
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    println!("Listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    // The default predicate already skips small bodies, images and event streams
+    if config.response_compression {
+        app.layer(CompressionLayer::new())
+    } else {
+        app
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{all_routes, api_doc, app};
+    use crate::request_id::REQUEST_ID_HEADER;
+    use crate::test_support::{state, state_with};
+    use axum::{
+        body::{to_bytes, Body},
+        extract::ConnectInfo,
+        http::{header, Request, StatusCode},
+        response::Response,
+        routing::get,
+    };
+    use std::collections::HashSet;
+    use std::net::SocketAddr;
+    use std::path::Path;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    /// Sends `request` through the whole application, as if from a local client
+    async fn serve(request: Request<Body>) -> Response {
+        app(state().await, Path::new("static_files")).oneshot(from_localhost(request)).await.unwrap()
+    }
+
+    /// `request` with the peer address the server would attach, as if from a local client
+    fn from_localhost(mut request: Request<Body>) -> Request<Body> {
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        request
+    }
+
+    /// Every route has OpenAPI docs and every documented path is served,
+    /// so the two can't drift apart unnoticed
+    #[test]
+    fn routes_match_openapi_document() {
+        let routed: HashSet<String> = all_routes(None).paths.iter().map(|path| format!("/api{}", path)).collect();
+        let documented: HashSet<String> = api_doc().paths.paths.into_keys().collect();
+
+        let mut undocumented: Vec<_> = routed.difference(&documented).collect();
+        undocumented.sort();
+        assert!(undocumented.is_empty(), "Routes missing from the OpenAPI document: {:?}", undocumented);
+        let mut unrouted: Vec<_> = documented.difference(&routed).collect();
+        unrouted.sort();
+        assert!(unrouted.is_empty(), "Documented paths without a route: {:?}", unrouted);
+    }
+
+    #[tokio::test]
+    async fn wrong_methods_get_405_and_unknown_api_paths_404() {
+        let response = serve(Request::get("/api/devices/1/wake").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[header::ALLOW], "POST");
+
+        let response = serve(Request::get("/api/no-such-route").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn slow_handlers_time_out_unless_exempt() {
+        let slow = || async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            "done"
+        };
+        let app = all_routes(Some(Duration::from_millis(20)))
+            .route("/slow", get(slow))
+            .long_running_route("/slow-but-exempt", get(slow))
+            .router
+            .with_state(state().await);
+
+        let response = app.clone().oneshot(Request::get("/slow").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let response = app.oneshot(Request::get("/slow-but-exempt").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn api_errors_are_json_for_json_clients() {
+        let request = Request::get("/api/no-such-route").header(header::ACCEPT, "application/json").body(Body::empty()).unwrap();
+        let response = serve(request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, r#"{"error":"Not found"}"#);
+    }
+
+    #[tokio::test]
+    async fn every_response_carries_a_request_id() {
+        let request = Request::get("/api/health").header(REQUEST_ID_HEADER, "from-the-proxy").body(Body::empty()).unwrap();
+        let response = serve(request).await;
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "from-the-proxy");
+
+        let response = serve(Request::get("/api/health").body(Body::empty()).unwrap()).await;
+        let generated = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert!(!generated.is_empty());
+        let response = serve(Request::get("/api/health").body(Body::empty()).unwrap()).await;
+        assert_ne!(response.headers()[REQUEST_ID_HEADER], generated);
+    }
+
+    #[tokio::test]
+    async fn serves_files_from_the_configured_static_dir() {
+        let static_dir = std::env::temp_dir().join(format!("wol-static-{}", std::process::id()));
+        std::fs::create_dir_all(&static_dir).unwrap();
+        std::fs::write(static_dir.join("hello.txt"), "hello from the frontend").unwrap();
+
+        let request = from_localhost(Request::get("/hello.txt").body(Body::empty()).unwrap());
+        let response = app(state().await, &static_dir).oneshot(request).await.unwrap();
+        std::fs::remove_dir_all(&static_dir).unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "hello from the frontend");
+    }
+
+    #[tokio::test]
+    async fn serves_the_openapi_document_for_tooling() {
+        let response = serve(Request::get("/api/openapi.json").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert!(response.headers().contains_key(header::CACHE_CONTROL));
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let doc: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(doc["paths"]["/api/devices"].is_object());
+    }
+
+    #[tokio::test]
+    async fn compresses_large_responses_when_enabled() {
+        for enabled in [true, false] {
+            let state = state_with(|config| config.response_compression = enabled).await;
+            let request = Request::get("/api/openapi.json").header(header::ACCEPT_ENCODING, "gzip").body(Body::empty()).unwrap();
+            let response = app(state, Path::new("static_files")).oneshot(from_localhost(request)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers().get(header::CONTENT_ENCODING).is_some_and(|e| e == "gzip"), enabled);
+        }
+    }
 }
@@ -0,0 +1,916 @@
+use crate::config;
+use crate::db::AppState;
+use crate::events::{self, DeviceStatusEvent};
+use async_trait::async_trait;
+use rand::Rng;
+use serde::Serialize;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use surge_ping::{Client, Config, ICMP, PingIdentifier, PingSequence};
+use tokio::net::TcpStream;
+use utoipa::ToSchema;
+
+/// Devices are loaded in pages of this size so memory stays bounded
+/// regardless of how many rows the devices table holds.
+const SWEEP_BATCH_SIZE: i64 = 100;
+/// Transitions kept per device in `status_history`; older ones are pruned
+const STATUS_HISTORY_PER_DEVICE: i64 = 1000;
+/// Allowed range for `devices.ping_timeout_ms`
+pub const MIN_PING_TIMEOUT_MS: i64 = 50;
+pub const MAX_PING_TIMEOUT_MS: i64 = 10_000;
+/// Upper bound for `PING_COUNT`, so an offline device can't stall a sweep for long
+pub const MAX_PING_COUNT: u32 = 10;
+
+/// Kind of socket ICMP probes use (`PING_MODE`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PingMode {
+    /// Datagram ICMP socket; works without root where the OS allows it
+    /// (on Linux, `net.ipv4.ping_group_range` must include the process' group)
+    #[default]
+    Unprivileged,
+    /// Raw socket; needs root or `CAP_NET_RAW`
+    Privileged,
+}
+
+impl FromStr for PingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "unprivileged" => Ok(PingMode::Unprivileged),
+            "privileged" => Ok(PingMode::Privileged),
+            other => Err(format!("Unknown ping mode: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for PingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PingMode::Unprivileged => write!(f, "unprivileged"),
+            PingMode::Privileged => write!(f, "privileged"),
+        }
+    }
+}
+
+/// How many of a device's `PING_COUNT` probes must be answered for it to count as
+/// online (`PING_DECISION`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PingDecision {
+    /// More than half of them
+    #[default]
+    Majority,
+    /// At least one, for links that drop the odd packet
+    Any,
+}
+
+impl PingDecision {
+    fn is_online(self, answered: u32, sent: u32) -> bool {
+        match self {
+            PingDecision::Majority => answered * 2 > sent,
+            PingDecision::Any => answered > 0,
+        }
+    }
+}
+
+impl FromStr for PingDecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "majority" => Ok(PingDecision::Majority),
+            "any" => Ok(PingDecision::Any),
+            other => Err(format!("Unknown ping decision: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for PingDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PingDecision::Majority => write!(f, "majority"),
+            PingDecision::Any => write!(f, "any"),
+        }
+    }
+}
+
+/// Set at startup once an ICMP socket of the configured mode could be created.
+/// While unset, ICMP probes fail and the pinger reports such devices as unknown.
+static ICMP_MODE: OnceLock<PingMode> = OnceLock::new();
+
+fn icmp_config(mode: PingMode, ip: IpAddr) -> Config {
+    let sock_type = match mode {
+        PingMode::Unprivileged => socket2::Type::DGRAM,
+        PingMode::Privileged => socket2::Type::RAW,
+    };
+    let kind = if ip.is_ipv4() { ICMP::V4 } else { ICMP::V6 };
+    Config::builder().kind(kind).sock_type_hint(sock_type).build()
+}
+
+/// Checks that the configured `PING_MODE` can open an ICMP socket, enabling ICMP
+/// probes if so. Returns the error to report at startup otherwise.
+pub fn init_icmp(mode: PingMode) -> Result<(), String> {
+    init_icmp_with(mode, |config| Client::new(config).map(drop))
+}
+
+fn init_icmp_with(mode: PingMode, open: impl FnOnce(&Config) -> std::io::Result<()>) -> Result<(), String> {
+    if let Err(e) = open(&icmp_config(mode, IpAddr::from([127, 0, 0, 1]))) {
+        return Err(format!(
+            "Cannot create an ICMP socket in {} mode ({}). ICMP probes are disabled and those devices will show as unknown; \
+             set PING_MODE to the other mode, grant CAP_NET_RAW, or use ARP/TCP liveness probes.",
+            mode, e
+        ));
+    }
+    let _ = ICMP_MODE.set(mode);
+    Ok(())
+}
+
+/// How the pinger decides whether a device is up (`devices.liveness_probe`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LivenessProbe {
+    /// ICMP echo request
+    #[default]
+    Icmp,
+    /// ARP request on the local subnet, for hosts that drop ICMP
+    Arp,
+    /// TCP connect to the given port, i.e. "is the service up"
+    Tcp(u16),
+}
+
+impl FromStr for LivenessProbe {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "icmp" => Ok(LivenessProbe::Icmp),
+            "arp" => Ok(LivenessProbe::Arp),
+            other => match other.strip_prefix("tcp:").map(str::parse::<u16>) {
+                Some(Ok(port)) if port != 0 => Ok(LivenessProbe::Tcp(port)),
+                Some(_) => Err(format!("Invalid TCP port in liveness probe: {}", other)),
+                None => Err(format!("Unknown liveness probe: {}", other)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for LivenessProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LivenessProbe::Icmp => write!(f, "icmp"),
+            LivenessProbe::Arp => write!(f, "arp"),
+            LivenessProbe::Tcp(port) => write!(f, "tcp:{}", port),
+        }
+    }
+}
+
+/// Pause before the next sweep: `base` moved randomly by up to `jitter` either way,
+/// so instances started together drift apart instead of probing in lockstep.
+fn sweep_delay(base: Duration, jitter: Duration) -> Duration {
+    if jitter.is_zero() {
+        return base;
+    }
+    let jitter_ms = jitter.as_millis() as i64;
+    let offset = rand::rng().random_range(-jitter_ms..=jitter_ms);
+    let delay_ms = (base.as_millis() as i64 + offset).max(0);
+    Duration::from_millis(delay_ms as u64)
+}
+
+pub fn spawn(state: AppState) {
+    if let Err(e) = init_icmp(state.config.ping_mode) {
+        eprintln!("ERROR: {}", e);
+    }
+    tokio::spawn(async move {
+        restore_provisional_state(&state).await;
+        // Sweep right away so the dashboard is accurate within seconds of a restart
+        loop {
+            sweep_unless_paused(&state).await;
+            tokio::time::sleep(sweep_delay(state.config.sweep_interval(), state.config.sweep_jitter())).await;
+        }
+    });
+}
+
+/// Runs a sweep unless maintenance mode is on.
+async fn sweep_unless_paused(state: &AppState) {
+    if !state.in_maintenance() {
+        sweep(state).await;
+    }
+}
+
+/// Probe timeout for a device: its `ping_timeout_ms` override, else the global default.
+pub fn probe_timeout(config: &config::Config, ping_timeout_ms: Option<i64>) -> Duration {
+    match ping_timeout_ms {
+        Some(ms) => Duration::from_millis(ms.clamp(MIN_PING_TIMEOUT_MS, MAX_PING_TIMEOUT_MS) as u64),
+        None => config.default_ping_timeout(),
+    }
+}
+
+/// Runs liveness probes. Sweeps and handlers go through the one in `AppState`, so
+/// tests can swap [`NetworkProber`] for a fake that answers as scripted.
+#[async_trait]
+pub trait Prober: Send + Sync {
+    /// See [`is_alive`]
+    async fn is_alive(&self, ip: IpAddr, probe: LivenessProbe, timeout: Duration) -> bool;
+}
+
+/// Probes over the network with [`is_alive`]
+pub struct NetworkProber;
+
+#[async_trait]
+impl Prober for NetworkProber {
+    async fn is_alive(&self, ip: IpAddr, probe: LivenessProbe, timeout: Duration) -> bool {
+        is_alive(ip, probe, timeout).await
+    }
+}
+
+/// Runs the given probe against `ip` and reports whether the device answered within `timeout`.
+pub async fn is_alive(ip: IpAddr, probe: LivenessProbe, timeout: Duration) -> bool {
+    match probe {
+        LivenessProbe::Icmp => icmp_probe(ip, timeout).await,
+        LivenessProbe::Arp => arp_probe(ip, timeout).await,
+        LivenessProbe::Tcp(port) => tcp_probe(SocketAddr::new(ip, port), timeout).await,
+    }
+}
+
+/// Runs a single probe, returning how long the device took to answer, if it did.
+pub async fn probe_once(prober: &dyn Prober, ip: IpAddr, probe: LivenessProbe, timeout: Duration) -> Option<Duration> {
+    let started = Instant::now();
+    prober.is_alive(ip, probe, timeout).await.then(|| started.elapsed())
+}
+
+/// Sends `PING_COUNT` probes one after another and applies `PING_DECISION` to the
+/// answers. Returns the fastest answer if the device counts as online.
+pub async fn probe_status(
+    prober: &dyn Prober,
+    config: &config::Config,
+    ip: IpAddr,
+    probe: LivenessProbe,
+    timeout: Duration,
+) -> Option<Duration> {
+    let mut rtts = Vec::new();
+    for _ in 0..config.ping_count {
+        if let Some(rtt) = probe_once(prober, ip, probe, timeout).await {
+            rtts.push(rtt);
+        }
+    }
+    let best = rtts.iter().min().copied();
+    config.ping_decision.is_online(rtts.len() as u32, config.ping_count).then_some(best).flatten()
+}
+
+/// Whether ICMP probes can run; see [`init_icmp`].
+pub fn icmp_available() -> bool {
+    ICMP_MODE.get().is_some()
+}
+
+/// How a [`probe_until_alive`] run went
+pub struct ProbeReport {
+    /// Probes started, including one cut short by the overall timeout
+    pub attempts: u32,
+    /// Duration of the successful probe; `None` if the device never answered
+    pub rtt: Option<Duration>,
+}
+
+/// Probes `ip` every `interval` until it answers or `timeout` elapses, reporting how
+/// many probes it took and how long the answering one took.
+pub async fn probe_until_alive(
+    prober: &dyn Prober,
+    ip: IpAddr,
+    probe: LivenessProbe,
+    probe_timeout: Duration,
+    timeout: Duration,
+    interval: Duration,
+) -> ProbeReport {
+    let mut attempts = 0;
+    let rtt = tokio::time::timeout(timeout, async {
+        loop {
+            attempts += 1;
+            if let Some(rtt) = probe_once(prober, ip, probe, probe_timeout).await {
+                return rtt;
+            }
+            tokio::time::sleep(interval).await;
+        }
+    })
+    .await
+    .ok();
+    ProbeReport { attempts, rtt }
+}
+
+async fn icmp_probe(ip: IpAddr, timeout: Duration) -> bool {
+    let Some(mode) = ICMP_MODE.get() else {
+        return false;
+    };
+    let Ok(client) = Client::new(&icmp_config(*mode, ip)) else {
+        return false;
+    };
+
+    let mut pinger = client.pinger(ip, PingIdentifier(rand::random())).await;
+    pinger.timeout(timeout);
+    match pinger.ping(PingSequence(0), &[0; 8]).await {
+        Ok((_, duration)) => {
+            println!("Ping success for {}: {:?}", ip, duration);
+            true
+        },
+        Err(_) => false,
+    }
+}
+
+async fn tcp_probe(addr: SocketAddr, timeout: Duration) -> bool {
+    matches!(
+        tokio::time::timeout(timeout, TcpStream::connect(addr)).await,
+        Ok(Ok(_))
+    )
+}
+
+#[cfg(unix)]
+async fn arp_probe(ip: IpAddr, timeout: Duration) -> bool {
+    let IpAddr::V4(target) = ip else {
+        println!("ARP probe is IPv4 only, cannot probe {}", ip);
+        return false;
+    };
+
+    match tokio::task::spawn_blocking(move || crate::arp::probe(target, timeout)).await {
+        Ok(Ok(alive)) => alive,
+        Ok(Err(e)) => {
+            println!("ARP probe for {} failed: {}", ip, e);
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+async fn arp_probe(ip: IpAddr, _timeout: Duration) -> bool {
+    println!("ARP probe is not supported on this platform, cannot probe {}", ip);
+    false
+}
+
+/// The stored `is_online` may be arbitrarily old after a restart. Until the first
+/// sweep finishes, only devices seen within the last two sweep intervals keep
+/// counting as online.
+async fn restore_provisional_state(state: &AppState) {
+    let cutoff = format!("-{} seconds", 2 * state.config.sweep_interval_secs);
+    let result = sqlx::query_scalar!(
+        r#"UPDATE devices SET is_online = 0, went_online_at = NULL
+         WHERE is_online = 1 AND (last_seen_at IS NULL OR last_seen_at < datetime('now', ?))
+         RETURNING id as "id!""#,
+        cutoff
+    )
+    .fetch_all(&state.db)
+    .await;
+
+    match result {
+        Ok(ids) => {
+            for id in ids {
+                record_transition(state, id, false).await;
+            }
+        }
+        Err(e) => println!("Failed to restore provisional device state: {}", e),
+    }
+}
+
+/// Probes every device that has an IP address and stores the result.
+async fn sweep(state: &AppState) {
+    // Keyset pagination over the partial index `idx_devices_pingable`
+    let mut last_id = 0;
+
+    loop {
+        let devices = match sqlx::query!(
+            r#"SELECT id as "id!", ip_address as "ip_address!", liveness_probe, ping_timeout_ms FROM devices
+               WHERE ip_address IS NOT NULL AND enabled = 1 AND monitoring_enabled = 1 AND id > ?
+               ORDER BY id
+               LIMIT ?"#,
+            last_id,
+            SWEEP_BATCH_SIZE
+        )
+        .fetch_all(&state.db)
+        .await
+        {
+            Ok(d) => d,
+            Err(e) => {
+                println!("Failed to load devices to sweep: {}", e);
+                return;
+            }
+        };
+
+        let Some(last) = devices.last() else {
+            return;
+        };
+        last_id = last.id;
+        let batch_len = devices.len() as i64;
+
+        for device in devices {
+            let Ok(ip) = device.ip_address.parse::<IpAddr>() else {
+                println!("WARNING: Device {} has an invalid IP address '{}', status set to unknown", device.id, device.ip_address);
+                mark_unknown(state, device.id).await;
+                continue;
+            };
+            let probe = device.liveness_probe.parse().unwrap_or_default();
+            // Not being able to ping says nothing about the device
+            if probe == LivenessProbe::Icmp && !icmp_available() {
+                mark_unknown(state, device.id).await;
+                continue;
+            }
+            let rtt = probe_status(
+                state.prober.as_ref(),
+                &state.config,
+                ip,
+                probe,
+                probe_timeout(&state.config, device.ping_timeout_ms),
+            )
+            .await;
+            record_sweep_result(state, device.id, rtt).await;
+        }
+
+        if batch_len < SWEEP_BATCH_SIZE {
+            return;
+        }
+    }
+}
+
+/// Like [`record_liveness`], but an online device is only marked offline after
+/// `OFFLINE_AFTER_MISSES` sweeps in a row without an answer.
+async fn record_sweep_result(state: &AppState, device_id: i64, rtt: Option<Duration>) {
+    if rtt.is_none() {
+        let missed = sqlx::query!(
+            r#"UPDATE devices SET consecutive_misses = consecutive_misses + 1 WHERE id = ?
+               RETURNING consecutive_misses, is_online as "is_online?: bool""#,
+            device_id
+        )
+        .fetch_optional(&state.db)
+        .await;
+
+        if let Ok(Some(device)) = missed
+            && device.is_online == Some(true)
+            && device.consecutive_misses < i64::from(state.config.offline_after_misses)
+        {
+            return;
+        }
+    }
+    record_liveness(state, device_id, rtt).await;
+}
+
+/// Stores a probe result, whichever probe produced it: the answer's round trip, or
+/// `None` if the device didn't answer.
+/// Only an actual transition touches `went_online_at` and publishes a status event.
+pub async fn record_liveness(state: &AppState, device_id: i64, rtt: Option<Duration>) {
+    let is_online = rtt.is_some();
+    // Only a row that actually changed comes back
+    let changed = sqlx::query_scalar!(
+        "UPDATE devices
+         SET is_online = ?, went_online_at = CASE WHEN ? THEN CURRENT_TIMESTAMP ELSE NULL END
+         WHERE id = ? AND is_online IS NOT ?
+         RETURNING owner_id",
+        is_online,
+        is_online,
+        device_id,
+        is_online
+    )
+    .fetch_optional(&state.db)
+    .await
+    .ok()
+    .flatten();
+
+    if let Some(rtt) = rtt {
+        let rtt_ms = rtt.as_millis() as i64;
+        let _ = sqlx::query!(
+            "UPDATE devices SET last_seen_at = CURRENT_TIMESTAMP, last_rtt_ms = ?, consecutive_misses = 0 WHERE id = ?",
+            rtt_ms,
+            device_id
+        )
+        .execute(&state.db)
+        .await;
+    }
+
+    if let Some(owner_id) = changed {
+        record_transition(state, device_id, is_online).await;
+        events::publish(&state.events, DeviceStatusEvent {
+            device_id,
+            is_online,
+            changed_at: chrono::Utc::now().naive_utc(),
+            owner_id,
+        });
+    }
+}
+
+/// Clears the stored status of a device that can't be probed, so it shows as unknown
+/// instead of keeping whatever was last observed.
+async fn mark_unknown(state: &AppState, device_id: i64) {
+    let _ = sqlx::query!(
+        "UPDATE devices SET is_online = NULL, went_online_at = NULL WHERE id = ? AND is_online IS NOT NULL",
+        device_id
+    )
+    .execute(&state.db)
+    .await;
+}
+
+/// Appends a transition to `status_history`, keeping the newest
+/// `STATUS_HISTORY_PER_DEVICE` entries of the device.
+async fn record_transition(state: &AppState, device_id: i64, is_online: bool) {
+    let status = if is_online { "online" } else { "offline" };
+    let inserted = sqlx::query!(
+        "INSERT INTO status_history (device_id, state) VALUES (?, ?)",
+        device_id,
+        status
+    )
+    .execute(&state.db)
+    .await;
+
+    if let Err(e) = inserted {
+        println!("Failed to record status transition of device {}: {}", device_id, e);
+        return;
+    }
+
+    let _ = sqlx::query!(
+        "DELETE FROM status_history WHERE device_id = ? AND id NOT IN (
+            SELECT id FROM status_history WHERE device_id = ?
+            ORDER BY id DESC
+            LIMIT ?
+        )",
+        device_id,
+        device_id,
+        STATUS_HISTORY_PER_DEVICE
+    )
+    .execute(&state.db)
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        icmp_available, init_icmp_with, is_alive, probe_status, record_liveness, restore_provisional_state, sweep, sweep_delay,
+        sweep_unless_paused, LivenessProbe, PingDecision, PingMode, SWEEP_BATCH_SIZE,
+    };
+    use crate::db::AppState;
+    use crate::test_support::{call, device, state, state_with, user, ScriptedProber};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+    use sqlx::Row;
+    use std::net::IpAddr;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn sweep_query_uses_pingable_index() {
+        let state = state().await;
+        // Same statement as in `sweep`
+        let plan = sqlx::query(
+            "EXPLAIN QUERY PLAN
+             SELECT id, ip_address, liveness_probe, ping_timeout_ms FROM devices
+             WHERE ip_address IS NOT NULL AND enabled = 1 AND monitoring_enabled = 1 AND id > ?
+             ORDER BY id
+             LIMIT ?",
+        )
+        .bind(0)
+        .bind(SWEEP_BATCH_SIZE)
+        .fetch_all(&state.db)
+        .await
+        .unwrap();
+
+        let details: Vec<String> = plan.iter().map(|row| row.get("detail")).collect();
+        assert!(details.iter().any(|d| d.contains("idx_devices_pingable")), "{details:?}");
+    }
+
+    #[tokio::test]
+    async fn restore_keeps_only_recently_seen_devices_online() {
+        let state = state().await;
+        let stale = device(&state, "stale", None).await;
+        let recent = device(&state, "recent", None).await;
+        let never_seen = device(&state, "never seen", None).await;
+        sqlx::query!(
+            "UPDATE devices SET is_online = 1, went_online_at = CURRENT_TIMESTAMP,
+                last_seen_at = CASE id WHEN ? THEN datetime('now', '-1 day') WHEN ? THEN CURRENT_TIMESTAMP END",
+            stale,
+            recent
+        )
+        .execute(&state.db)
+        .await
+        .unwrap();
+
+        restore_provisional_state(&state).await;
+
+        let online = sqlx::query_scalar!(r#"SELECT id as "id!" FROM devices WHERE is_online = 1 AND went_online_at IS NOT NULL"#)
+            .fetch_all(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(online, [recent]);
+        let went_offline = sqlx::query_scalar!("SELECT device_id FROM status_history WHERE state = 'offline' ORDER BY device_id")
+            .fetch_all(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(went_offline, [stale, never_seen]);
+    }
+
+    #[test]
+    fn parses_liveness_probes() {
+        assert_eq!("icmp".parse(), Ok(LivenessProbe::Icmp));
+        assert_eq!(" ARP ".parse(), Ok(LivenessProbe::Arp));
+        assert_eq!("tcp:22".parse(), Ok(LivenessProbe::Tcp(22)));
+        assert!("tcp:0".parse::<LivenessProbe>().is_err());
+        assert!("tcp:http".parse::<LivenessProbe>().is_err());
+        assert!("udp:53".parse::<LivenessProbe>().is_err());
+        for probe in [LivenessProbe::Icmp, LivenessProbe::Arp, LivenessProbe::Tcp(445)] {
+            assert_eq!(probe.to_string().parse(), Ok(probe));
+        }
+    }
+
+    #[tokio::test]
+    async fn sweep_probes_each_device_its_own_way() {
+        let prober = ScriptedProber::new([true]);
+        let state = AppState { prober: prober.clone(), ..state().await };
+        for (name, ip, probe) in [("nas", "192.168.1.2", "arp"), ("server", "192.168.1.3", "tcp:22")] {
+            let id = device(&state, name, None).await;
+            sqlx::query!("UPDATE devices SET ip_address = ?, liveness_probe = ? WHERE id = ?", ip, probe, id)
+                .execute(&state.db)
+                .await
+                .unwrap();
+        }
+
+        sweep(&state).await;
+
+        let probes: Vec<_> = prober.probes.lock().unwrap().iter().map(|&(ip, probe, _)| (ip, probe)).collect();
+        let nas: IpAddr = "192.168.1.2".parse().unwrap();
+        let server: IpAddr = "192.168.1.3".parse().unwrap();
+        assert!(probes.contains(&(nas, LivenessProbe::Arp)), "{probes:?}");
+        assert!(probes.contains(&(server, LivenessProbe::Tcp(22))), "{probes:?}");
+        let online = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!: i64" FROM devices WHERE is_online = 1"#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(online, 2);
+    }
+
+    #[tokio::test]
+    async fn invalid_stored_ip_is_flagged_unknown() {
+        let prober = ScriptedProber::new([true]);
+        let state = AppState { prober: prober.clone(), ..state().await };
+        let id = device(&state, "broken", None).await;
+        sqlx::query!("UPDATE devices SET ip_address = '192.168.1.300', is_online = 1 WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        sweep(&state).await;
+
+        assert!(prober.probes.lock().unwrap().is_empty());
+        let is_online = sqlx::query_scalar!("SELECT is_online FROM devices WHERE id = ?", id)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(is_online, None);
+    }
+
+    #[tokio::test]
+    async fn icmp_socket_failure_is_reported_and_leaves_devices_unknown() {
+        let denied = init_icmp_with(PingMode::Privileged, |_| Err(std::io::ErrorKind::PermissionDenied.into()));
+        let error = denied.unwrap_err();
+        assert!(error.starts_with("Cannot create an ICMP socket in privileged mode (permission denied)"), "{error}");
+        assert!(!icmp_available());
+
+        // Not being able to ping doesn't make a device offline
+        let prober = ScriptedProber::new([false]);
+        let state = AppState { prober: prober.clone(), ..state().await };
+        let id = device(&state, "desktop", None).await;
+        sqlx::query!("UPDATE devices SET ip_address = '192.168.1.2', is_online = 1 WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+        sweep(&state).await;
+
+        assert!(prober.probes.lock().unwrap().is_empty());
+        let is_online = sqlx::query_scalar!("SELECT is_online FROM devices WHERE id = ?", id)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(is_online, None);
+    }
+
+    #[tokio::test]
+    async fn one_answer_in_three_is_online_under_the_any_rule() {
+        let prober = ScriptedProber::new([false, true, false]);
+        let state = AppState {
+            prober: prober.clone(),
+            ..state_with(|config| {
+                config.ping_count = 3;
+                config.ping_decision = PingDecision::Any;
+            })
+            .await
+        };
+        let id = device(&state, "flaky", None).await;
+        sqlx::query!("UPDATE devices SET ip_address = '192.168.1.2', liveness_probe = 'arp' WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        sweep(&state).await;
+
+        assert_eq!(prober.probes.lock().unwrap().len(), 3);
+        let device = sqlx::query!("SELECT is_online, last_rtt_ms FROM devices WHERE id = ?", id)
+            .fetch_one(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(device.is_online, Some(true));
+        assert!(device.last_rtt_ms.is_some());
+
+        // The same answers aren't a majority
+        let config = crate::config::Config { ping_decision: PingDecision::Majority, ..state.config.as_ref().clone() };
+        let prober = ScriptedProber::new([false, true, false]);
+        let ip = "192.168.1.2".parse().unwrap();
+        assert_eq!(probe_status(prober.as_ref(), &config, ip, LivenessProbe::Arp, Duration::from_secs(1)).await, None);
+    }
+
+    #[tokio::test]
+    async fn online_devices_go_offline_only_after_consecutive_misses() {
+        // An answer in between starts the count over
+        let prober = ScriptedProber::new([false, false, true, false, false, false]);
+        let state = AppState { prober: prober.clone(), ..state_with(|config| config.offline_after_misses = 3).await };
+        let id = device(&state, "flaky", None).await;
+        sqlx::query!("UPDATE devices SET ip_address = '192.168.1.2', liveness_probe = 'arp', is_online = 1 WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let mut seen = Vec::new();
+        for _ in 0..6 {
+            sweep(&state).await;
+            let is_online = sqlx::query_scalar!(r#"SELECT is_online as "is_online!: bool" FROM devices WHERE id = ?"#, id)
+                .fetch_one(&state.db)
+                .await
+                .unwrap();
+            seen.push(is_online);
+        }
+        assert_eq!(seen, [true, true, true, true, true, false]);
+    }
+
+    #[tokio::test]
+    async fn new_devices_are_not_pinged_when_monitoring_is_off_by_default() {
+        let prober = ScriptedProber::new([true]);
+        let state = AppState { prober: prober.clone(), ..state_with(|config| config.default_monitoring_enabled = false).await };
+        let (_, admin) = user(&state, "admin", "admin").await;
+        for (ip, monitoring_enabled) in [("192.168.1.2", None), ("192.168.1.3", Some(true))] {
+            let payload = json!({
+                "name": ip,
+                "mac_address": "00:11:22:33:44:55",
+                "ip_address": ip,
+                "liveness_probe": "arp",
+                "monitoring_enabled": monitoring_enabled,
+            });
+            let (status, _) = call(&state, Method::POST, "/api/devices", Some(&admin), Some(payload)).await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+
+        sweep(&state).await;
+
+        let probed: Vec<IpAddr> = prober.probes.lock().unwrap().iter().map(|&(ip, _, _)| ip).collect();
+        assert_eq!(probed, ["192.168.1.3".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn maintenance_mode_pauses_sweeps() {
+        let prober = ScriptedProber::new([true]);
+        let state = AppState { prober: prober.clone(), ..state().await };
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "desktop", None).await;
+        sqlx::query!("UPDATE devices SET ip_address = '192.168.1.2', liveness_probe = 'arp' WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let (status, _) = call(&state, Method::PUT, "/api/maintenance", Some(&admin), Some(json!({ "enabled": true }))).await;
+        assert_eq!(status, StatusCode::OK);
+        sweep_unless_paused(&state).await;
+        assert!(prober.probes.lock().unwrap().is_empty());
+
+        call(&state, Method::PUT, "/api/maintenance", Some(&admin), Some(json!({ "enabled": false }))).await;
+        sweep_unless_paused(&state).await;
+        assert_eq!(prober.probes.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn tcp_probe_follows_the_port() {
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        let timeout = Duration::from_secs(1);
+        let listener = TcpListener::bind((localhost, 0)).await.unwrap();
+        let open = listener.local_addr().unwrap().port();
+        assert!(is_alive(localhost, LivenessProbe::Tcp(open), timeout).await);
+
+        drop(listener);
+        assert!(!is_alive(localhost, LivenessProbe::Tcp(open), timeout).await);
+    }
+
+    #[tokio::test]
+    async fn went_online_at_changes_only_on_transitions() {
+        let state = state().await;
+        let id = device(&state, "desktop", None).await;
+        let went_online_at = || {
+            let state = state.clone();
+            async move {
+                sqlx::query_scalar!("SELECT went_online_at FROM devices WHERE id = ?", id)
+                    .fetch_one(&state.db)
+                    .await
+                    .unwrap()
+            }
+        };
+        let up = Some(Duration::from_millis(1));
+
+        record_liveness(&state, id, None).await;
+        assert_eq!(went_online_at().await, None);
+
+        record_liveness(&state, id, up).await;
+        assert!(went_online_at().await.is_some());
+        // Backdate it, so a spurious rewrite would show
+        sqlx::query!("UPDATE devices SET went_online_at = '2020-01-01 00:00:00' WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+        record_liveness(&state, id, up).await;
+        assert_eq!(went_online_at().await.unwrap().to_string(), "2020-01-01 00:00:00");
+
+        record_liveness(&state, id, None).await;
+        assert_eq!(went_online_at().await, None);
+    }
+
+    #[tokio::test]
+    async fn transitions_are_recorded_in_status_history() {
+        let state = state().await;
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let id = device(&state, "server", None).await;
+        sqlx::query!("UPDATE devices SET is_online = NULL WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+        let up = Some(Duration::from_millis(1));
+
+        // Down, up, down; repeated results aren't transitions
+        for rtt in [None, None, up, up, None] {
+            record_liveness(&state, id, rtt).await;
+        }
+
+        let (status, body) = call(&state, Method::GET, &format!("/api/devices/{id}/history"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let history: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let states: Vec<&str> = history.as_array().unwrap().iter().map(|t| t["state"].as_str().unwrap()).collect();
+        assert_eq!(states, ["offline", "online", "offline"]);
+    }
+
+    #[test]
+    fn sweep_delay_stays_within_jitter() {
+        let base = Duration::from_secs(60);
+        let jitter = Duration::from_secs(10);
+        let delays: Vec<Duration> = (0..1000).map(|_| sweep_delay(base, jitter)).collect();
+        assert!(delays.iter().all(|d| (base - jitter..=base + jitter).contains(d)));
+        // Actually random, not a fixed offset
+        assert!(delays.iter().any(|d| *d != delays[0]));
+
+        assert_eq!(sweep_delay(base, Duration::ZERO), base);
+    }
+
+    #[tokio::test]
+    async fn sweep_uses_each_devices_timeout_override() {
+        let prober = ScriptedProber::new([true]);
+        let state = AppState { prober: prober.clone(), ..state().await };
+        for (name, ip, timeout) in [("vpn-box", "10.8.0.2", Some(2500)), ("lan-box", "192.168.1.2", None)] {
+            let id = device(&state, name, None).await;
+            sqlx::query!("UPDATE devices SET ip_address = ?, liveness_probe = 'arp', ping_timeout_ms = ? WHERE id = ?", ip, timeout, id)
+                .execute(&state.db)
+                .await
+                .unwrap();
+        }
+
+        sweep(&state).await;
+
+        let timeouts: Vec<(String, Duration)> =
+            prober.probes.lock().unwrap().iter().map(|&(ip, _, timeout)| (ip.to_string(), timeout)).collect();
+        assert!(timeouts.contains(&("10.8.0.2".to_string(), Duration::from_millis(2500))), "{timeouts:?}");
+        assert!(timeouts.contains(&("192.168.1.2".to_string(), state.config.default_ping_timeout())), "{timeouts:?}");
+        assert_ne!(state.config.default_ping_timeout(), Duration::from_millis(2500));
+    }
+
+    #[tokio::test]
+    async fn new_devices_are_unknown_until_swept() {
+        let state = AppState { prober: ScriptedProber::new([false]), ..state().await };
+        let (_, admin) = user(&state, "admin", "admin").await;
+        let new_device = json!({
+            "name": "desktop",
+            "mac_address": "AA:BB:CC:DD:EE:FF",
+            "ip_address": "192.168.1.20",
+            "liveness_probe": "arp"
+        });
+        let (status, body) = call(&state, Method::POST, "/api/devices", Some(&admin), Some(new_device)).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let status_of_device = || {
+            let (state, admin) = (state.clone(), admin.clone());
+            async move {
+                let (_, body) = call(&state, Method::GET, "/api/devices", Some(&admin), None).await;
+                serde_json::from_str::<serde_json::Value>(&body).unwrap()[0]["status"].clone()
+            }
+        };
+
+        assert_eq!(status_of_device().await, "unknown");
+        sweep(&state).await;
+        assert_eq!(status_of_device().await, "offline");
+    }
+}
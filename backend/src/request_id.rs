@@ -0,0 +1,112 @@
+use crate::error::read_error_body;
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use rand::distr::{Alphanumeric, SampleString};
+use tracing::Instrument;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Client-supplied ids longer than this are replaced with a generated one
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Id of the request currently being handled, if called from within one.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Reuses the caller's `X-Request-Id` or generates one, makes it available via
+/// [`current`] and the tracing span, echoes it in the response header and
+/// appends it to plain-text 5xx bodies so users can quote it in bug reports.
+pub async fn middleware(req: Request, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_owned)
+        .unwrap_or_else(|| Alphanumeric.sample_string(&mut rand::rng(), 16));
+
+    // `client_ip` is filled in by `client_ip::middleware`
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+        uri = %req.uri(),
+        client_ip = tracing::field::Empty
+    );
+    let mut res = REQUEST_ID
+        .scope(id.clone(), next.run(req))
+        .instrument(span.clone())
+        .await;
+    span.in_scope(|| tracing::info!(status = res.status().as_u16(), "request completed"));
+
+    let is_plain_text = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("text/plain"));
+    if res.status().is_server_error() && is_plain_text {
+        let (mut parts, body) = res.into_parts();
+        res = match read_error_body(body).await {
+            Ok(message) => {
+                parts.headers.remove(header::CONTENT_LENGTH);
+                let message = format!("{} (request id: {})", String::from_utf8_lossy(&message), id);
+                Response::from_parts(parts, Body::from(message))
+            }
+            Err(body) => Response::from_parts(parts, body),
+        };
+    }
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{middleware, REQUEST_ID_HEADER};
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    /// Body of `GET /` answered with a 500 carrying `message`, sent with request id `abc`
+    async fn error_body(message: String) -> String {
+        let app = Router::new()
+            .route("/", get(move || async move { (StatusCode::INTERNAL_SERVER_ERROR, message) }))
+            .layer(axum::middleware::from_fn(middleware));
+        let request = Request::get("/").header(REQUEST_ID_HEADER, "abc").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "abc");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn appends_request_id_to_server_errors() {
+        assert_eq!(error_body("Boom".to_string()).await, "Boom (request id: abc)");
+    }
+
+    #[tokio::test]
+    async fn leaves_oversized_errors_untouched() {
+        let message = "x".repeat(64 * 1024);
+        assert_eq!(error_body(message.clone()).await, message);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::db::AppState;
+use std::time::Duration;
+
+/// Pause between cleanups; the first one runs right at startup
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Rows deleted per statement, so a large first cleanup doesn't hold the write lock for long
+const DELETE_BATCH_SIZE: i64 = 5000;
+
+#[derive(Clone, Copy)]
+enum Table {
+    AuditLog,
+    StatusHistory,
+}
+
+impl Table {
+    fn name(self) -> &'static str {
+        match self {
+            Table::AuditLog => "audit_log",
+            Table::StatusHistory => "status_history",
+        }
+    }
+
+    /// Deletes up to `DELETE_BATCH_SIZE` rows older than `cutoff` (an SQLite date modifier)
+    async fn delete_batch(self, state: &AppState, cutoff: &str) -> Result<u64, sqlx::Error> {
+        let result = match self {
+            Table::AuditLog => sqlx::query!(
+                "DELETE FROM audit_log WHERE id IN (
+                    SELECT id FROM audit_log WHERE created_at < datetime('now', ?) LIMIT ?
+                )",
+                cutoff,
+                DELETE_BATCH_SIZE
+            )
+            .execute(&state.db)
+            .await?,
+            Table::StatusHistory => sqlx::query!(
+                "DELETE FROM status_history WHERE id IN (
+                    SELECT id FROM status_history WHERE changed_at < datetime('now', ?) LIMIT ?
+                )",
+                cutoff,
+                DELETE_BATCH_SIZE
+            )
+            .execute(&state.db)
+            .await?,
+        };
+        Ok(result.rows_affected())
+    }
+}
+
+/// Deletes every row of `table` older than `days`, returning how many were deleted.
+async fn prune(state: &AppState, table: Table, days: u32) -> Result<u64, sqlx::Error> {
+    let cutoff = format!("-{} days", days);
+    let mut pruned = 0;
+    loop {
+        let deleted = table.delete_batch(state, &cutoff).await?;
+        pruned += deleted;
+        if deleted < DELETE_BATCH_SIZE as u64 {
+            return Ok(pruned);
+        }
+    }
+}
+
+/// Periodically deletes audit entries and status transitions older than
+/// `AUDIT_RETENTION_DAYS` / `STATUS_HISTORY_RETENTION_DAYS`; 0 keeps a table's rows forever.
+/// These are the only tables that grow on their own: wakes are audit entries, and
+/// individual ping results aren't kept beyond the device row's latest ones.
+pub fn spawn(state: AppState) {
+    let tables = [
+        (Table::AuditLog, state.config.audit_retention_days),
+        (Table::StatusHistory, state.config.status_history_retention_days),
+    ];
+    if tables.iter().all(|&(_, days)| days == 0) {
+        return;
+    }
+
+    tokio::spawn(async move {
+        loop {
+            for (table, days) in tables {
+                if days == 0 {
+                    continue;
+                }
+                match prune(&state, table, days).await {
+                    Ok(0) => {}
+                    Ok(pruned) => println!("Pruned {} {} rows older than {} days", pruned, table.name(), days),
+                    Err(e) => println!("Failed to prune {}: {}", table.name(), e),
+                }
+            }
+            tokio::time::sleep(CLEANUP_INTERVAL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prune, Table};
+    use crate::test_support::{device, state};
+
+    #[tokio::test]
+    async fn prunes_only_old_audit_entries() {
+        let state = state().await;
+        sqlx::query!(
+            "INSERT INTO audit_log (action, details, created_at) VALUES
+                ('login', 'old', datetime('now', '-40 days')),
+                ('login', 'recent', datetime('now', '-20 days')),
+                ('login', 'new', CURRENT_TIMESTAMP)"
+        )
+        .execute(&state.db)
+        .await
+        .unwrap();
+
+        assert_eq!(prune(&state, Table::AuditLog, 30).await.unwrap(), 1);
+        let left = sqlx::query_scalar!("SELECT details FROM audit_log ORDER BY id")
+            .fetch_all(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(left, [Some("recent".to_string()), Some("new".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn prunes_only_old_status_transitions() {
+        let state = state().await;
+        let id = device(&state, "desktop", None).await;
+        sqlx::query!(
+            "INSERT INTO status_history (device_id, state, changed_at) VALUES
+                (?, 'online', datetime('now', '-10 days')),
+                (?, 'offline', datetime('now', '-8 days')),
+                (?, 'online', datetime('now', '-1 day'))",
+            id,
+            id,
+            id
+        )
+        .execute(&state.db)
+        .await
+        .unwrap();
+
+        assert_eq!(prune(&state, Table::StatusHistory, 7).await.unwrap(), 2);
+        let left = sqlx::query_scalar!("SELECT state FROM status_history")
+            .fetch_all(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(left, ["online"]);
+    }
+}
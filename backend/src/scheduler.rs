@@ -0,0 +1,306 @@
+use crate::api::devices::send_device_wake;
+use crate::audit;
+use crate::db::AppState;
+use std::fmt;
+use serde::Serialize;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::Instant;
+use utoipa::ToSchema;
+
+/// How often due schedules are looked up
+const TICK_INTERVAL: Duration = Duration::from_secs(15);
+/// A schedule found later than this after its `fire_at` counts as missed,
+/// e.g. because the server was down at the time
+const MISSED_FIRE_GRACE_SECS: i64 = 60;
+/// Pause before the first retry; doubled for every further one
+const RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// What to do with a one-shot schedule whose `fire_at` passed while nobody was watching
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissedFirePolicy {
+    /// Wake the device anyway, just late
+    #[default]
+    FireLate,
+    /// Drop the schedule without waking
+    Skip,
+}
+
+impl FromStr for MissedFirePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "fire-late" => Ok(MissedFirePolicy::FireLate),
+            "skip" => Ok(MissedFirePolicy::Skip),
+            other => Err(format!("Unknown missed fire policy: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for MissedFirePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissedFirePolicy::FireLate => write!(f, "fire-late"),
+            MissedFirePolicy::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+/// A scheduled wake that has been claimed from the table. Failed ones stay in
+/// the scheduler's in-memory retry queue until they succeed or run out of attempts.
+struct PendingWake {
+    schedule_id: i64,
+    device_id: i64,
+    created_by: Option<i64>,
+    mac_address: String,
+    broadcast_addr: Option<String>,
+    wol_ports: Option<String>,
+    /// Attempts made so far
+    attempts: u32,
+    next_attempt_at: Instant,
+}
+
+impl PendingWake {
+    /// Sends the packet once and audits the attempt. Returns whether it went out.
+    async fn attempt(&mut self, state: &AppState) -> bool {
+        self.attempts += 1;
+        let result = send_device_wake(
+            state,
+            self.device_id,
+            &self.mac_address,
+            self.broadcast_addr.as_deref(),
+            self.wol_ports.as_deref(),
+        )
+        .await;
+        let (action, details) = match &result {
+            Ok(()) => (audit::ACTION_SCHEDULED_WAKE, format!("schedule {}, attempt {}: sent", self.schedule_id, self.attempts)),
+            Err(e) => (audit::ACTION_SCHEDULED_WAKE_FAILED, format!("schedule {}, attempt {}: {}", self.schedule_id, self.attempts, e)),
+        };
+        audit::record(&state.db, self.created_by, action, None, Some(self.device_id), Some(&details)).await;
+        result.is_ok()
+    }
+
+    /// After a failed attempt: queue it again with exponential backoff, unless it is out of retries.
+    fn requeue(mut self, max_retries: u32, retries: &mut Vec<PendingWake>) {
+        if self.attempts > max_retries {
+            println!("Giving up on scheduled wake {} for device {} after {} attempts", self.schedule_id, self.device_id, self.attempts);
+            return;
+        }
+        self.next_attempt_at = Instant::now() + RETRY_BACKOFF * 2u32.saturating_pow(self.attempts - 1);
+        retries.push(self);
+    }
+}
+
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        let mut retries = Vec::new();
+        loop {
+            fire_due(&state, &mut retries).await;
+            retry_failed(&state, &mut retries).await;
+            tokio::time::sleep(TICK_INTERVAL).await;
+        }
+    });
+}
+
+/// Re-attempts queued wakes whose backoff has elapsed.
+async fn retry_failed(state: &AppState, retries: &mut Vec<PendingWake>) {
+    let now = Instant::now();
+    for mut wake in std::mem::take(retries) {
+        if state.in_maintenance() {
+            println!("Dropping retry of scheduled wake {} for device {}: maintenance mode", wake.schedule_id, wake.device_id);
+        } else if wake.next_attempt_at > now {
+            retries.push(wake);
+        } else if !wake.attempt(state).await {
+            wake.requeue(state.config.schedule_wake_retries, retries);
+        }
+    }
+}
+
+/// Fires every schedule whose `fire_at` has passed and removes it.
+/// Failed sends are handed to the retry queue.
+async fn fire_due(state: &AppState, retries: &mut Vec<PendingWake>) {
+    let grace = format!("-{} seconds", MISSED_FIRE_GRACE_SECS);
+    let due = match sqlx::query!(
+        r#"SELECT s.id as "id!", s.device_id, s.created_by, d.mac_address, d.broadcast_addr, d.enabled, d.wol_ports,
+                  s.fire_at < datetime('now', ?) as "missed!: bool"
+           FROM schedules s
+           JOIN devices d ON d.id = s.device_id
+           WHERE s.fire_at <= CURRENT_TIMESTAMP
+           ORDER BY s.fire_at"#,
+        grace
+    )
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            println!("Failed to load due schedules: {}", e);
+            return;
+        }
+    };
+
+    for schedule in due {
+        // Claim the row first so a slow send can never fire it twice
+        match sqlx::query!("DELETE FROM schedules WHERE id = ?", schedule.id)
+            .execute(&state.db)
+            .await
+        {
+            Ok(r) if r.rows_affected() == 1 => {}
+            _ => continue,
+        }
+
+        if schedule.missed && state.config.schedule_missed_policy == MissedFirePolicy::Skip {
+            println!("Skipping missed schedule {} for device {}", schedule.id, schedule.device_id);
+            continue;
+        }
+        if !schedule.enabled {
+            let details = format!("schedule {}: skipped, device disabled", schedule.id);
+            audit::record(&state.db, schedule.created_by, audit::ACTION_SCHEDULED_WAKE_SKIPPED, None, Some(schedule.device_id), Some(&details)).await;
+            continue;
+        }
+        if state.in_maintenance() {
+            let details = format!("schedule {}: skipped, maintenance mode", schedule.id);
+            audit::record(&state.db, schedule.created_by, audit::ACTION_SCHEDULED_WAKE_SKIPPED, None, Some(schedule.device_id), Some(&details)).await;
+            continue;
+        }
+
+        let mut wake = PendingWake {
+            schedule_id: schedule.id,
+            device_id: schedule.device_id,
+            created_by: schedule.created_by,
+            mac_address: schedule.mac_address,
+            broadcast_addr: schedule.broadcast_addr,
+            wol_ports: schedule.wol_ports,
+            attempts: 0,
+            next_attempt_at: Instant::now(),
+        };
+        if !wake.attempt(state).await {
+            wake.requeue(state.config.schedule_wake_retries, retries);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fire_due, retry_failed, MissedFirePolicy, MISSED_FIRE_GRACE_SECS};
+    use crate::audit;
+    use crate::db::AppState;
+    use crate::test_support::{device, recording_state, state, state_with, user, RecordingSender};
+    use std::sync::Arc;
+    use tokio::time::Instant;
+
+    #[tokio::test]
+    async fn skipped_schedules_are_not_audited_as_wakes() {
+        let state = state().await;
+        let (user_id, _) = user(&state, "alice", "user").await;
+        let id = device(&state, "desktop", Some(user_id)).await;
+        sqlx::query!("UPDATE devices SET enabled = 0 WHERE id = ?", id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+        sqlx::query!("INSERT INTO schedules (device_id, fire_at, created_by) VALUES (?, CURRENT_TIMESTAMP, ?)", id, user_id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let mut retries = Vec::new();
+        fire_due(&state, &mut retries).await;
+
+        let actions = sqlx::query_scalar!("SELECT action FROM audit_log WHERE device_id = ?", id)
+            .fetch_all(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(actions, [audit::ACTION_SCHEDULED_WAKE_SKIPPED]);
+        assert!(retries.is_empty());
+    }
+
+    /// Adds a one-shot schedule at `fire_at`, an SQLite datetime expression
+    async fn schedule(state: &AppState, device_id: i64, fire_at: &str) {
+        let query = format!("INSERT INTO schedules (device_id, fire_at) VALUES (?, {})", fire_at);
+        sqlx::query(&query).bind(device_id).execute(&state.db).await.unwrap();
+    }
+
+    async fn schedule_count(state: &AppState) -> i64 {
+        sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!: i64" FROM schedules"#)
+            .fetch_one(&state.db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn one_shot_schedule_fires_once() {
+        let (state, sender) = recording_state().await;
+        let id = device(&state, "desktop", None).await;
+        schedule(&state, id, "CURRENT_TIMESTAMP").await;
+        // Not due yet
+        schedule(&state, id, "datetime('now', '+1 hour')").await;
+
+        let mut retries = Vec::new();
+        fire_due(&state, &mut retries).await;
+        fire_due(&state, &mut retries).await;
+
+        assert_eq!(sender.sent.lock().unwrap().len(), 1);
+        assert_eq!(schedule_count(&state).await, 1);
+        let actions = sqlx::query_scalar!("SELECT action FROM audit_log WHERE device_id = ?", id)
+            .fetch_all(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(actions, [audit::ACTION_SCHEDULED_WAKE]);
+    }
+
+    #[tokio::test]
+    async fn missed_schedule_fires_late_by_default() {
+        let (state, sender) = recording_state().await;
+        assert_eq!(state.config.schedule_missed_policy, MissedFirePolicy::FireLate);
+        let id = device(&state, "desktop", None).await;
+        schedule(&state, id, &format!("datetime('now', '-{} seconds')", MISSED_FIRE_GRACE_SECS * 5)).await;
+
+        fire_due(&state, &mut Vec::new()).await;
+
+        assert_eq!(sender.sent.lock().unwrap().len(), 1);
+        assert_eq!(schedule_count(&state).await, 0);
+    }
+
+    #[tokio::test]
+    async fn missed_schedule_is_dropped_with_skip_policy() {
+        let sender = Arc::new(RecordingSender::default());
+        let state = AppState {
+            wake_sender: sender.clone(),
+            ..state_with(|config| config.schedule_missed_policy = MissedFirePolicy::Skip).await
+        };
+        let id = device(&state, "desktop", None).await;
+        schedule(&state, id, &format!("datetime('now', '-{} seconds')", MISSED_FIRE_GRACE_SECS * 5)).await;
+
+        fire_due(&state, &mut Vec::new()).await;
+
+        assert!(sender.sent.lock().unwrap().is_empty());
+        assert_eq!(schedule_count(&state).await, 0);
+    }
+
+    #[tokio::test]
+    async fn failed_scheduled_wake_is_retried() {
+        let sender = RecordingSender::failing(1);
+        let state = AppState { wake_sender: sender.clone(), ..state().await };
+        let id = device(&state, "desktop", None).await;
+        schedule(&state, id, "CURRENT_TIMESTAMP").await;
+
+        let mut retries = Vec::new();
+        fire_due(&state, &mut retries).await;
+        assert_eq!(retries.len(), 1);
+        assert!(sender.sent.lock().unwrap().is_empty());
+
+        // Skip the backoff
+        retries[0].next_attempt_at = Instant::now();
+        retry_failed(&state, &mut retries).await;
+        assert!(retries.is_empty());
+        assert_eq!(sender.sent.lock().unwrap().len(), 1);
+
+        let actions = sqlx::query_scalar!("SELECT action FROM audit_log WHERE device_id = ? ORDER BY id", id)
+            .fetch_all(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(actions, [audit::ACTION_SCHEDULED_WAKE_FAILED, audit::ACTION_SCHEDULED_WAKE]);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::wol;
+use sqlx::{Pool, Sqlite};
+use std::net::IpAddr;
+
+/// Canonical `AA:BB:CC:DD:EE:FF` form of a MAC that [`wol::parse_mac`] rejects only for
+/// its formatting: stray whitespace, dots, or missing or mixed separators.
+fn fixed_mac(mac: &str) -> Option<String> {
+    let bytes = wol::parse_mac(mac, true)?;
+    let groups: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    Some(groups.join(":"))
+}
+
+/// Replacement for a stored address that doesn't parse: trimmed if that's all it
+/// takes, unset if it is blank. `None` when it can't be fixed.
+fn fixed_addr(addr: &str) -> Option<Option<String>> {
+    let trimmed = addr.trim();
+    if trimmed.is_empty() {
+        return Some(None);
+    }
+    trimmed.parse::<IpAddr>().is_ok().then(|| Some(trimmed.to_string()))
+}
+
+#[derive(Clone, Copy)]
+enum Column {
+    MacAddress,
+    IpAddress,
+    BroadcastAddr,
+}
+
+impl Column {
+    fn name(self) -> &'static str {
+        match self {
+            Column::MacAddress => "mac_address",
+            Column::IpAddress => "ip_address",
+            Column::BroadcastAddr => "broadcast_addr",
+        }
+    }
+}
+
+/// One invalid column of a device, and what it can be fixed to
+struct Problem {
+    column: Column,
+    value: String,
+    /// `Some(None)` unsets the column
+    fix: Option<Option<String>>,
+}
+
+fn problems(mac: &str, ip: Option<&str>, broadcast: Option<&str>) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    // Stored MACs are rewritten to the strict form even where lenient parsing would accept them
+    if wol::parse_mac(mac, false).is_none() {
+        problems.push(Problem {
+            column: Column::MacAddress,
+            value: mac.to_string(),
+            fix: fixed_mac(mac).map(Some),
+        });
+    }
+    if let Some(ip) = ip
+        && ip.parse::<IpAddr>().is_err()
+    {
+        problems.push(Problem { column: Column::IpAddress, value: ip.to_string(), fix: fixed_addr(ip) });
+    }
+    // Blank broadcast addresses are valid and mean "global broadcast"
+    if let Some(broadcast) = broadcast
+        && wol::broadcast_target(Some(broadcast)).is_none()
+    {
+        problems.push(Problem { column: Column::BroadcastAddr, value: broadcast.to_string(), fix: fixed_addr(broadcast) });
+    }
+    problems
+}
+
+async fn apply_fix(db: &Pool<Sqlite>, device_id: i64, column: Column, value: Option<&str>) -> Result<(), sqlx::Error> {
+    match column {
+        Column::MacAddress => sqlx::query!("UPDATE devices SET mac_address = ? WHERE id = ?", value, device_id).execute(db).await?,
+        Column::IpAddress => sqlx::query!("UPDATE devices SET ip_address = ? WHERE id = ?", value, device_id).execute(db).await?,
+        Column::BroadcastAddr => sqlx::query!("UPDATE devices SET broadcast_addr = ? WHERE id = ?", value, device_id).execute(db).await?,
+    };
+    Ok(())
+}
+
+fn describe(value: Option<&str>) -> String {
+    value.map_or("unset".to_string(), |v| format!("'{}'", v))
+}
+
+/// Scans every device for MAC, IP and broadcast addresses that would only fail at
+/// wake time and logs a warning for each. With `fix`, rewrites the ones that
+/// are merely badly formatted. Nothing is ever deleted. Returns the warnings.
+pub async fn validate_devices(db: &Pool<Sqlite>, fix: bool) -> Vec<String> {
+    let devices = match sqlx::query!(
+        r#"SELECT id as "id!", name, mac_address, ip_address, broadcast_addr FROM devices ORDER BY id"#
+    )
+    .fetch_all(db)
+    .await
+    {
+        Ok(d) => d,
+        Err(e) => {
+            println!("WARNING: Startup device check failed: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut warnings = Vec::new();
+    let mut invalid = 0;
+    for device in &devices {
+        let problems = problems(&device.mac_address, device.ip_address.as_deref(), device.broadcast_addr.as_deref());
+        if !problems.is_empty() {
+            invalid += 1;
+        }
+
+        for problem in problems {
+            let outcome = match (&problem.fix, fix) {
+                (None, _) => "can't be fixed automatically".to_string(),
+                (Some(value), false) => format!("fixable to {} with --fix-on-start", describe(value.as_deref())),
+                (Some(value), true) => match apply_fix(db, device.id, problem.column, value.as_deref()).await {
+                    Ok(()) => format!("fixed to {}", describe(value.as_deref())),
+                    Err(e) => format!("fix failed: {}", e),
+                },
+            };
+            let warning = format!(
+                "Device {} '{}' has an invalid {} '{}': {}",
+                device.id, device.name, problem.column.name(), problem.value, outcome
+            );
+            println!("WARNING: {}", warning);
+            warnings.push(warning);
+        }
+    }
+
+    println!("Startup device check: {} of {} devices have invalid addresses", invalid, devices.len());
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_devices;
+    use crate::test_support::state;
+
+    #[tokio::test]
+    async fn scan_reports_and_fixes_bad_macs() {
+        let state = state().await;
+        for (name, mac) in [("good", "AA:BB:CC:DD:EE:FF"), ("dotted", "aabb.ccdd.eeff"), ("short", "AA:BB:CC:DD:EE")] {
+            sqlx::query!("INSERT INTO devices (name, mac_address) VALUES (?, ?)", name, mac)
+                .execute(&state.db)
+                .await
+                .unwrap();
+        }
+
+        let warnings = validate_devices(&state.db, false).await;
+        assert_eq!(
+            warnings,
+            [
+                "Device 2 'dotted' has an invalid mac_address 'aabb.ccdd.eeff': fixable to 'AA:BB:CC:DD:EE:FF' with --fix-on-start",
+                "Device 3 'short' has an invalid mac_address 'AA:BB:CC:DD:EE': can't be fixed automatically",
+            ]
+        );
+
+        validate_devices(&state.db, true).await;
+        let macs = sqlx::query_scalar!("SELECT mac_address FROM devices ORDER BY id")
+            .fetch_all(&state.db)
+            .await
+            .unwrap();
+        assert_eq!(macs, ["AA:BB:CC:DD:EE:FF", "AA:BB:CC:DD:EE:FF", "AA:BB:CC:DD:EE"]);
+    }
+}
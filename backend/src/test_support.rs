@@ -0,0 +1,206 @@
+//! Shared setup for tests: an in-memory database with every migration applied,
+//! users with access tokens, and requests against the real `/api` router.
+
+use crate::auth::create_jwt;
+use crate::config::Config;
+use crate::db::AppState;
+use crate::events;
+use crate::pinger::{LivenessProbe, Prober};
+use crate::wol::WakeSender;
+use async_trait::async_trait;
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use sqlx::sqlite::SqlitePoolOptions;
+use std::collections::VecDeque;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tower::ServiceExt;
+
+/// A magic packet handed to [`RecordingSender`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentPacket {
+    pub mac: [u8; 6],
+    pub target: String,
+    pub ports: Vec<u16>,
+    pub source_port: Option<u16>,
+    /// Time on the sender's clock
+    pub at: Duration,
+}
+
+/// Records magic packets instead of sending them. Pauses only move its own clock
+/// forward, so delays between wakes cost no real time.
+#[derive(Default)]
+pub struct RecordingSender {
+    pub sent: Mutex<Vec<SentPacket>>,
+    clock: Mutex<Duration>,
+    /// Sends left to fail before packets go out
+    failures: Mutex<u32>,
+}
+
+impl RecordingSender {
+    /// A sender whose first `times` sends fail
+    pub fn failing(times: u32) -> Arc<Self> {
+        Arc::new(RecordingSender {
+            failures: Mutex::new(times),
+            ..RecordingSender::default()
+        })
+    }
+}
+
+#[async_trait]
+impl WakeSender for RecordingSender {
+    async fn send(&self, mac: &[u8; 6], target: &str, ports: &[u16], source_port: Option<u16>) -> io::Result<()> {
+        {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(io::Error::new(io::ErrorKind::HostUnreachable, "scripted failure"));
+            }
+        }
+        self.sent.lock().unwrap().push(SentPacket {
+            mac: *mac,
+            target: target.to_string(),
+            ports: ports.to_vec(),
+            source_port,
+            at: *self.clock.lock().unwrap(),
+        });
+        Ok(())
+    }
+
+    async fn pause(&self, delay: Duration) {
+        *self.clock.lock().unwrap() += delay;
+    }
+}
+
+/// Answers liveness probes as scripted: each answer in turn, then the last one for good.
+/// Without answers every probe fails.
+#[derive(Default)]
+pub struct ScriptedProber {
+    answers: Mutex<VecDeque<bool>>,
+    /// Every probe run, with its timeout, in order
+    pub probes: Mutex<Vec<(IpAddr, LivenessProbe, Duration)>>,
+}
+
+impl ScriptedProber {
+    pub fn new(answers: impl IntoIterator<Item = bool>) -> Arc<Self> {
+        Arc::new(ScriptedProber {
+            answers: Mutex::new(answers.into_iter().collect()),
+            probes: Mutex::default(),
+        })
+    }
+}
+
+#[async_trait]
+impl Prober for ScriptedProber {
+    async fn is_alive(&self, ip: IpAddr, probe: LivenessProbe, timeout: Duration) -> bool {
+        self.probes.lock().unwrap().push((ip, probe, timeout));
+        let mut answers = self.answers.lock().unwrap();
+        match answers.len() {
+            0 => false,
+            1 => answers[0],
+            _ => answers.pop_front().unwrap(),
+        }
+    }
+}
+
+/// [`state`] with a sender whose packets the test can inspect
+pub async fn recording_state() -> (AppState, Arc<RecordingSender>) {
+    let sender = Arc::new(RecordingSender::default());
+    let state = AppState { wake_sender: sender.clone(), ..state().await };
+    (state, sender)
+}
+
+pub async fn state() -> AppState {
+    state_with(|_| {}).await
+}
+
+/// Like [`state`], with `configure` applied to the default configuration
+pub async fn state_with(configure: impl FnOnce(&mut Config)) -> AppState {
+    // One connection, since every connection to `:memory:` opens a database of its own
+    let db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .expect("Failed to open test database");
+    sqlx::migrate!().run(&db).await.expect("Failed to run migrations");
+
+    let mut config = Config::load(Path::new("static_files"), false);
+    configure(&mut config);
+    AppState {
+        db,
+        events: events::channel(),
+        config: Arc::new(config),
+        agent_breaker: Arc::default(),
+        maintenance: Arc::default(),
+        wake_sender: Arc::new(RecordingSender::default()),
+        prober: Arc::new(ScriptedProber::default()),
+    }
+}
+
+/// Creates a user and returns its id and an access token
+pub async fn user(state: &AppState, username: &str, role: &str) -> (i64, String) {
+    let id = sqlx::query_scalar!(
+        r#"INSERT INTO users (username, password_hash, role) VALUES (?, '', ?) RETURNING id as "id!""#,
+        username,
+        role
+    )
+    .fetch_one(&state.db)
+    .await
+    .expect("Failed to create test user");
+    let token = create_jwt(&state.config, id, username, role, chrono::Duration::minutes(15)).expect("Failed to sign token");
+    (id, token)
+}
+
+/// Creates a device without an IP address, owned by `owner_id`, and returns its id
+pub async fn device(state: &AppState, name: &str, owner_id: Option<i64>) -> i64 {
+    sqlx::query_scalar!(
+        r#"INSERT INTO devices (name, mac_address, owner_id) VALUES (?, 'AA:BB:CC:DD:EE:FF', ?) RETURNING id as "id!""#,
+        name,
+        owner_id
+    )
+    .fetch_one(&state.db)
+    .await
+    .expect("Failed to create test device")
+}
+
+pub fn app(state: &AppState) -> Router {
+    Router::new()
+        .nest("/api", crate::all_routes(None).router)
+        .with_state(state.clone())
+}
+
+/// Sends a request to the API and returns the status and body
+pub async fn call(
+    state: &AppState,
+    method: Method,
+    uri: &str,
+    token: Option<&str>,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, String) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    let request = match body {
+        Some(body) => request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    }
+    .expect("Failed to build request");
+    send(state, request).await
+}
+
+/// Sends a prepared request to the API and returns the status and body
+pub async fn send(state: &AppState, request: Request<Body>) -> (StatusCode, String) {
+    let response = app(state).oneshot(request).await.expect("Router failed");
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.expect("Failed to read body");
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
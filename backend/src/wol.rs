@@ -0,0 +1,232 @@
+use async_trait::async_trait;
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use wake_on_lan::MagicPacket;
+
+/// UDP port magic packets are sent to unless `WOL_DEFAULT_PORT` says otherwise
+pub const WOL_PORT: u16 = 9;
+/// Used when a device has no broadcast address configured
+pub const GLOBAL_BROADCAST: &str = "255.255.255.255";
+
+/// Only one socket can bind a fixed `WOL_SOURCE_PORT` at a time, so concurrent
+/// sends from it (e.g. a group wake) take turns
+static SOURCE_PORT_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Parses a MAC address like `AA:BB:CC:DD:EE:FF` or `AA-BB-CC-DD-EE-FF`.
+///
+/// Strict: exactly six groups of two hex digits, separated throughout by the
+/// same delimiter. Anything else is rejected rather than guessed at.
+///
+/// `lenient` (`LENIENT_MAC_PARSING`) also accepts other spellings of the same twelve
+/// hex digits, e.g. `aabb.ccdd.eeff`, `AA BB CC DD EE FF` or bare `AABBCCDDEEFF`.
+pub fn parse_mac(mac: &str, lenient: bool) -> Option<[u8; 6]> {
+    if lenient {
+        return parse_mac_lenient(mac);
+    }
+    let delimiter = if mac.contains(':') { ':' } else { '-' };
+    let mut bytes = [0u8; 6];
+    let mut groups = mac.split(delimiter);
+
+    for byte in &mut bytes {
+        let group = groups.next()?;
+        if group.len() != 2 || !group.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        *byte = u8::from_str_radix(group, 16).ok()?;
+    }
+
+    if groups.next().is_some() {
+        return None;
+    }
+    Some(bytes)
+}
+
+/// Twelve hex digits with any mix of `:`, `-`, `.` and whitespace around or between them
+fn parse_mac_lenient(mac: &str) -> Option<[u8; 6]> {
+    let hex: Vec<u8> = mac
+        .bytes()
+        .filter(|b| !matches!(b, b':' | b'-' | b'.') && !b.is_ascii_whitespace())
+        .collect();
+    if hex.len() != 12 || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+
+    let mut bytes = [0u8; 6];
+    for (byte, pair) in bytes.iter_mut().zip(hex.chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Where to send a magic packet for a configured broadcast address.
+///
+/// Unset or blank means "no broadcast configured" and falls back to
+/// [`GLOBAL_BROADCAST`]; anything that isn't an IP address is `None`.
+pub fn broadcast_target(configured: Option<&str>) -> Option<&str> {
+    match configured.map(str::trim) {
+        None | Some("") => Some(GLOBAL_BROADCAST),
+        Some(addr) => addr.parse::<IpAddr>().is_ok().then_some(addr),
+    }
+}
+
+/// Puts magic packets on the wire and waits between them. Handlers go through the one
+/// in `AppState`, so tests can swap [`UdpWakeSender`] for a fake that only records
+/// what was sent and when, on a clock of its own.
+#[async_trait]
+pub trait WakeSender: Send + Sync {
+    /// See [`send_magic_packets`]
+    async fn send(&self, mac: &[u8; 6], target: &str, ports: &[u16], source_port: Option<u16>) -> io::Result<()>;
+
+    /// Waits `delay` before the next wake, e.g. between the members of a sequential group
+    async fn pause(&self, delay: Duration) {
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Sends over UDP with [`send_magic_packets`]
+pub struct UdpWakeSender;
+
+#[async_trait]
+impl WakeSender for UdpWakeSender {
+    async fn send(&self, mac: &[u8; 6], target: &str, ports: &[u16], source_port: Option<u16>) -> io::Result<()> {
+        send_magic_packets(mac, target, ports, source_port).await
+    }
+}
+
+/// Sends the same magic packet to `target` on each of `ports`, for NICs that
+/// only listen on one of them. Stops at the first failed send.
+///
+/// With a `source_port` the packets leave from that port, for firewalls that
+/// only let WoL traffic out from a known one; otherwise from an ephemeral port.
+/// Uses a tokio socket so a send never blocks a runtime worker thread.
+pub async fn send_magic_packets(mac: &[u8; 6], target: &str, ports: &[u16], source_port: Option<u16>) -> io::Result<()> {
+    let magic_packet = MagicPacket::new(mac);
+
+    let _guard = match source_port {
+        Some(_) => Some(SOURCE_PORT_LOCK.lock().await),
+        None => None,
+    };
+    let socket = UdpSocket::bind(("0.0.0.0", source_port.unwrap_or(0))).await?;
+    socket.set_broadcast(true)?;
+    for &port in ports {
+        socket.send_to(magic_packet.magic_bytes(), (target, port)).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_mac, send_magic_packets};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::UdpSocket;
+    use wake_on_lan::MagicPacket;
+
+    const MAC: [u8; 6] = [0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF];
+
+    #[test]
+    fn strict_accepts_colons_or_dashes() {
+        assert_eq!(parse_mac("AA:BB:CC:DD:EE:FF", false), Some(MAC));
+        assert_eq!(parse_mac("aa-bb-cc-dd-ee-ff", false), Some(MAC));
+    }
+
+    #[test]
+    fn strict_rejects_anything_else() {
+        for mac in [
+            "AA:BB:CC:DD:EE",
+            "AA:BB:CC:DD:EE:FF:00",
+            "AA:BB:CC:DD:EE:ZZ",
+            "AA:BB:CC:DD:EE:F",
+            "AA:BB:CC-DD:EE:FF",
+            "AA::BB:CC:DD:EE:FF",
+            "AA:BB:CC:DD:EE:FF:",
+            " AA:BB:CC:DD:EE:FF",
+            "AABB.CCDD.EEFF",
+            "AABBCCDDEEFF",
+        ] {
+            assert_eq!(parse_mac(mac, false), None, "{mac}");
+        }
+    }
+
+    #[test]
+    fn lenient_accepts_other_separators() {
+        for mac in [
+            "AA:BB:CC:DD:EE:FF",
+            "aabb.ccdd.eeff",
+            "AA BB CC DD EE FF",
+            "AABBCCDDEEFF",
+            "AA:BB-CC:DD-EE:FF",
+            " aa:bb:cc:dd:ee:ff\n",
+        ] {
+            assert_eq!(parse_mac(mac, true), Some(MAC), "{mac}");
+        }
+    }
+
+    #[test]
+    fn lenient_still_needs_twelve_hex_digits() {
+        for mac in ["AABBCCDDEE", "AABBCCDDEEFF00", "AABBCCDDEEZZ", "AA_BB_CC_DD_EE_FF", ""] {
+            assert_eq!(parse_mac(mac, true), None, "{mac}");
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_from_the_configured_source_port() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        // A port that was free a moment ago
+        let source_port = UdpSocket::bind("0.0.0.0:0").await.unwrap().local_addr().unwrap().port();
+
+        send_magic_packets(&MAC, "127.0.0.1", &[port], Some(source_port)).await.unwrap();
+
+        let mut buf = [0; 128];
+        let (len, from) = receiver.recv_from(&mut buf).await.unwrap();
+        assert_eq!(from.port(), source_port);
+        assert_eq!(&buf[..len], MagicPacket::new(&MAC).magic_bytes());
+    }
+
+    #[tokio::test]
+    async fn sends_one_packet_per_port() {
+        let receivers = [UdpSocket::bind("127.0.0.1:0").await.unwrap(), UdpSocket::bind("127.0.0.1:0").await.unwrap()];
+        let ports: Vec<u16> = receivers.iter().map(|r| r.local_addr().unwrap().port()).collect();
+
+        send_magic_packets(&MAC, "127.0.0.1", &ports, None).await.unwrap();
+
+        for receiver in &receivers {
+            let mut buf = [0; 128];
+            let len = receiver.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], MagicPacket::new(&MAC).magic_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn a_batch_of_sends_leaves_room_for_other_tasks() {
+        const BATCH: usize = 500;
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let sent = Arc::new(AtomicUsize::new(0));
+
+        // The test runtime has a single thread, so the second task only gets to run
+        // before the batch is done if sending yields to it
+        let batch = tokio::spawn({
+            let sent = sent.clone();
+            async move {
+                for _ in 0..BATCH {
+                    send_magic_packets(&MAC, "127.0.0.1", &[port], None).await.unwrap();
+                    sent.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+        let other = tokio::spawn({
+            let sent = sent.clone();
+            async move { sent.load(Ordering::Relaxed) }
+        });
+
+        assert!(other.await.unwrap() < BATCH);
+        batch.await.unwrap();
+        assert_eq!(sent.load(Ordering::Relaxed), BATCH);
+    }
+}